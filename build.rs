@@ -0,0 +1,153 @@
+//! Compiles every GLSL shader in `shaders/` to SPIR-V and generates `embedded_shaders.rs`, which
+//! `src/vulkan/shaders.rs` includes. A missing compiler fails the build unless
+//! `VOXEL_PREBUILT_SHADERS=1` opts into embedding the checked-in `.spv` files. Each of those has a
+//! `.spv.hash` file next to it holding a hash of the source it was compiled from, which has to
+//! match the current source. Builds with the compiler refresh both whenever they are out of date.
+
+use std::env;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const SHADER_DIR: &str = "shaders";
+const GLSLC_ENV: &str = "VOXEL_GLSLC";
+const PREBUILT_ENV: &str = "VOXEL_PREBUILT_SHADERS";
+const SHADER_EXTENSIONS: [&str; 6] = ["vert", "frag", "comp", "geom", "tesc", "tese"];
+const SPIRV_MAGIC: u32 = 0x0723_0203;
+
+fn main() {
+    let out_dir = PathBuf::from(env::var_os("OUT_DIR").unwrap());
+    println!("cargo:rerun-if-changed={}", SHADER_DIR);
+    println!("cargo:rerun-if-env-changed={}", GLSLC_ENV);
+    println!("cargo:rerun-if-env-changed={}", PREBUILT_ENV);
+
+    let mut sources: Vec<PathBuf> = fs::read_dir(SHADER_DIR)
+        .unwrap_or_else(|e| panic!("failed to read {}: {}", SHADER_DIR, e))
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|path| {
+            path.extension()
+                .and_then(|extension| extension.to_str())
+                .is_some_and(|extension| SHADER_EXTENSIONS.contains(&extension))
+        })
+        .collect();
+    sources.sort();
+
+    let glslc = env::var_os(GLSLC_ENV).map_or_else(|| PathBuf::from("glslc"), PathBuf::from);
+    let glslc_found = Command::new(&glslc).arg("--version").output().is_ok();
+    if !glslc_found {
+        if env::var(PREBUILT_ENV).as_deref() != Ok("1") {
+            fail(&format!(
+                "{} not found; install the Vulkan SDK or shaderc, set {} to the compiler, or set {}=1 to embed the checked-in SPIR-V",
+                glslc.display(),
+                GLSLC_ENV,
+                PREBUILT_ENV
+            ));
+        }
+        println!(
+            "cargo:warning={} not found, embedding the prebuilt SPIR-V from {}/ ({}=1)",
+            glslc.display(),
+            SHADER_DIR,
+            PREBUILT_ENV
+        );
+    }
+
+    let mut generated = String::new();
+    let mut table = String::new();
+    for source in &sources {
+        println!("cargo:rerun-if-changed={}", source.display());
+        let name = source.file_name().unwrap().to_str().unwrap();
+        let spv = if glslc_found {
+            let output = out_dir.join(format!("{}.spv", name));
+            compile(&glslc, source, &output);
+            refresh_prebuilt(source, &output);
+            output
+        } else {
+            let prebuilt = prebuilt_path(source);
+            println!("cargo:rerun-if-changed={}", prebuilt.display());
+            let prebuilt = prebuilt.canonicalize().unwrap_or_else(|e| {
+                fail(&format!("{} has no prebuilt {} and glslc is unavailable: {}", name, prebuilt.display(), e))
+            });
+            if fs::read_to_string(hash_path(&prebuilt)).ok().as_deref().map(str::trim) != Some(&source_hash(source)) {
+                fail(&format!("{} was not compiled from the current {}; recompile it with glslc", prebuilt.display(), source.display()));
+            }
+            prebuilt
+        };
+
+        let bytes = fs::read(&spv).unwrap_or_else(|e| panic!("failed to read {}: {}", spv.display(), e));
+        if bytes.len() < 20 || !bytes.len().is_multiple_of(4) || u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) != SPIRV_MAGIC {
+            panic!("{} is not valid SPIR-V", spv.display());
+        }
+
+        let ident = name.to_uppercase().replace(|c: char| !c.is_ascii_alphanumeric(), "_");
+        writeln!(
+            generated,
+            "static {}: AlignedSpirv<{}> = AlignedSpirv(*include_bytes!({:?}));",
+            ident,
+            bytes.len(),
+            spv
+        )
+        .unwrap();
+        writeln!(table, "    ({:?}, &{}.0),", name, ident).unwrap();
+    }
+    writeln!(generated, "\nstatic EMBEDDED_SHADERS: &[(&str, &[u8])] = &[\n{}];", table).unwrap();
+
+    fs::write(out_dir.join("embedded_shaders.rs"), generated).unwrap();
+}
+
+/// Stops the build with `message` and no backtrace.
+fn fail(message: &str) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(1);
+}
+
+fn prebuilt_path(source: &Path) -> PathBuf {
+    Path::new(SHADER_DIR).join(format!("{}.spv", source.file_name().unwrap().to_str().unwrap()))
+}
+
+fn hash_path(prebuilt: &Path) -> PathBuf {
+    let mut path = prebuilt.as_os_str().to_owned();
+    path.push(".hash");
+    PathBuf::from(path)
+}
+
+/// FNV-1a of `source` with line endings normalized, so a checkout with CRLF conversion still
+/// matches. Only has to notice edits, not resist tampering.
+fn source_hash(source: &Path) -> String {
+    let text = fs::read(source).unwrap_or_else(|e| panic!("failed to read {}: {}", source.display(), e));
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for &byte in text.iter().filter(|&&byte| byte != b'\r') {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    format!("{:016x}", hash)
+}
+
+/// Copies freshly compiled SPIR-V over the checked-in fallback when its hash no longer matches,
+/// so the prebuilt files stay usable for builds without glslc. Unchanged files are left alone to
+/// avoid retriggering the build.
+fn refresh_prebuilt(source: &Path, compiled: &Path) {
+    let prebuilt = prebuilt_path(source);
+    let hash_file = hash_path(&prebuilt);
+    let hash = source_hash(source);
+    if fs::read_to_string(&hash_file).ok().as_deref().map(str::trim) == Some(&hash) {
+        return;
+    }
+    let written = fs::copy(compiled, &prebuilt).and_then(|_| fs::write(&hash_file, format!("{}\n", hash)));
+    if let Err(e) = written {
+        println!("cargo:warning=failed to refresh {}: {}", prebuilt.display(), e);
+    }
+}
+
+/// Fails the build with the compiler's diagnostics if `source` doesn't compile.
+fn compile(glslc: &Path, source: &Path, output: &Path) {
+    let result = Command::new(glslc)
+        .arg(source)
+        .arg("-o")
+        .arg(output)
+        .output()
+        .unwrap_or_else(|e| panic!("failed to run {}: {}", glslc.display(), e));
+    if !result.status.success() {
+        fail(&format!("failed to compile {}:\n{}", source.display(), String::from_utf8_lossy(&result.stderr).trim()));
+    }
+}
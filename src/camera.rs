@@ -0,0 +1,261 @@
+use std::collections::HashSet;
+use std::f32::consts::FRAC_PI_2;
+use std::time::Duration;
+
+use winit::keyboard::KeyCode;
+
+use crate::vulkan::descriptors::*;
+
+pub type Vec3 = [f32; 3];
+
+/// World up. The world is right-handed with Y up; a camera with zero yaw looks down -Z.
+pub const UP: Vec3 = [0.0, 1.0, 0.0];
+
+/// Vulkan's clip space has Y pointing down. Every `Camera` projection includes this flip, so world
+/// geometry is counter-clockwise when seen from the front.
+pub const Y_FLIP: Mat4 = [
+    [1.0, 0.0, 0.0, 0.0],
+    [0.0, -1.0, 0.0, 0.0],
+    [0.0, 0.0, 1.0, 0.0],
+    [0.0, 0.0, 0.0, 1.0],
+];
+
+/// Pitch stays just short of straight up or down, where yaw would stop meaning anything.
+pub const MAX_PITCH: f32 = FRAC_PI_2 - 0.01;
+
+/// Both projections use reverse-Z: the near plane maps to depth 1 and the far plane to 0, which
+/// spreads float depth precision evenly over distance. The depth buffer is cleared to 0 and
+/// tested with `GREATER_OR_EQUAL`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Projection {
+    /// `fov_y` is the vertical field of view in radians.
+    Perspective { fov_y: f32, near: f32, far: f32 },
+    /// `height` is the world-space height of the view volume; the width follows the aspect ratio.
+    Orthographic { height: f32, near: f32, far: f32 },
+}
+
+impl Default for Projection {
+    fn default() -> Self {
+        Projection::Perspective {
+            fov_y: 70f32.to_radians(),
+            near: 0.1,
+            far: 1000.0,
+        }
+    }
+}
+
+impl Projection {
+    /// Maps view space (looking down -Z, Y up) to Vulkan clip space (Y down, depth in 0..1).
+    pub fn matrix(&self, aspect: f32) -> Mat4 {
+        match *self {
+            Projection::Perspective { fov_y, near, far } => {
+                let focal = 1.0 / (fov_y / 2.0).tan();
+                let depth_range = far - near;
+                [
+                    [focal / aspect, 0.0, 0.0, 0.0],
+                    [0.0, -focal, 0.0, 0.0],
+                    [0.0, 0.0, near / depth_range, -1.0],
+                    [0.0, 0.0, near * far / depth_range, 0.0],
+                ]
+            }
+            Projection::Orthographic { height, near, far } => {
+                let depth_range = far - near;
+                [
+                    [2.0 / (height * aspect), 0.0, 0.0, 0.0],
+                    [0.0, -2.0 / height, 0.0, 0.0],
+                    [0.0, 0.0, 1.0 / depth_range, 0.0],
+                    [0.0, 0.0, far / depth_range, 1.0],
+                ]
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Camera {
+    pub position: Vec3,
+    /// Radians, turning right from -Z towards +X.
+    pub yaw: f32,
+    /// Radians, positive looks up. Kept within `MAX_PITCH`.
+    pub pitch: f32,
+    pub projection: Projection,
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Camera::new([0.0, 0.0, 2.0], 0.0, 0.0)
+    }
+}
+
+impl Camera {
+    pub fn new(position: Vec3, yaw: f32, pitch: f32) -> Camera {
+        Camera {
+            position,
+            yaw,
+            pitch: pitch.clamp(-MAX_PITCH, MAX_PITCH),
+            projection: Projection::default(),
+        }
+    }
+
+    pub fn forward(&self) -> Vec3 {
+        let (sin_yaw, cos_yaw) = self.yaw.sin_cos();
+        let (sin_pitch, cos_pitch) = self.pitch.sin_cos();
+        [cos_pitch * sin_yaw, sin_pitch, -cos_pitch * cos_yaw]
+    }
+
+    /// Horizontal, regardless of pitch.
+    pub fn right(&self) -> Vec3 {
+        let (sin_yaw, cos_yaw) = self.yaw.sin_cos();
+        [cos_yaw, 0.0, sin_yaw]
+    }
+
+    pub fn rotate(&mut self, delta_yaw: f32, delta_pitch: f32) {
+        self.yaw = (self.yaw + delta_yaw).rem_euclid(std::f32::consts::TAU);
+        self.pitch = (self.pitch + delta_pitch).clamp(-MAX_PITCH, MAX_PITCH);
+    }
+
+    pub fn view_matrix(&self) -> Mat4 {
+        let forward = self.forward();
+        let right = self.right();
+        let up = cross(right, forward);
+        let p = self.position;
+        [
+            [right[0], up[0], -forward[0], 0.0],
+            [right[1], up[1], -forward[1], 0.0],
+            [right[2], up[2], -forward[2], 0.0],
+            [-dot(right, p), -dot(up, p), dot(forward, p), 1.0],
+        ]
+    }
+
+    pub fn projection_matrix(&self, aspect: f32) -> Mat4 {
+        self.projection.matrix(aspect)
+    }
+
+    /// Camera matrices for the vertex shader, with an identity model matrix.
+    pub fn uniforms(&self, aspect: f32) -> UniformBufferObject {
+        UniformBufferObject {
+            model: IDENTITY,
+            view: self.view_matrix(),
+            proj: self.projection_matrix(aspect),
+        }
+    }
+}
+
+pub fn dot(a: Vec3, b: Vec3) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+pub fn cross(a: Vec3, b: Vec3) -> Vec3 {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+/// `a * b` for column-major matrices, so `b` is applied first.
+pub fn mul_mat4(a: &Mat4, b: &Mat4) -> Mat4 {
+    let mut result = [[0.0; 4]; 4];
+    for (column, b_column) in result.iter_mut().zip(b) {
+        *column = transform(a, *b_column);
+    }
+    result
+}
+
+pub fn transform(m: &Mat4, v: [f32; 4]) -> [f32; 4] {
+    let mut result = [0.0; 4];
+    for (row, value) in result.iter_mut().enumerate() {
+        *value = (0..4).map(|column| m[column][row] * v[column]).sum();
+    }
+    result
+}
+
+/// Free-fly movement: WASD moves along the view direction, Space and Left Shift straight up and
+/// down, Left Control speeds up. Mouse motion only turns the camera while the cursor is grabbed.
+#[derive(Debug, Clone)]
+pub struct FlyController {
+    /// World units per second.
+    pub speed: f32,
+    pub sprint_multiplier: f32,
+    /// Radians per mouse count.
+    pub sensitivity: f32,
+    pressed: HashSet<KeyCode>,
+    mouse_delta: (f64, f64),
+    grabbed: bool,
+}
+
+impl Default for FlyController {
+    fn default() -> Self {
+        FlyController {
+            speed: 5.0,
+            sprint_multiplier: 4.0,
+            sensitivity: 0.002,
+            pressed: HashSet::new(),
+            mouse_delta: (0.0, 0.0),
+            grabbed: false,
+        }
+    }
+}
+
+impl FlyController {
+    /// Presses only count while the cursor is grabbed; releases always do.
+    pub fn handle_key(&mut self, key: KeyCode, pressed: bool) {
+        if !pressed {
+            self.pressed.remove(&key);
+        } else if self.grabbed {
+            self.pressed.insert(key);
+        }
+    }
+
+    /// Raw motion from `DeviceEvent::MouseMotion`; ignored unless the cursor is grabbed.
+    pub fn handle_mouse_motion(&mut self, dx: f64, dy: f64) {
+        if self.grabbed {
+            self.mouse_delta.0 += dx;
+            self.mouse_delta.1 += dy;
+        }
+    }
+
+    pub fn is_grabbed(&self) -> bool {
+        self.grabbed
+    }
+
+    /// Releasing also forgets held keys, since their release events may go elsewhere.
+    pub fn set_grabbed(&mut self, grabbed: bool) {
+        self.grabbed = grabbed;
+        if !grabbed {
+            self.pressed.clear();
+            self.mouse_delta = (0.0, 0.0);
+        }
+    }
+
+    /// Applies the mouse motion gathered since the last update and moves for `dt`.
+    pub fn update(&mut self, camera: &mut Camera, dt: Duration) {
+        let (dx, dy) = std::mem::take(&mut self.mouse_delta);
+        camera.rotate(dx as f32 * self.sensitivity, -dy as f32 * self.sensitivity);
+
+        let axis = |positive, negative| self.pressed.contains(&positive) as i32 as f32 - self.pressed.contains(&negative) as i32 as f32;
+        let forward_amount = axis(KeyCode::KeyW, KeyCode::KeyS);
+        let right_amount = axis(KeyCode::KeyD, KeyCode::KeyA);
+        let up_amount = axis(KeyCode::Space, KeyCode::ShiftLeft);
+
+        let forward = camera.forward();
+        let right = camera.right();
+        let mut direction = [0.0; 3];
+        for i in 0..3 {
+            direction[i] = forward[i] * forward_amount + right[i] * right_amount + UP[i] * up_amount;
+        }
+        let length = dot(direction, direction).sqrt();
+        if length == 0.0 {
+            return;
+        }
+
+        let mut speed = self.speed;
+        if self.pressed.contains(&KeyCode::ControlLeft) {
+            speed *= self.sprint_multiplier;
+        }
+        let distance = speed * dt.as_secs_f32() / length;
+        for (position, direction) in camera.position.iter_mut().zip(direction) {
+            *position += direction * distance;
+        }
+    }
+}
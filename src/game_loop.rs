@@ -0,0 +1,122 @@
+use std::time::{Duration, Instant};
+
+/// Simulation rate used by `GameLoop::default`.
+pub const DEFAULT_TICK_RATE: u32 = 60;
+
+/// Frames longer than this (breakpoints, window drags, a stalled driver) are clamped so the
+/// simulation doesn't try to catch up on seconds of missed ticks.
+pub const MAX_FRAME_TIME: Duration = Duration::from_millis(250);
+
+/// Upper bound on ticks run in one frame. Time beyond that is dropped instead of carried over,
+/// otherwise a machine that can't keep up would fall further behind every frame.
+pub const MAX_TICKS_PER_FRAME: u32 = 8;
+
+/// What one rendered frame has to do.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FrameTiming {
+    /// Number of fixed simulation ticks to run before rendering.
+    pub ticks: u32,
+    /// How far between the last and the next tick the rendered frame is, in `0.0..1.0`.
+    pub alpha: f32,
+    /// Wall time since the previous frame, before clamping.
+    pub frame_time: Duration,
+    /// Simulation time thrown away this frame to stay real-time.
+    pub dropped: Duration,
+}
+
+/// Accumulates variable frame times and turns them into a whole number of fixed ticks plus the
+/// interpolation factor for the remainder.
+#[derive(Debug, Clone)]
+pub struct FixedTimestep {
+    tick: Duration,
+    accumulator: Duration,
+}
+
+impl FixedTimestep {
+    pub fn new(tick_rate: u32) -> FixedTimestep {
+        FixedTimestep {
+            tick: Duration::from_secs(1) / tick_rate.max(1),
+            accumulator: Duration::ZERO,
+        }
+    }
+
+    pub fn tick(&self) -> Duration {
+        self.tick
+    }
+
+    pub fn advance(&mut self, frame_time: Duration) -> FrameTiming {
+        let clamped = frame_time.min(MAX_FRAME_TIME);
+        let mut dropped = frame_time - clamped;
+        self.accumulator += clamped;
+
+        let mut ticks = 0;
+        while self.accumulator >= self.tick && ticks < MAX_TICKS_PER_FRAME {
+            self.accumulator -= self.tick;
+            ticks += 1;
+        }
+        if self.accumulator >= self.tick {
+            let remainder = Duration::from_nanos((self.accumulator.as_nanos() % self.tick.as_nanos()) as u64);
+            dropped += self.accumulator - remainder;
+            self.accumulator = remainder;
+        }
+
+        FrameTiming {
+            ticks,
+            alpha: self.accumulator.as_secs_f32() / self.tick.as_secs_f32(),
+            frame_time,
+            dropped,
+        }
+    }
+}
+
+/// Drives the windowed main loop: measures frame times, feeds them to a `FixedTimestep` and
+/// optionally limits the frame rate.
+#[derive(Debug, Clone)]
+pub struct GameLoop {
+    timestep: FixedTimestep,
+    frame_cap: Option<Duration>,
+    last_frame: Option<Instant>,
+}
+
+impl Default for GameLoop {
+    fn default() -> Self {
+        GameLoop::new(DEFAULT_TICK_RATE, None)
+    }
+}
+
+impl GameLoop {
+    /// `max_fps` of `None` renders as fast as the present mode allows.
+    pub fn new(tick_rate: u32, max_fps: Option<u32>) -> GameLoop {
+        GameLoop {
+            timestep: FixedTimestep::new(tick_rate),
+            frame_cap: max_fps.map(|fps| Duration::from_secs(1) / fps.max(1)),
+            last_frame: None,
+        }
+    }
+
+    pub fn tick_duration(&self) -> Duration {
+        self.timestep.tick()
+    }
+
+    pub fn set_frame_cap(&mut self, max_fps: Option<u32>) {
+        self.frame_cap = max_fps.map(|fps| Duration::from_secs(1) / fps.max(1));
+    }
+
+    /// Call once per rendered frame. The first frame runs no ticks.
+    pub fn begin_frame(&mut self, now: Instant) -> FrameTiming {
+        let frame_time = self.last_frame.map_or(Duration::ZERO, |last| now.saturating_duration_since(last));
+        self.last_frame = Some(now);
+        self.timestep.advance(frame_time)
+    }
+
+    /// When the next frame may start under the frame cap, or `None` if it may start right away.
+    pub fn next_frame_at(&self) -> Option<Instant> {
+        Some(self.last_frame? + self.frame_cap?)
+    }
+
+    /// Forgets the previous frame, e.g. after the window was minimized, so the pause isn't
+    /// simulated.
+    pub fn pause(&mut self) {
+        self.last_frame = None;
+    }
+}
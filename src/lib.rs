@@ -0,0 +1,6 @@
+pub mod camera;
+pub mod game_loop;
+pub mod window;
+pub mod vulkan;
+
+pub use window::AppEvents;
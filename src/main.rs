@@ -1,10 +1,28 @@
-mod window;
-mod vulkan;
+use std::path::Path;
 
+use voxel_engine::vulkan::headless::HeadlessRenderer;
+use voxel_engine::AppEvents;
 use winit::event_loop::EventLoop;
-use window::*;
 
 fn main() {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn")).init();
+
+    // `--headless <out.png>` renders a single frame offscreen instead of opening a window
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("--headless") {
+        let output = args.get(2).map(String::as_str).unwrap_or("headless.png");
+        let result = HeadlessRenderer::new(800, 600)
+            .and_then(|mut renderer| renderer.render_to_png(Path::new(output)));
+        match result {
+            Ok(()) => println!("Wrote headless frame to {}", output),
+            Err(e) => {
+                eprintln!("Headless rendering failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
     let event_loop = EventLoop::new().unwrap();
     
     let mut events = AppEvents::default();
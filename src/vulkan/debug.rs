@@ -0,0 +1,153 @@
+use std::ffi::{c_void, CStr, CString};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use ash::vk::{self, Handle};
+use ash::{Entry, Instance};
+
+pub const VALIDATION_LAYER: &CStr = c"VK_LAYER_KHRONOS_validation";
+
+/// `1` forces validation on (even in release builds), `0` forces it off.
+pub const VALIDATION_ENV: &str = "VOXEL_VALIDATION";
+
+static VALIDATION_ERRORS: AtomicUsize = AtomicUsize::new(0);
+
+/// Validation is on in debug builds or with the `validation` feature, unless overridden by
+/// `VOXEL_VALIDATION`.
+pub fn validation_requested() -> bool {
+    match std::env::var(VALIDATION_ENV).as_deref() {
+        Ok("1") => true,
+        Ok("0") => false,
+        _ => cfg!(debug_assertions) || cfg!(feature = "validation"),
+    }
+}
+
+pub fn check_validation_layer_support(entry: &Entry) -> bool {
+    let available_layers = unsafe {
+        entry.enumerate_instance_layer_properties().unwrap_or_default()
+    };
+
+    available_layers.iter().any(|layer| {
+        let layer_name = unsafe { CStr::from_ptr(layer.layer_name.as_ptr()) };
+        layer_name == VALIDATION_LAYER
+    })
+}
+
+/// Number of validation errors reported by any messenger in this process. Tests assert this stays
+/// at zero after rendering.
+pub fn validation_error_count() -> usize {
+    VALIDATION_ERRORS.load(Ordering::SeqCst)
+}
+
+/// Forwards Vulkan messages to the `log` crate: ERROR -> error, WARNING -> warn, INFO -> info and
+/// VERBOSE -> trace.
+unsafe extern "system" fn vulkan_debug_callback(
+    message_severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+    message_type: vk::DebugUtilsMessageTypeFlagsEXT,
+    p_callback_data: *const vk::DebugUtilsMessengerCallbackDataEXT<'_>,
+    _p_user_data: *mut c_void,
+) -> vk::Bool32 {
+    let (message_id, message) = if p_callback_data.is_null() {
+        (None, None)
+    } else {
+        let data = &*p_callback_data;
+        (
+            (!data.p_message_id_name.is_null()).then(|| CStr::from_ptr(data.p_message_id_name).to_string_lossy()),
+            (!data.p_message.is_null()).then(|| CStr::from_ptr(data.p_message).to_string_lossy()),
+        )
+    };
+    let message_id = message_id.unwrap_or_default();
+    let message = message.unwrap_or_default();
+
+    let level = match message_severity {
+        vk::DebugUtilsMessageSeverityFlagsEXT::ERROR => log::Level::Error,
+        vk::DebugUtilsMessageSeverityFlagsEXT::WARNING => log::Level::Warn,
+        vk::DebugUtilsMessageSeverityFlagsEXT::INFO => log::Level::Info,
+        _ => log::Level::Trace,
+    };
+    if level == log::Level::Error && message_type.contains(vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION) {
+        VALIDATION_ERRORS.fetch_add(1, Ordering::SeqCst);
+    }
+
+    log::log!(target: "vulkan", level, "[{:?}] {} {}", message_type, message_id, message);
+
+    vk::FALSE
+}
+
+/// Chain this into `InstanceCreateInfo::p_next` to also catch messages from instance creation and
+/// destruction.
+pub fn debug_messenger_create_info() -> vk::DebugUtilsMessengerCreateInfoEXT<'static> {
+    vk::DebugUtilsMessengerCreateInfoEXT {
+        s_type: vk::StructureType::DEBUG_UTILS_MESSENGER_CREATE_INFO_EXT,
+        message_severity: vk::DebugUtilsMessageSeverityFlagsEXT::ERROR
+            | vk::DebugUtilsMessageSeverityFlagsEXT::WARNING
+            | vk::DebugUtilsMessageSeverityFlagsEXT::INFO
+            | vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE,
+        message_type: vk::DebugUtilsMessageTypeFlagsEXT::GENERAL
+            | vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION
+            | vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE,
+        pfn_user_callback: Some(vulkan_debug_callback),
+        ..Default::default()
+    }
+}
+
+pub struct DebugMessenger {
+    loader: ash::ext::debug_utils::Instance,
+    messenger: vk::DebugUtilsMessengerEXT,
+}
+
+impl DebugMessenger {
+    pub fn new(entry: &Entry, instance: &Instance) -> Result<DebugMessenger, vk::Result> {
+        let loader = ash::ext::debug_utils::Instance::new(entry, instance);
+        let messenger = unsafe {
+            loader.create_debug_utils_messenger(&debug_messenger_create_info(), None)?
+        };
+
+        Ok(DebugMessenger { loader, messenger })
+    }
+
+    /// Must be called before the instance is destroyed.
+    pub fn destroy(&self) {
+        unsafe { self.loader.destroy_debug_utils_messenger(self.messenger, None) };
+    }
+}
+
+/// Attaches readable names to Vulkan handles for validation messages and capture tools such as
+/// RenderDoc. Every call is a no-op when `VK_EXT_debug_utils` is not enabled.
+#[derive(Clone, Default)]
+pub struct DebugNames {
+    loader: Option<ash::ext::debug_utils::Device>,
+}
+
+impl DebugNames {
+    pub fn new(instance: &Instance, device: &ash::Device, enabled: bool) -> DebugNames {
+        DebugNames {
+            loader: enabled.then(|| ash::ext::debug_utils::Device::new(instance, device)),
+        }
+    }
+
+    pub fn name<H: Handle>(&self, handle: H, name: &str) {
+        let Some(loader) = self.loader.as_ref() else {
+            return;
+        };
+        let object_name = CString::new(name).unwrap();
+        let name_info = vk::DebugUtilsObjectNameInfoEXT {
+            s_type: vk::StructureType::DEBUG_UTILS_OBJECT_NAME_INFO_EXT,
+            object_type: H::TYPE,
+            object_handle: handle.as_raw(),
+            p_object_name: object_name.as_ptr(),
+            ..Default::default()
+        };
+
+        unsafe {
+            if let Err(e) = loader.set_debug_utils_object_name(&name_info) {
+                log::warn!("Failed to name {:?} object {}: {:?}", H::TYPE, name, e);
+            }
+        }
+    }
+
+    pub fn name_all<H: Handle + Copy>(&self, handles: &[H], name: &str) {
+        for (i, &handle) in handles.iter().enumerate() {
+            self.name(handle, &format!("{} {}", name, i));
+        }
+    }
+}
@@ -0,0 +1,138 @@
+use ash::vk;
+
+/// A device-owned Vulkan object waiting to be destroyed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum VulkanObject {
+    ImageView(vk::ImageView),
+    Image(vk::Image),
+    Buffer(vk::Buffer),
+    DeviceMemory(vk::DeviceMemory),
+    Framebuffer(vk::Framebuffer),
+    RenderPass(vk::RenderPass),
+    PipelineLayout(vk::PipelineLayout),
+    Pipeline(vk::Pipeline),
+    PipelineCache(vk::PipelineCache),
+    DescriptorSetLayout(vk::DescriptorSetLayout),
+    ShaderModule(vk::ShaderModule),
+    CommandPool(vk::CommandPool),
+    CommandBuffers(vk::CommandPool, Vec<vk::CommandBuffer>),
+    Semaphore(vk::Semaphore),
+    Fence(vk::Fence),
+}
+
+macro_rules! impl_from_handle {
+    ($($handle:ident),* $(,)?) => {
+        $(
+            impl From<vk::$handle> for VulkanObject {
+                fn from(handle: vk::$handle) -> Self {
+                    VulkanObject::$handle(handle)
+                }
+            }
+        )*
+    };
+}
+
+impl_from_handle!(
+    ImageView,
+    Image,
+    Buffer,
+    DeviceMemory,
+    Framebuffer,
+    RenderPass,
+    PipelineLayout,
+    Pipeline,
+    PipelineCache,
+    DescriptorSetLayout,
+    ShaderModule,
+    CommandPool,
+    Semaphore,
+    Fence,
+);
+
+impl VulkanObject {
+    /// # Safety
+    /// The object must have been created from `device` and no longer be in use by the GPU.
+    unsafe fn destroy(self, device: &ash::Device) {
+        match self {
+            VulkanObject::ImageView(handle) => device.destroy_image_view(handle, None),
+            VulkanObject::Image(handle) => device.destroy_image(handle, None),
+            VulkanObject::Buffer(handle) => device.destroy_buffer(handle, None),
+            VulkanObject::DeviceMemory(handle) => device.free_memory(handle, None),
+            VulkanObject::Framebuffer(handle) => device.destroy_framebuffer(handle, None),
+            VulkanObject::RenderPass(handle) => device.destroy_render_pass(handle, None),
+            VulkanObject::PipelineLayout(handle) => device.destroy_pipeline_layout(handle, None),
+            VulkanObject::Pipeline(handle) => device.destroy_pipeline(handle, None),
+            VulkanObject::PipelineCache(handle) => device.destroy_pipeline_cache(handle, None),
+            VulkanObject::DescriptorSetLayout(handle) => device.destroy_descriptor_set_layout(handle, None),
+            VulkanObject::ShaderModule(handle) => device.destroy_shader_module(handle, None),
+            VulkanObject::CommandPool(handle) => device.destroy_command_pool(handle, None),
+            VulkanObject::CommandBuffers(pool, buffers) => {
+                if !buffers.is_empty() {
+                    device.free_command_buffers(pool, &buffers);
+                }
+            }
+            VulkanObject::Semaphore(handle) => device.destroy_semaphore(handle, None),
+            VulkanObject::Fence(handle) => device.destroy_fence(handle, None),
+        }
+    }
+}
+
+/// Records objects as they are created and destroys them in reverse order on `flush`, so anything
+/// created from an earlier object (a framebuffer from a render pass, a command buffer from its
+/// pool) is always gone first. Each queue is tied to one lifetime: the swapchain's queue is
+/// flushed on every recreation, the device's queue right before `destroy_device`.
+#[derive(Debug, Default)]
+pub struct DeletionQueue {
+    objects: Vec<VulkanObject>,
+}
+
+impl DeletionQueue {
+    pub fn push(&mut self, object: impl Into<VulkanObject>) {
+        self.objects.push(object.into());
+    }
+
+    pub fn push_all<H: Copy + Into<VulkanObject>>(&mut self, handles: &[H]) {
+        self.objects.extend(handles.iter().map(|&handle| handle.into()));
+    }
+
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+
+    /// Destroys `old` right away and queues `new` in its place, so `new` is destroyed at the point
+    /// in the order where `old` would have been. `old` must be queued and no longer in use.
+    pub fn replace(&mut self, device: &ash::Device, old: impl Into<VulkanObject>, new: impl Into<VulkanObject>) {
+        let old = old.into();
+        let new = new.into();
+        match self.objects.iter().position(|object| *object == old) {
+            Some(index) => {
+                self.objects[index] = new;
+                unsafe { old.destroy(device) };
+            }
+            None => {
+                log::error!("Replacing {:?}, which isn't queued for deletion", old);
+                self.objects.push(new);
+            }
+        }
+    }
+
+    /// Destroys every queued object, newest first. The device must be idle (or at least done
+    /// with all of them).
+    pub fn flush(&mut self, device: &ash::Device) {
+        while let Some(object) = self.objects.pop() {
+            unsafe { object.destroy(device) };
+        }
+    }
+}
+
+impl Drop for DeletionQueue {
+    fn drop(&mut self) {
+        if !self.objects.is_empty() {
+            log::error!("Leaked {} Vulkan objects: {:?}", self.objects.len(), self.objects);
+        }
+    }
+}
@@ -0,0 +1,73 @@
+use ash::{vk, Instance};
+
+use crate::vulkan::error::*;
+use crate::vulkan::memory::*;
+
+/// Depth formats in order of preference. The spec requires depth attachment support for at least
+/// one of `X8_D24_UNORM_PACK32` and `D32_SFLOAT`, and at least one of `D24_UNORM_S8_UINT` and
+/// `D32_SFLOAT_S8_UINT`, so one of these is always available.
+pub const DEPTH_FORMAT_CANDIDATES: [vk::Format; 4] = [
+    vk::Format::D32_SFLOAT,
+    vk::Format::X8_D24_UNORM_PACK32,
+    vk::Format::D24_UNORM_S8_UINT,
+    vk::Format::D32_SFLOAT_S8_UINT,
+];
+
+/// Depth clear value. Depth is reverse-Z (near is 1, far is 0, see `camera::Projection`), so the
+/// buffer starts at the far plane and `GREATER_OR_EQUAL` lets anything in front of it through.
+pub const DEPTH_CLEAR_VALUE: vk::ClearDepthStencilValue = vk::ClearDepthStencilValue { depth: 0.0, stencil: 0 };
+
+/// Returns the first of `candidates` whose optimal-tiling features, as reported by `features`,
+/// include `required`.
+pub fn select_format(
+    candidates: &[vk::Format],
+    required: vk::FormatFeatureFlags,
+    features: impl Fn(vk::Format) -> vk::FormatFeatureFlags,
+) -> Option<vk::Format> {
+    candidates.iter().copied().find(|&format| features(format).contains(required))
+}
+
+/// Picks the depth attachment format for `physical_device` from `DEPTH_FORMAT_CANDIDATES`.
+pub fn find_depth_format(instance: &Instance, physical_device: vk::PhysicalDevice) -> EngineResult<vk::Format> {
+    select_format(
+        &DEPTH_FORMAT_CANDIDATES,
+        vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT,
+        |format| unsafe {
+            instance
+                .get_physical_device_format_properties(physical_device, format)
+                .optimal_tiling_features
+        },
+    )
+    .ok_or_else(|| EngineError::DeviceSelection("no supported depth attachment format".to_string()))
+}
+
+pub fn has_stencil_component(format: vk::Format) -> bool {
+    matches!(
+        format,
+        vk::Format::D16_UNORM_S8_UINT | vk::Format::D24_UNORM_S8_UINT | vk::Format::D32_SFLOAT_S8_UINT
+    )
+}
+
+/// Aspects a view of a depth image in `format` has to cover.
+pub fn depth_aspect_mask(format: vk::Format) -> vk::ImageAspectFlags {
+    if has_stencil_component(format) {
+        vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL
+    } else {
+        vk::ImageAspectFlags::DEPTH
+    }
+}
+
+/// A device-local depth attachment matching `extent` and the color target's sample count. Its
+/// contents are cleared at the start of every render pass and never stored, so no layout
+/// transition is needed up front.
+pub fn create_depth_image(
+    allocator: &MemoryAllocator,
+    name: &str,
+    format: vk::Format,
+    extent: vk::Extent2D,
+    samples: vk::SampleCountFlags,
+) -> EngineResult<Image> {
+    let mut desc = ImageDesc::new(format, extent, vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT);
+    desc.samples = samples;
+    allocator.create_image(name, desc, MemoryUsage::GpuOnly)
+}
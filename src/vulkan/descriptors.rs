@@ -0,0 +1,319 @@
+use std::mem::size_of;
+
+use ash::vk;
+
+use crate::vulkan::error::*;
+use crate::vulkan::memory::*;
+
+/// Column-major 4x4 matrix, laid out the way GLSL's `mat4` expects it.
+pub type Mat4 = [[f32; 4]; 4];
+
+pub const IDENTITY: Mat4 = [
+    [1.0, 0.0, 0.0, 0.0],
+    [0.0, 1.0, 0.0, 0.0],
+    [0.0, 0.0, 1.0, 0.0],
+    [0.0, 0.0, 0.0, 1.0],
+];
+
+/// Mirrors `UniformBufferObject` at set 0, binding 0 in `shaders/glsl.vert`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UniformBufferObject {
+    pub model: Mat4,
+    pub view: Mat4,
+    pub proj: Mat4,
+}
+
+// SAFETY: three `mat4`s of `f32` in a `repr(C)` struct leave no padding
+unsafe impl Pod for UniformBufferObject {}
+
+impl Default for UniformBufferObject {
+    fn default() -> Self {
+        UniformBufferObject {
+            model: IDENTITY,
+            view: IDENTITY,
+            proj: IDENTITY,
+        }
+    }
+}
+
+/// Collects bindings for one descriptor set layout.
+#[derive(Default)]
+pub struct DescriptorLayoutBuilder {
+    bindings: Vec<vk::DescriptorSetLayoutBinding<'static>>,
+}
+
+impl DescriptorLayoutBuilder {
+    pub fn new() -> DescriptorLayoutBuilder {
+        DescriptorLayoutBuilder::default()
+    }
+
+    pub fn add_binding(mut self, binding: u32, descriptor_type: vk::DescriptorType, stage_flags: vk::ShaderStageFlags) -> Self {
+        self.bindings.push(vk::DescriptorSetLayoutBinding {
+            binding,
+            descriptor_type,
+            descriptor_count: 1,
+            stage_flags,
+            ..Default::default()
+        });
+        self
+    }
+
+    pub fn bindings(&self) -> &[vk::DescriptorSetLayoutBinding<'static>] {
+        &self.bindings
+    }
+
+    pub fn build(&self, device: &ash::Device) -> EngineResult<vk::DescriptorSetLayout> {
+        let layout_create_info = vk::DescriptorSetLayoutCreateInfo {
+            s_type: vk::StructureType::DESCRIPTOR_SET_LAYOUT_CREATE_INFO,
+            binding_count: self.bindings.len() as u32,
+            p_bindings: self.bindings.as_ptr(),
+            ..Default::default()
+        };
+
+        unsafe {
+            device
+                .create_descriptor_set_layout(&layout_create_info, None)
+                .context("create descriptor set layout")
+        }
+    }
+}
+
+/// How many descriptors of a type each pool holds, per set it can allocate.
+#[derive(Debug, Clone, Copy)]
+pub struct PoolSizeRatio {
+    pub descriptor_type: vk::DescriptorType,
+    pub ratio: f32,
+}
+
+const MAX_SETS_PER_POOL: u32 = 4092;
+
+/// Hands out descriptor sets from a list of pools, creating a bigger pool whenever the current
+/// one runs out. Sets are never freed one by one; `reset` recycles all of them at once.
+pub struct DescriptorAllocator {
+    ratios: Vec<PoolSizeRatio>,
+    ready_pools: Vec<vk::DescriptorPool>,
+    full_pools: Vec<vk::DescriptorPool>,
+    sets_per_pool: u32,
+}
+
+impl Default for DescriptorAllocator {
+    fn default() -> Self {
+        DescriptorAllocator::new(&[], 1)
+    }
+}
+
+impl DescriptorAllocator {
+    /// No pool is created until the first allocation.
+    pub fn new(ratios: &[PoolSizeRatio], initial_sets: u32) -> DescriptorAllocator {
+        DescriptorAllocator {
+            ratios: ratios.to_vec(),
+            ready_pools: Vec::new(),
+            full_pools: Vec::new(),
+            sets_per_pool: initial_sets.max(1),
+        }
+    }
+
+    fn create_pool(&mut self, device: &ash::Device) -> EngineResult<vk::DescriptorPool> {
+        let set_count = self.sets_per_pool;
+        self.sets_per_pool = (self.sets_per_pool + self.sets_per_pool / 2).min(MAX_SETS_PER_POOL);
+
+        let pool_sizes: Vec<_> = self.ratios.iter().map(|ratio| vk::DescriptorPoolSize {
+            ty: ratio.descriptor_type,
+            descriptor_count: ((ratio.ratio * set_count as f32).ceil() as u32).max(1),
+        }).collect();
+        let pool_create_info = vk::DescriptorPoolCreateInfo {
+            s_type: vk::StructureType::DESCRIPTOR_POOL_CREATE_INFO,
+            max_sets: set_count,
+            pool_size_count: pool_sizes.len() as u32,
+            p_pool_sizes: pool_sizes.as_ptr(),
+            ..Default::default()
+        };
+
+        unsafe {
+            device
+                .create_descriptor_pool(&pool_create_info, None)
+                .context("create descriptor pool")
+        }
+    }
+
+    pub fn allocate(&mut self, device: &ash::Device, layout: vk::DescriptorSetLayout) -> EngineResult<vk::DescriptorSet> {
+        let layouts = [layout];
+        loop {
+            let (pool, fresh) = match self.ready_pools.pop() {
+                Some(pool) => (pool, false),
+                None => (self.create_pool(device)?, true),
+            };
+            let allocate_info = vk::DescriptorSetAllocateInfo {
+                s_type: vk::StructureType::DESCRIPTOR_SET_ALLOCATE_INFO,
+                descriptor_pool: pool,
+                descriptor_set_count: 1,
+                p_set_layouts: layouts.as_ptr(),
+                ..Default::default()
+            };
+
+            match unsafe { device.allocate_descriptor_sets(&allocate_info) } {
+                Ok(sets) => {
+                    self.ready_pools.push(pool);
+                    return Ok(sets[0]);
+                }
+                Err(vk::Result::ERROR_OUT_OF_POOL_MEMORY | vk::Result::ERROR_FRAGMENTED_POOL) => {
+                    self.full_pools.push(pool);
+                    // A fresh pool that can't fit a single set would loop forever
+                    if fresh {
+                        return Err(vk::Result::ERROR_OUT_OF_POOL_MEMORY).context("allocate descriptor set");
+                    }
+                }
+                Err(e) => {
+                    self.ready_pools.push(pool);
+                    return Err(e).context("allocate descriptor set");
+                }
+            }
+        }
+    }
+
+    /// Returns every set to its pool. None of them may still be in use by the GPU.
+    pub fn reset(&mut self, device: &ash::Device) -> EngineResult<()> {
+        self.ready_pools.append(&mut self.full_pools);
+        for &pool in &self.ready_pools {
+            unsafe {
+                device
+                    .reset_descriptor_pool(pool, vk::DescriptorPoolResetFlags::empty())
+                    .context("reset descriptor pool")?
+            };
+        }
+        Ok(())
+    }
+
+    pub fn destroy(&mut self, device: &ash::Device) {
+        for pool in self.ready_pools.drain(..).chain(self.full_pools.drain(..)) {
+            unsafe { device.destroy_descriptor_pool(pool, None) };
+        }
+    }
+}
+
+enum DescriptorInfo {
+    Buffer(vk::DescriptorBufferInfo),
+    Image(vk::DescriptorImageInfo),
+}
+
+/// Batches descriptor writes and applies them to a set in one `update_descriptor_sets` call.
+#[derive(Default)]
+pub struct DescriptorWriter {
+    writes: Vec<(u32, vk::DescriptorType, DescriptorInfo)>,
+}
+
+impl DescriptorWriter {
+    pub fn new() -> DescriptorWriter {
+        DescriptorWriter::default()
+    }
+
+    pub fn write_buffer(
+        mut self,
+        binding: u32,
+        buffer: vk::Buffer,
+        offset: vk::DeviceSize,
+        range: vk::DeviceSize,
+        descriptor_type: vk::DescriptorType,
+    ) -> Self {
+        let info = vk::DescriptorBufferInfo { buffer, offset, range };
+        self.writes.push((binding, descriptor_type, DescriptorInfo::Buffer(info)));
+        self
+    }
+
+    pub fn write_image(
+        mut self,
+        binding: u32,
+        image_view: vk::ImageView,
+        sampler: vk::Sampler,
+        image_layout: vk::ImageLayout,
+        descriptor_type: vk::DescriptorType,
+    ) -> Self {
+        let info = vk::DescriptorImageInfo { sampler, image_view, image_layout };
+        self.writes.push((binding, descriptor_type, DescriptorInfo::Image(info)));
+        self
+    }
+
+    pub fn update_set(&self, device: &ash::Device, set: vk::DescriptorSet) {
+        let writes: Vec<_> = self.writes.iter().map(|(binding, descriptor_type, info)| {
+            let mut write = vk::WriteDescriptorSet {
+                s_type: vk::StructureType::WRITE_DESCRIPTOR_SET,
+                dst_set: set,
+                dst_binding: *binding,
+                descriptor_count: 1,
+                descriptor_type: *descriptor_type,
+                ..Default::default()
+            };
+            match info {
+                DescriptorInfo::Buffer(info) => write.p_buffer_info = info,
+                DescriptorInfo::Image(info) => write.p_image_info = info,
+            }
+            write
+        }).collect();
+
+        unsafe { device.update_descriptor_sets(&writes, &[]) };
+    }
+}
+
+/// Set 0 as used by the scene pipeline: the camera UBO for the vertex stage.
+pub fn scene_descriptor_layout() -> DescriptorLayoutBuilder {
+    DescriptorLayoutBuilder::new().add_binding(0, vk::DescriptorType::UNIFORM_BUFFER, vk::ShaderStageFlags::VERTEX)
+}
+
+pub fn create_scene_descriptor_set_layout(device: &ash::Device) -> EngineResult<vk::DescriptorSetLayout> {
+    scene_descriptor_layout().build(device)
+}
+
+/// One host-visible uniform buffer and descriptor set per frame in flight, so the CPU can write
+/// the next frame's matrices while the GPU still reads the previous ones.
+#[derive(Default)]
+pub struct FrameUniforms {
+    buffers: Vec<Buffer>,
+    sets: Vec<vk::DescriptorSet>,
+}
+
+impl FrameUniforms {
+    pub fn new(
+        device: &ash::Device,
+        allocator: &MemoryAllocator,
+        descriptor_allocator: &mut DescriptorAllocator,
+        layout: vk::DescriptorSetLayout,
+        frame_count: usize,
+    ) -> EngineResult<FrameUniforms> {
+        let mut frame_uniforms = FrameUniforms::default();
+        for frame in 0..frame_count {
+            let mut buffer = allocator.create_buffer(
+                &format!("Camera Uniform Buffer {}", frame),
+                size_of::<UniformBufferObject>() as vk::DeviceSize,
+                vk::BufferUsageFlags::UNIFORM_BUFFER,
+                MemoryUsage::CpuToGpu,
+            )?;
+            buffer.write(0, &[UniformBufferObject::default()]);
+
+            let set = descriptor_allocator.allocate(device, layout)?;
+            DescriptorWriter::new()
+                .write_buffer(0, buffer.handle(), 0, buffer.size(), vk::DescriptorType::UNIFORM_BUFFER)
+                .update_set(device, set);
+
+            frame_uniforms.buffers.push(buffer);
+            frame_uniforms.sets.push(set);
+        }
+        Ok(frame_uniforms)
+    }
+
+    pub fn sets(&self) -> &[vk::DescriptorSet] {
+        &self.sets
+    }
+
+    /// The frame's previous submission must have finished (its in-flight fence waited on).
+    pub fn update(&mut self, frame: usize, ubo: &UniformBufferObject) {
+        self.buffers[frame].write(0, std::slice::from_ref(ubo));
+    }
+}
+
+/// Binds `set` as set 0 for the graphics pipeline using `layout`.
+pub fn bind_descriptor_set(device: &ash::Device, command_buffer: vk::CommandBuffer, layout: vk::PipelineLayout, set: vk::DescriptorSet) {
+    unsafe {
+        device.cmd_bind_descriptor_sets(command_buffer, vk::PipelineBindPoint::GRAPHICS, layout, 0, &[set], &[]);
+    }
+}
@@ -1,92 +1,437 @@
 // use std::ffi::CStr;
+use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
 
 use ash::vk;
-use ash::Instance;
+use ash::{Entry, Instance};
 
+use crate::vulkan::debug::*;
+use crate::vulkan::error::*;
 
-pub fn pick_physical_device(instance: &Instance) -> vk::PhysicalDevice {
+/// Creates the instance with the given extensions. When validation is requested and the layer is
+/// installed, `VK_LAYER_KHRONOS_validation` and `VK_EXT_debug_utils` are enabled as well and a
+/// messenger routing to the logger is returned alongside the instance.
+pub fn create_instance(
+    entry: &Entry,
+    app_name: &str,
+    mut extension_names: Vec<*const c_char>,
+) -> EngineResult<(Instance, Option<DebugMessenger>)> {
+    let app_name = CString::new(app_name).unwrap();
+    let engine_name = CString::new("Custom Engine").unwrap();
+    let app_info = vk::ApplicationInfo {
+        s_type: vk::StructureType::APPLICATION_INFO,
+        p_application_name: app_name.as_ptr(),
+        application_version: vk::make_api_version(0, 1, 0, 0),
+        p_engine_name: engine_name.as_ptr(),
+        engine_version: vk::make_api_version(0, 1, 0, 0),
+        api_version: vk::make_api_version(0, 1, 3, 0),
+        ..Default::default()
+    };
+
+    let enable_validation = validation_requested() && {
+        let supported = check_validation_layer_support(entry);
+        if !supported {
+            log::warn!("Validation requested but {:?} is not installed", VALIDATION_LAYER);
+        }
+        supported
+    };
+    let validation_layers = [VALIDATION_LAYER.as_ptr()];
+    if enable_validation {
+        extension_names.push(ash::ext::debug_utils::NAME.as_ptr());
+    }
+
+    let messenger_info = debug_messenger_create_info();
+    let instance_info = vk::InstanceCreateInfo {
+        s_type: vk::StructureType::INSTANCE_CREATE_INFO,
+        p_next: if enable_validation {
+            &messenger_info as *const _ as *const std::ffi::c_void
+        } else {
+            std::ptr::null()
+        },
+        p_application_info: &app_info,
+        enabled_extension_count: extension_names.len() as u32,
+        pp_enabled_extension_names: extension_names.as_ptr(),
+        enabled_layer_count: if enable_validation { validation_layers.len() as u32 } else { 0 },
+        pp_enabled_layer_names: validation_layers.as_ptr(),
+        ..Default::default()
+    };
+
+    let instance = unsafe { entry.create_instance(&instance_info, None) }.map_err(EngineError::Instance)?;
+    // Validation output is a debugging aid, so a failing messenger is not worth aborting over
+    let debug_messenger = if enable_validation {
+        DebugMessenger::new(entry, &instance)
+            .map_err(|e| log::warn!("Failed to create debug messenger: {}", e))
+            .ok()
+    } else {
+        None
+    };
+    println!("Validation layers enabled: {}", enable_validation);
+
+    Ok((instance, debug_messenger))
+}
+
+
+/// Forces a device by its index in the candidate list or by (part of) its name, e.g.
+/// `VOXEL_DEVICE=1` or `VOXEL_DEVICE=llvmpipe`. Overrides `AppEvents::device_preference`.
+pub const DEVICE_ENV: &str = "VOXEL_DEVICE";
+
+/// One physical device as seen by `pick_physical_device`. `score` is `None` when the device can't
+/// run the engine, with the reason in `rejection`.
+#[derive(Debug, Clone)]
+pub struct DeviceCandidate {
+    pub device: vk::PhysicalDevice,
+    pub index: usize,
+    pub name: String,
+    pub device_type: vk::PhysicalDeviceType,
+    pub score: Option<u32>,
+    pub rejection: Option<String>,
+}
+
+/// Base score per device type. CPU implementations such as lavapipe or SwiftShader score lowest
+/// but are still accepted, so the engine runs (slowly) on machines without a GPU.
+pub fn device_type_score(device_type: vk::PhysicalDeviceType) -> u32 {
+    match device_type {
+        vk::PhysicalDeviceType::DISCRETE_GPU => 10_000,
+        vk::PhysicalDeviceType::INTEGRATED_GPU => 5_000,
+        vk::PhysicalDeviceType::VIRTUAL_GPU => 2_000,
+        vk::PhysicalDeviceType::CPU => 100,
+        _ => 50,
+    }
+}
+
+/// Optional features the engine can make use of; each one present adds to the score.
+pub fn device_feature_score(features: &vk::PhysicalDeviceFeatures) -> u32 {
+    [
+        (features.sampler_anisotropy, 200),
+        (features.fill_mode_non_solid, 50),
+        (features.multi_draw_indirect, 100),
+        (features.geometry_shader, 50),
+    ]
+    .iter()
+    .filter(|(supported, _)| *supported == vk::TRUE)
+    .map(|(_, score)| score)
+    .sum()
+}
+
+/// Whether `preference` (an index or a case-insensitive part of the name) selects this device.
+pub fn device_matches_preference(preference: &str, index: usize, name: &str) -> bool {
+    let preference = preference.trim();
+    match preference.parse::<usize>() {
+        Ok(preferred_index) => preferred_index == index,
+        Err(_) => !preference.is_empty() && name.to_lowercase().contains(&preference.to_lowercase()),
+    }
+}
+
+/// Scores `device`, or returns why it can't be used. With a surface the device also needs the
+/// swapchain extension, a queue family that can present and at least one surface format and
+/// present mode. A failed query makes the device unsuitable rather than aborting the selection,
+/// so one broken device doesn't hide the others.
+fn rate_physical_device(
+    instance: &Instance,
+    device: vk::PhysicalDevice,
+    surface: Option<(&ash::khr::surface::Instance, vk::SurfaceKHR)>,
+) -> Result<u32, String> {
+    let properties = unsafe { instance.get_physical_device_properties(device) };
+    let features = unsafe { instance.get_physical_device_features(device) };
+
+    if find_graphics_queue_family(instance, device).is_none() {
+        return Err("no graphics queue family".to_string());
+    }
+
+    if let Some((surface_loader, surface)) = surface {
+        let extensions = unsafe {
+            instance
+                .enumerate_device_extension_properties(device)
+                .map_err(|e| format!("failed to enumerate device extensions: {}", e))?
+        };
+        let has_swapchain = extensions.iter().any(|extension| {
+            let name = unsafe { CStr::from_ptr(extension.extension_name.as_ptr()) };
+            name == ash::khr::swapchain::NAME
+        });
+        if !has_swapchain {
+            return Err(format!("missing {:?}", ash::khr::swapchain::NAME));
+        }
+
+        let queue_family_count = unsafe { instance.get_physical_device_queue_family_properties(device).len() };
+        let mut can_present = false;
+        for index in 0..queue_family_count as u32 {
+            let supported = unsafe {
+                surface_loader
+                    .get_physical_device_surface_support(device, index, surface)
+                    .map_err(|e| format!("failed to query surface support: {}", e))?
+            };
+            can_present |= supported;
+        }
+        if !can_present {
+            return Err("no queue family can present to the window".to_string());
+        }
+
+        let formats = unsafe {
+            surface_loader
+                .get_physical_device_surface_formats(device, surface)
+                .map_err(|e| format!("failed to query surface formats: {}", e))?
+        };
+        let present_modes = unsafe {
+            surface_loader
+                .get_physical_device_surface_present_modes(device, surface)
+                .map_err(|e| format!("failed to query surface present modes: {}", e))?
+        };
+        if formats.is_empty() || present_modes.is_empty() {
+            return Err("no surface formats or present modes".to_string());
+        }
+    }
+
+    let mut score = device_type_score(properties.device_type) + device_feature_score(&features);
+    // Larger textures are a reasonable proxy for a more capable device within the same type
+    score += properties.limits.max_image_dimension2_d / 1024;
+
+    Ok(score)
+}
+
+/// Lists every device with its score and returns the best one, or the one selected by
+/// `VOXEL_DEVICE` / `preference`. Pass the window surface to require presentation support; pass
+/// `None` for offscreen rendering.
+pub fn pick_physical_device(
+    instance: &Instance,
+    surface: Option<(&ash::khr::surface::Instance, vk::SurfaceKHR)>,
+    preference: Option<&str>,
+) -> EngineResult<vk::PhysicalDevice> {
     let devices = unsafe {
         instance
             .enumerate_physical_devices()
-            .expect("Failed to enumerate physical devices!")
+            .context("enumerate physical devices")?
     };
 
     println!("Found {} physical devices.", devices.len());
 
-    let mut selected_device = None;
-
-    for &device in &devices {
+    let mut candidates = Vec::with_capacity(devices.len());
+    for (index, &device) in devices.iter().enumerate() {
         let properties = unsafe { instance.get_physical_device_properties(device) };
-        let features = unsafe { instance.get_physical_device_features(device) };
+        let name = unsafe { CStr::from_ptr(properties.device_name.as_ptr()) }
+            .to_string_lossy()
+            .into_owned();
+        let rating = rate_physical_device(instance, device, surface);
 
-        println!("Device: {:?} ({:?}) API_VER: {:?}",
-            unsafe { std::ffi::CStr::from_ptr(properties.device_name.as_ptr()) },
+        println!("[{}] Device: {} ({:?}) API_VER: {}.{}.{} Score: {}",
+            index,
+            name,
             properties.device_type,
-            properties.api_version
-        );
-        // println!("Supported Features: {:?}", features); // Debug Vulkan Feature Support for device
-
-        let is_discrete_gpu = properties.device_type == vk::PhysicalDeviceType::DISCRETE_GPU;
-        let is_integrated_gpu = properties.device_type == vk::PhysicalDeviceType::INTEGRATED_GPU;
-        let supports_geometry_shader = features.geometry_shader == vk::TRUE;
-
-        if supports_geometry_shader {
-            if is_discrete_gpu {
-                selected_device = Some(device);
-                break; // Immediately select discrete GPU if available
-            } else if is_integrated_gpu && selected_device.is_none() {
-                selected_device = Some(device); // Fallback to integrated GPU if no discrete found
+            vk::api_version_major(properties.api_version),
+            vk::api_version_minor(properties.api_version),
+            vk::api_version_patch(properties.api_version),
+            match &rating {
+                Ok(score) => score.to_string(),
+                Err(reason) => format!("unsuitable ({})", reason),
             }
+        );
+
+        candidates.push(DeviceCandidate {
+            device,
+            index,
+            name,
+            device_type: properties.device_type,
+            score: rating.as_ref().ok().copied(),
+            rejection: rating.err(),
+        });
+    }
+
+    let env_preference = std::env::var(DEVICE_ENV).ok();
+    if let Some(preference) = env_preference.as_deref().or(preference) {
+        let forced = candidates.iter()
+            .find(|candidate| device_matches_preference(preference, candidate.index, &candidate.name))
+            .ok_or_else(|| EngineError::DeviceSelection(format!("no device matches {:?}", preference)))?;
+        if let Some(reason) = &forced.rejection {
+            return Err(EngineError::DeviceSelection(format!("requested device {} is unsuitable: {}", forced.name, reason)));
         }
+        println!("Using requested device [{}] {}", forced.index, forced.name);
+        return Ok(forced.device);
     }
 
-    let physical_device = selected_device.expect("No suitable GPU found!");
-    physical_device
+    let best = candidates.iter()
+        .filter_map(|candidate| candidate.score.map(|score| (score, candidate)))
+        .max_by_key(|(score, candidate)| (*score, std::cmp::Reverse(candidate.index)))
+        .map(|(_, candidate)| candidate)
+        .ok_or_else(|| EngineError::DeviceSelection(format!(
+            "none of the {} devices can run the engine: {}",
+            candidates.len(),
+            candidates.iter()
+                .map(|candidate| format!("{} ({})", candidate.name, candidate.rejection.as_deref().unwrap_or("unknown")))
+                .collect::<Vec<_>>()
+                .join(", ")
+        )))?;
+
+    if best.device_type == vk::PhysicalDeviceType::CPU {
+        log::warn!("No GPU available, falling back to software rendering on {}", best.name);
+    }
+    println!("Using device [{}] {}", best.index, best.name);
+    Ok(best.device)
 }
 
-pub fn find_queue_families(instance: &Instance, device: vk::PhysicalDevice, surface: vk::SurfaceKHR, surface_loader: ash::khr::surface::Instance) -> (u32, u32) {
-    let queue_families = unsafe {instance.get_physical_device_queue_family_properties(device)};
+pub fn find_graphics_queue_family(instance: &Instance, device: vk::PhysicalDevice) -> Option<u32> {
+    let queue_families = unsafe { instance.get_physical_device_queue_family_properties(device) };
+    queue_families.iter()
+        .position(|family| family.queue_flags.contains(vk::QueueFlags::GRAPHICS))
+        .map(|index| index as u32)
+}
 
-    let mut graphics_queue_index: Option<u32> = None;
-    let mut present_queue_index: Option<u32> = None;
+/// Queue family indices used by the engine. Transfer and compute fall back to the graphics family
+/// when the device has no dedicated one, and present is the graphics family whenever it can
+/// present (always the case for offscreen rendering).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct QueueFamilies {
+    pub graphics: u32,
+    pub present: u32,
+    pub transfer: u32,
+    pub compute: u32,
+}
 
-    for (index, queue_family) in queue_families.iter().enumerate() {
-        let index = index as u32; // Convert index to u32
-        // Check if the queue supports graphics
-        let supports_graphics = queue_family.queue_flags.contains(vk::QueueFlags::GRAPHICS);
+impl QueueFamilies {
+    /// Picks the families from `properties`. `can_present` is asked for each family index; pass
+    /// `|_| true` when there is no surface.
+    pub fn select(properties: &[vk::QueueFamilyProperties], mut can_present: impl FnMut(u32) -> bool) -> Option<QueueFamilies> {
+        let supports = |flags: vk::QueueFlags| {
+            properties.iter()
+                .enumerate()
+                .filter(move |(_, family)| family.queue_count > 0 && family.queue_flags.contains(flags))
+                .map(|(index, family)| (index as u32, family.queue_flags))
+        };
+        let present_support: Vec<bool> = (0..properties.len() as u32).map(&mut can_present).collect();
 
-        // Check if the queue supports presenting to the window
-        let supports_presentation = unsafe {
-            surface_loader
-                .get_physical_device_surface_support(device, index as u32, surface)
-                .unwrap()
+        // Presenting from the graphics family avoids an ownership transfer of every swapchain image
+        let graphics = supports(vk::QueueFlags::GRAPHICS)
+            .map(|(index, _)| index)
+            .find(|&index| present_support[index as usize])
+            .or_else(|| supports(vk::QueueFlags::GRAPHICS).map(|(index, _)| index).next())?;
+        let present = if present_support[graphics as usize] {
+            graphics
+        } else {
+            present_support.iter().position(|&supported| supported)? as u32
         };
-        if supports_graphics {
-            graphics_queue_index = Some(index as u32);
+
+        // Prefer a transfer-only family (usually a DMA engine), then anything without graphics
+        let transfer = supports(vk::QueueFlags::TRANSFER)
+            .find(|(_, flags)| !flags.intersects(vk::QueueFlags::GRAPHICS | vk::QueueFlags::COMPUTE))
+            .or_else(|| supports(vk::QueueFlags::TRANSFER).find(|(_, flags)| !flags.contains(vk::QueueFlags::GRAPHICS)))
+            .map_or(graphics, |(index, _)| index);
+        let compute = supports(vk::QueueFlags::COMPUTE)
+            .find(|(_, flags)| !flags.contains(vk::QueueFlags::GRAPHICS))
+            .map_or(graphics, |(index, _)| index);
+
+        Some(QueueFamilies { graphics, present, transfer, compute })
+    }
+
+    /// Every distinct family index, graphics first. One queue is created per entry.
+    pub fn unique(&self) -> Vec<u32> {
+        let mut indices = vec![self.graphics];
+        for index in [self.present, self.transfer, self.compute] {
+            if !indices.contains(&index) {
+                indices.push(index);
+            }
+        }
+        indices
+    }
+
+    pub fn has_dedicated_transfer(&self) -> bool {
+        self.transfer != self.graphics
+    }
+
+    pub fn has_async_compute(&self) -> bool {
+        self.compute != self.graphics
+    }
+}
+
+/// Finds the queue families for `device`. Pass the window surface to also find a family that can
+/// present to it; without one, `present` is the graphics family.
+pub fn find_queue_families(
+    instance: &Instance,
+    device: vk::PhysicalDevice,
+    surface: Option<(&ash::khr::surface::Instance, vk::SurfaceKHR)>,
+) -> EngineResult<QueueFamilies> {
+    let properties = unsafe { instance.get_physical_device_queue_family_properties(device) };
+
+    let mut present_error = None;
+    let queue_families = QueueFamilies::select(&properties, |index| match surface {
+        Some((surface_loader, surface)) => unsafe {
+            surface_loader
+                .get_physical_device_surface_support(device, index, surface)
+                .unwrap_or_else(|e| {
+                    present_error = Some(e);
+                    false
+                })
+        },
+        None => true,
+    });
+    if let Some(e) = present_error {
+        return Err(e).surface_context("query surface support");
+    }
+
+    queue_families.ok_or_else(|| EngineError::DeviceSelection("no suitable graphics and present queue found".to_string()))
+}
+
+/// The logical device together with one queue from each family it was created with. Queues that
+/// share a family are the same handle. Derefs to `ash::Device`.
+pub struct DeviceContext {
+    pub device: ash::Device,
+    pub queue_families: QueueFamilies,
+    pub graphics_queue: vk::Queue,
+    pub present_queue: vk::Queue,
+    pub transfer_queue: vk::Queue,
+    pub compute_queue: vk::Queue,
+    /// Optional features that were available and got enabled.
+    pub enabled_features: vk::PhysicalDeviceFeatures,
+    /// `dynamicRendering` and `synchronization2` are enabled, see `supports_dynamic_rendering`.
+    pub dynamic_rendering: bool,
+}
+
+impl DeviceContext {
+    /// Every object created from the device must already be destroyed.
+    pub fn destroy(&self) {
+        unsafe { self.device.destroy_device(None) };
+    }
+
+    pub fn name_queues(&self, debug_names: &DebugNames) {
+        debug_names.name(self.device.handle(), "Logical Device");
+        debug_names.name(self.graphics_queue, "Graphics Queue");
+        if self.present_queue != self.graphics_queue {
+            debug_names.name(self.present_queue, "Present Queue");
         }
-        if supports_presentation {
-            present_queue_index = Some(index as u32);
+        if self.queue_families.has_dedicated_transfer() {
+            debug_names.name(self.transfer_queue, "Transfer Queue");
         }
-        if graphics_queue_index.is_some() && present_queue_index.is_some() {
-            break;
+        if self.queue_families.has_async_compute() && self.compute_queue != self.transfer_queue {
+            debug_names.name(self.compute_queue, "Compute Queue");
         }
     }
+}
+
+impl std::ops::Deref for DeviceContext {
+    type Target = ash::Device;
 
-    (
-        graphics_queue_index.expect("No suitable graphics queue found!"),
-        present_queue_index.expect("No suitable present queue found!")
-    )
+    fn deref(&self) -> &ash::Device {
+        &self.device
+    }
 }
 
-pub fn create_logical_device(instance: &ash::Instance, physical_device: vk::PhysicalDevice, queue_family: (u32, u32)) -> (ash::Device, vk::Queue, Option<vk::Queue>) {
-    let (graphics_queue_index, present_queue_index) = queue_family;
-    let use_single_queue = graphics_queue_index == present_queue_index;
-    let mut unique_indices = vec![graphics_queue_index];
-    if !use_single_queue {unique_indices.push(present_queue_index)};
+/// Whether the device is Vulkan 1.3 with the `dynamicRendering` and `synchronization2` features,
+/// which the dynamic path needs together.
+pub fn supports_dynamic_rendering(instance: &Instance, physical_device: vk::PhysicalDevice) -> bool {
+    let properties = unsafe { instance.get_physical_device_properties(physical_device) };
+    if properties.api_version < vk::API_VERSION_1_3 {
+        return false;
+    }
+    let mut vulkan_13_features = vk::PhysicalDeviceVulkan13Features::default();
+    let mut features = vk::PhysicalDeviceFeatures2::default().push_next(&mut vulkan_13_features);
+    unsafe { instance.get_physical_device_features2(physical_device, &mut features) };
+    vulkan_13_features.dynamic_rendering == vk::TRUE && vulkan_13_features.synchronization2 == vk::TRUE
+}
 
-    let required_device_extensions = vec![ // specific extensions I'll need to run this program
-        ash::khr::swapchain::NAME.as_ptr()
-    ];
+pub fn create_logical_device(
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+    queue_families: QueueFamilies,
+    required_device_extensions: &[*const c_char],
+) -> EngineResult<DeviceContext> {
+    let unique_indices = queue_families.unique();
 
     // let available_extensions = unsafe {instance.enumerate_device_extension_properties(physical_device).expect("Failed to get device extensions")};
     // println!("Available Extentions from Physical device: "); // Debug
@@ -95,7 +440,7 @@ pub fn create_logical_device(instance: &ash::Instance, physical_device: vk::Phys
     //     println!("- {}", ext_name.to_string_lossy());
     // }
 
-    let queue_priorities = vec![1.0_f32];
+    let queue_priorities = [1.0_f32];
     let queue_create_infos: Vec<_> = unique_indices.iter().map(|&queue_index| {
         vk::DeviceQueueCreateInfo {
             s_type: vk::StructureType::DEVICE_QUEUE_CREATE_INFO,
@@ -107,10 +452,27 @@ pub fn create_logical_device(instance: &ash::Instance, physical_device: vk::Phys
         }
     }).collect();
 
-    let physical_device_features = vk::PhysicalDeviceFeatures::default();
+    let supported_features = unsafe { instance.get_physical_device_features(physical_device) };
+    let physical_device_features = vk::PhysicalDeviceFeatures {
+        sampler_anisotropy: supported_features.sampler_anisotropy,
+        ..Default::default()
+    };
+
+    let dynamic_rendering = supports_dynamic_rendering(instance, physical_device);
+    let vulkan_13_features = vk::PhysicalDeviceVulkan13Features {
+        s_type: vk::StructureType::PHYSICAL_DEVICE_VULKAN_1_3_FEATURES,
+        dynamic_rendering: vk::TRUE,
+        synchronization2: vk::TRUE,
+        ..Default::default()
+    };
 
     let device_create_info = vk::DeviceCreateInfo {
         s_type: vk::StructureType::DEVICE_CREATE_INFO,
+        p_next: if dynamic_rendering {
+            &vulkan_13_features as *const _ as *const std::ffi::c_void
+        } else {
+            std::ptr::null()
+        },
         queue_create_info_count: queue_create_infos.len() as u32,
         p_queue_create_infos: queue_create_infos.as_ptr(),
         enabled_extension_count: required_device_extensions.len() as u32,
@@ -122,17 +484,33 @@ pub fn create_logical_device(instance: &ash::Instance, physical_device: vk::Phys
     let logical_device: ash::Device = unsafe {
         instance
             .create_device(physical_device, &device_create_info, None)
-            .expect("Failed to create logical Device!")
+            .context("create logical device")?
     };
 
-    let graphics_queue = unsafe { logical_device.get_device_queue(graphics_queue_index, 0) };
-    let present_queue = if use_single_queue {
-        None // Same queue handles both
-    } else {
-        Some(unsafe { logical_device.get_device_queue(present_queue_index, 0) })
+    let queue = |family| unsafe { logical_device.get_device_queue(family, 0) };
+    let (graphics_queue, present_queue, transfer_queue, compute_queue) = (
+        queue(queue_families.graphics),
+        queue(queue_families.present),
+        queue(queue_families.transfer),
+        queue(queue_families.compute),
+    );
+    let device_context = DeviceContext {
+        device: logical_device,
+        queue_families,
+        graphics_queue,
+        present_queue,
+        transfer_queue,
+        compute_queue,
+        enabled_features: physical_device_features,
+        dynamic_rendering,
     };
-    println!("Graphics queue: {:?}", graphics_queue);
-    println!("Present queue: {:?}", present_queue);
+    println!("Queue families: {:?}", queue_families);
+    println!("Graphics queue: {:?}, present queue: {:?}, transfer queue: {:?}, compute queue: {:?}",
+        device_context.graphics_queue,
+        device_context.present_queue,
+        device_context.transfer_queue,
+        device_context.compute_queue,
+    );
 
-    (logical_device, graphics_queue, present_queue)
+    Ok(device_context)
 }
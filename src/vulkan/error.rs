@@ -0,0 +1,78 @@
+use std::path::PathBuf;
+
+use ash::vk;
+use thiserror::Error;
+
+pub type EngineResult<T> = Result<T, EngineError>;
+
+/// Everything that can go wrong while setting up or driving the Vulkan renderer.
+#[derive(Debug, Error)]
+pub enum EngineError {
+    #[error("Failed to load the Vulkan library: {0}")]
+    Loading(#[from] ash::LoadingError),
+    #[error("Failed to create Vulkan instance: {0}")]
+    Instance(vk::Result),
+    #[error("No suitable GPU found: {0}")]
+    DeviceSelection(String),
+    #[error("Surface error while trying to {context}: {result}")]
+    Surface { context: &'static str, result: vk::Result },
+    #[error("Swapchain error while trying to {context}: {result}")]
+    Swapchain { context: &'static str, result: vk::Result },
+    #[error("Failed to load shader {path:?}: {reason}")]
+    ShaderLoading { path: PathBuf, reason: String },
+    #[error("Shader interface mismatch: {0}")]
+    ShaderInterface(String),
+    #[error("Failed to load texture {path:?}: {reason}")]
+    TextureLoading { path: PathBuf, reason: String },
+    #[error("Invalid upload: {0}")]
+    Upload(String),
+    #[error("Out of memory while trying to {context}: {result}")]
+    Allocation { context: &'static str, result: vk::Result },
+    #[error("GPU memory allocator error: {0}")]
+    Memory(#[from] gpu_allocator::AllocationError),
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("The Vulkan device was lost")]
+    DeviceLost,
+    #[error("Vulkan call failed while trying to {context}: {result}")]
+    Vulkan { context: &'static str, result: vk::Result },
+}
+
+impl EngineError {
+    /// Device loss and out-of-memory results get their own variants regardless of the call site,
+    /// so callers can react to them without matching on raw `vk::Result`s.
+    fn classify(context: &'static str, result: vk::Result, otherwise: fn(&'static str, vk::Result) -> EngineError) -> EngineError {
+        match result {
+            vk::Result::ERROR_DEVICE_LOST => EngineError::DeviceLost,
+            vk::Result::ERROR_OUT_OF_HOST_MEMORY | vk::Result::ERROR_OUT_OF_DEVICE_MEMORY => {
+                EngineError::Allocation { context, result }
+            }
+            _ => otherwise(context, result),
+        }
+    }
+
+    pub fn is_device_lost(&self) -> bool {
+        matches!(self, EngineError::DeviceLost)
+    }
+}
+
+/// Attaches what we were doing to a failed Vulkan call. `context` reads as "while trying to ...".
+pub trait VkResultExt<T> {
+    fn context(self, context: &'static str) -> EngineResult<T>;
+    fn surface_context(self, context: &'static str) -> EngineResult<T>;
+    fn swapchain_context(self, context: &'static str) -> EngineResult<T>;
+}
+
+impl<T> VkResultExt<T> for Result<T, vk::Result> {
+    fn context(self, context: &'static str) -> EngineResult<T> {
+        self.map_err(|result| EngineError::classify(context, result, |context, result| EngineError::Vulkan { context, result }))
+    }
+
+    fn surface_context(self, context: &'static str) -> EngineResult<T> {
+        self.map_err(|result| EngineError::classify(context, result, |context, result| EngineError::Surface { context, result }))
+    }
+
+    fn swapchain_context(self, context: &'static str) -> EngineResult<T> {
+        self.map_err(|result| EngineError::classify(context, result, |context, result| EngineError::Swapchain { context, result }))
+    }
+}
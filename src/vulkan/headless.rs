@@ -0,0 +1,419 @@
+use std::{fs::File, io::BufWriter, path::Path};
+
+use ash::{vk, Entry, Instance};
+
+use crate::camera::Y_FLIP;
+use crate::vulkan::debug::*;
+use crate::vulkan::deletion_queue::*;
+use crate::vulkan::depth::*;
+use crate::vulkan::descriptors::*;
+use crate::vulkan::device::*;
+use crate::vulkan::error::*;
+use crate::vulkan::memory::*;
+use crate::vulkan::mesh::*;
+use crate::vulkan::other::*;
+use crate::vulkan::pipeline::*;
+use crate::vulkan::renderer::*;
+use crate::vulkan::rendering::*;
+use crate::vulkan::upload::*;
+
+/// Offscreen color format; RGBA so the readback can be handed out without swizzling.
+pub const HEADLESS_COLOR_FORMAT: vk::Format = vk::Format::R8G8B8A8_UNORM;
+
+/// Renders the same pass and graphics pipeline as the windowed path into an offscreen
+/// image and copies the result back to host memory. Needs no window, surface or swapchain, so it
+/// runs under a software driver such as lavapipe on a GPU-less machine.
+pub struct HeadlessRenderer {
+    _entry: Entry,
+    instance: Instance,
+    debug_messenger: Option<DebugMessenger>,
+    pub physical_device: vk::PhysicalDevice,
+    pub device: DeviceContext,
+    extent: vk::Extent2D,
+
+    /// Taken in `Drop` before the device is destroyed, along with everything allocated from it.
+    allocator: Option<MemoryAllocator>,
+    uploads: Option<UploadManager>,
+    subsystems: Vec<Box<dyn RenderSubsystem>>,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    descriptor_allocator: DescriptorAllocator,
+    frame_uniforms: FrameUniforms,
+    color_image: Option<Image>,
+    color_image_view: vk::ImageView,
+    depth_format: vk::Format,
+    depth_image: Option<Image>,
+    depth_image_view: vk::ImageView,
+    readback_buffer: Option<Buffer>,
+
+    render_path: RenderPath,
+    render_pass: vk::RenderPass,
+    pipeline_layout: vk::PipelineLayout,
+    graphics_pipeline: vk::Pipeline,
+    framebuffer: vk::Framebuffer,
+    commands: FrameCommands,
+    fence: vk::Fence,
+    deletion_queue: DeletionQueue,
+}
+
+impl HeadlessRenderer {
+    /// Returns an error instead of panicking when Vulkan or a usable device is missing, so callers
+    /// such as tests can skip on machines without a driver.
+    pub fn new(width: u32, height: u32) -> EngineResult<Self> {
+        let entry = unsafe { Entry::load()? };
+
+        let (instance, debug_messenger) = create_instance(&entry, "Sage Zinnia (Headless)", vec![])?;
+
+        let device = pick_physical_device(&instance, None, None).and_then(|physical_device| {
+            let queue_families = find_queue_families(&instance, physical_device, None)?;
+            let device = create_logical_device(&instance, physical_device, queue_families, &[])?;
+            Ok((physical_device, device))
+        });
+        let (physical_device, device) = match device {
+            Ok(device) => device,
+            Err(e) => {
+                if let Some(debug_messenger) = debug_messenger {
+                    debug_messenger.destroy();
+                }
+                unsafe { instance.destroy_instance(None) };
+                return Err(e);
+            }
+        };
+
+        // From here on `Drop` flushes whatever was queued if a later step fails
+        let mut renderer = HeadlessRenderer {
+            _entry: entry,
+            instance,
+            debug_messenger,
+            physical_device,
+            device,
+            extent: vk::Extent2D { width, height },
+            allocator: None,
+            uploads: None,
+            subsystems: Vec::new(),
+            descriptor_set_layout: vk::DescriptorSetLayout::null(),
+            descriptor_allocator: DescriptorAllocator::default(),
+            frame_uniforms: FrameUniforms::default(),
+            color_image: None,
+            color_image_view: vk::ImageView::null(),
+            depth_format: vk::Format::UNDEFINED,
+            depth_image: None,
+            depth_image_view: vk::ImageView::null(),
+            readback_buffer: None,
+            render_path: RenderPath::default(),
+            render_pass: vk::RenderPass::null(),
+            pipeline_layout: vk::PipelineLayout::null(),
+            graphics_pipeline: vk::Pipeline::null(),
+            framebuffer: vk::Framebuffer::null(),
+            commands: FrameCommands::default(),
+            fence: vk::Fence::null(),
+            deletion_queue: DeletionQueue::default(),
+        };
+        renderer.create_resources()?;
+
+        Ok(renderer)
+    }
+
+    fn create_resources(&mut self) -> EngineResult<()> {
+        let device = &self.device;
+        let extent = self.extent;
+        let allocator = self.allocator.insert(MemoryAllocator::new(&self.instance, device, self.physical_device)?);
+
+        let color_image = self.color_image.insert(allocator.create_image(
+            "Headless Color Image",
+            ImageDesc::new(
+                HEADLESS_COLOR_FORMAT,
+                extent,
+                vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC,
+            ),
+            MemoryUsage::GpuOnly,
+        )?);
+        self.color_image_view = create_image_views(device, HEADLESS_COLOR_FORMAT, &[color_image.handle()])?[0];
+        self.deletion_queue.push(self.color_image_view);
+
+        self.depth_format = find_depth_format(&self.instance, self.physical_device)?;
+        let depth_image = self.depth_image.insert(create_depth_image(
+            allocator,
+            "Headless Depth Image",
+            self.depth_format,
+            extent,
+            vk::SampleCountFlags::TYPE_1,
+        )?);
+        self.depth_image_view = create_image_view(
+            device,
+            depth_image.handle(),
+            self.depth_format,
+            depth_aspect_mask(self.depth_format),
+        )?;
+        self.deletion_queue.push(self.depth_image_view);
+
+        let readback_buffer = self.readback_buffer.insert(allocator.create_buffer(
+            "Headless Readback Buffer",
+            (extent.width * extent.height * 4) as vk::DeviceSize,
+            vk::BufferUsageFlags::TRANSFER_DST,
+            MemoryUsage::GpuToCpu,
+        )?);
+
+        let uploads = self.uploads.insert(UploadManager::new(device, allocator, STAGING_RING_SIZE)?);
+        let quad = Mesh::new(allocator, uploads, "Quad", &QUAD_VERTICES, &QUAD_INDICES)?;
+        self.subsystems.push(Box::new(StaticScene { meshes: vec![quad] }));
+        uploads.flush_and_wait()?;
+
+        self.descriptor_set_layout = create_scene_descriptor_set_layout(device)?;
+        self.deletion_queue.push(self.descriptor_set_layout);
+        self.descriptor_allocator = DescriptorAllocator::new(
+            &[PoolSizeRatio { descriptor_type: vk::DescriptorType::UNIFORM_BUFFER, ratio: 1.0 }],
+            1,
+        );
+        self.frame_uniforms = FrameUniforms::new(
+            device,
+            allocator,
+            &mut self.descriptor_allocator,
+            self.descriptor_set_layout,
+            1,
+        )?;
+        self.frame_uniforms.update(0, &UniformBufferObject { proj: Y_FLIP, ..Default::default() });
+
+        self.render_path = RenderPath::from_env(device.dynamic_rendering);
+        let color_formats = [HEADLESS_COLOR_FORMAT];
+        let target = match self.render_path {
+            RenderPath::RenderPass => {
+                self.render_pass = create_render_pass(
+                    device,
+                    HEADLESS_COLOR_FORMAT,
+                    self.depth_format,
+                    vk::SampleCountFlags::TYPE_1,
+                    vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                )?;
+                self.deletion_queue.push(self.render_pass);
+                PipelineTarget::RenderPass { render_pass: self.render_pass, subpass: 0 }
+            }
+            RenderPath::Dynamic => PipelineTarget::Dynamic {
+                color_formats: &color_formats,
+                depth_format: self.depth_format,
+            },
+        };
+        let (graphics_pipeline, pipeline_layout) = create_graphics_pipeline(
+            device,
+            vk::PipelineCache::null(),
+            target,
+            &[self.descriptor_set_layout],
+            vk::SampleCountFlags::TYPE_1,
+        )?;
+        self.graphics_pipeline = graphics_pipeline;
+        self.pipeline_layout = pipeline_layout;
+        self.deletion_queue.push(self.pipeline_layout);
+        self.deletion_queue.push(self.graphics_pipeline);
+        if self.render_path == RenderPath::RenderPass {
+            self.framebuffer = create_framebuffers(device, self.render_pass, &[self.color_image_view], self.depth_image_view, None, &extent)?[0];
+            self.deletion_queue.push(self.framebuffer);
+        }
+
+        self.commands = FrameCommands::create(device, device.queue_families.graphics, 1)?[0];
+        self.deletion_queue.push(self.commands.command_pool);
+
+        let fence_create_info = vk::FenceCreateInfo {
+            s_type: vk::StructureType::FENCE_CREATE_INFO,
+            ..Default::default()
+        };
+        self.fence = unsafe {
+            device
+                .create_fence(&fence_create_info, None)
+                .context("create fence")?
+        };
+        self.deletion_queue.push(self.fence);
+
+        let debug_names = DebugNames::new(&self.instance, device, self.debug_messenger.is_some());
+        debug_names.name(color_image.handle(), "Headless Color Image");
+        debug_names.name(self.color_image_view, "Headless Color Image View");
+        debug_names.name(depth_image.handle(), "Headless Depth Image");
+        debug_names.name(self.depth_image_view, "Headless Depth Image View");
+        debug_names.name(readback_buffer.handle(), "Headless Readback Buffer");
+        debug_names.name(self.descriptor_set_layout, "Scene Descriptor Set Layout");
+        debug_names.name_all(self.frame_uniforms.sets(), "Camera Descriptor Set");
+        if self.render_path == RenderPath::RenderPass {
+            debug_names.name(self.render_pass, "Headless Render Pass");
+            debug_names.name(self.framebuffer, "Headless Framebuffer");
+        }
+        debug_names.name(self.pipeline_layout, "Triangle Pipeline Layout");
+        debug_names.name(self.graphics_pipeline, "Triangle Pipeline");
+        debug_names.name(self.commands.command_pool, "Headless Command Pool");
+        debug_names.name(self.commands.command_buffer, "Headless Command Buffer");
+        debug_names.name(self.fence, "Headless Fence");
+
+        Ok(())
+    }
+
+    /// Whether `VK_LAYER_KHRONOS_validation` is active for this renderer.
+    pub fn validation_enabled(&self) -> bool {
+        self.debug_messenger.is_some()
+    }
+
+    pub fn extent(&self) -> vk::Extent2D {
+        self.extent
+    }
+
+    /// Matrices used by the next `render`. Until set, clip space is world space with Y pointing up,
+    /// like with any `Camera`.
+    pub fn set_uniforms(&mut self, ubo: &UniformBufferObject) {
+        self.frame_uniforms.update(0, ubo);
+    }
+
+    /// Renders one frame and returns its pixels as tightly packed RGBA8 rows, top row first.
+    pub fn render(&mut self) -> EngineResult<Vec<u8>> {
+        let device = &self.device;
+        let color_image = self.color_image.as_ref().unwrap().handle();
+        let readback_buffer = self.readback_buffer.as_ref().unwrap().handle();
+
+        let target = match self.render_path {
+            RenderPath::RenderPass => SceneTarget::RenderPass {
+                render_pass: self.render_pass,
+                framebuffer: self.framebuffer,
+            },
+            RenderPath::Dynamic => SceneTarget::Dynamic(RenderingAttachments {
+                target: color_image,
+                target_view: self.color_image_view,
+                msaa_color: None,
+                depth: self.depth_image.as_ref().unwrap().handle(),
+                depth_view: self.depth_image_view,
+                depth_format: self.depth_format,
+                final_layout: vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            }),
+        };
+        let pass = ScenePass {
+            target,
+            pipeline: self.graphics_pipeline,
+            pipeline_layout: self.pipeline_layout,
+            descriptor_set: self.frame_uniforms.sets()[0],
+            extent: self.extent,
+        };
+        let command_buffer = self.commands.begin(device)?;
+        record_scene(device, command_buffer, &pass, 0, 0.0, &mut self.subsystems);
+
+        // The pass already left the image in TRANSFER_SRC_OPTIMAL; make its writes visible
+        // to the copy, then make the copy visible to the host.
+        let attachment_to_transfer = vk::MemoryBarrier {
+            s_type: vk::StructureType::MEMORY_BARRIER,
+            src_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            dst_access_mask: vk::AccessFlags::TRANSFER_READ,
+            ..Default::default()
+        };
+        let transfer_to_host = vk::MemoryBarrier {
+            s_type: vk::StructureType::MEMORY_BARRIER,
+            src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+            dst_access_mask: vk::AccessFlags::HOST_READ,
+            ..Default::default()
+        };
+        let copy_region = vk::BufferImageCopy {
+            buffer_offset: 0,
+            buffer_row_length: 0,
+            buffer_image_height: 0,
+            image_subresource: vk::ImageSubresourceLayers {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                mip_level: 0,
+                base_array_layer: 0,
+                layer_count: 1,
+            },
+            image_offset: vk::Offset3D { x: 0, y: 0, z: 0 },
+            image_extent: vk::Extent3D {
+                width: self.extent.width,
+                height: self.extent.height,
+                depth: 1,
+            },
+        };
+
+        unsafe {
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[attachment_to_transfer],
+                &[],
+                &[],
+            );
+            device.cmd_copy_image_to_buffer(
+                command_buffer,
+                color_image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                readback_buffer,
+                &[copy_region],
+            );
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::HOST,
+                vk::DependencyFlags::empty(),
+                &[transfer_to_host],
+                &[],
+                &[],
+            );
+        }
+        self.commands.end(device)?;
+
+        let command_buffers = [command_buffer];
+        let submit_info = vk::SubmitInfo {
+            s_type: vk::StructureType::SUBMIT_INFO,
+            command_buffer_count: 1,
+            p_command_buffers: command_buffers.as_ptr(),
+            ..Default::default()
+        };
+
+        let size = (self.extent.width * self.extent.height * 4) as usize;
+        unsafe {
+            device
+                .queue_submit(device.graphics_queue, &[submit_info], self.fence)
+                .context("submit offscreen command buffer")?;
+            device
+                .wait_for_fences(&[self.fence], true, u64::MAX)
+                .context("wait for offscreen fence")?;
+            device.reset_fences(&[self.fence]).context("reset offscreen fence")?;
+        }
+
+        // GPU-to-CPU memory is always host coherent, so the mapping can be read directly
+        let mapped = self.readback_buffer.as_ref().unwrap().mapped_slice().unwrap();
+        Ok(mapped[..size].to_vec())
+    }
+
+    /// Renders one frame and writes it to `path` as an RGBA PNG.
+    pub fn render_to_png(&mut self, path: &Path) -> EngineResult<()> {
+        let pixels = self.render()?;
+        write_png(path, self.extent.width, self.extent.height, &pixels)?;
+        Ok(())
+    }
+}
+
+impl Drop for HeadlessRenderer {
+    fn drop(&mut self) {
+        unsafe {
+            let device = &self.device;
+            if let Err(e) = device.device_wait_idle() {
+                log::error!("Failed to wait for device idle on shutdown: {}", e);
+            }
+            self.deletion_queue.flush(device);
+            self.color_image = None;
+            self.depth_image = None;
+            self.readback_buffer = None;
+            self.descriptor_allocator.destroy(device);
+            self.frame_uniforms = FrameUniforms::default();
+            self.subsystems.clear();
+            self.uploads = None;
+            self.allocator = None;
+            device.destroy();
+            if let Some(debug_messenger) = self.debug_messenger.take() {
+                debug_messenger.destroy();
+            }
+            self.instance.destroy_instance(None);
+        }
+    }
+}
+
+/// Writes tightly packed RGBA8 pixels to `path` as a PNG.
+pub fn write_png(path: &Path, width: u32, height: u32, pixels: &[u8]) -> std::io::Result<()> {
+    let file = File::create(path)?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(pixels)?;
+    Ok(())
+}
@@ -0,0 +1,307 @@
+use std::ptr;
+use std::sync::{Arc, Mutex};
+
+use ash::{vk, Instance};
+use gpu_allocator::vulkan::{Allocation, AllocationCreateDesc, AllocationScheme, Allocator, AllocatorCreateDesc};
+use gpu_allocator::{AllocationSizes, AllocatorDebugSettings, AllocatorReport, MemoryLocation};
+
+use crate::vulkan::error::*;
+
+/// Plain data that can be copied into GPU memory byte for byte.
+///
+/// # Safety
+///
+/// Every byte of a value must be initialized: no padding between or after fields, and no
+/// pointers, references or other types with invalid bit patterns.
+pub unsafe trait Pod: Copy {}
+
+unsafe impl Pod for u8 {}
+unsafe impl Pod for u16 {}
+unsafe impl Pod for u32 {}
+unsafe impl Pod for f32 {}
+unsafe impl<T: Pod, const N: usize> Pod for [T; N] {}
+
+/// The bytes of `data`, for copying it into a buffer.
+pub fn as_bytes<T: Pod>(data: &[T]) -> &[u8] {
+    // SAFETY: `Pod` types have no uninitialized bytes
+    unsafe { std::slice::from_raw_parts(data.as_ptr() as *const u8, std::mem::size_of_val(data)) }
+}
+
+/// Where a buffer or image lives and who gets to touch it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryUsage {
+    /// Device-local and never mapped: vertex/index buffers, textures, depth images.
+    GpuOnly,
+    /// Host-visible and persistently mapped for writing: staging and uniform buffers.
+    CpuToGpu,
+    /// Host-visible and cached for reading back results.
+    GpuToCpu,
+}
+
+impl MemoryUsage {
+    fn location(self) -> MemoryLocation {
+        match self {
+            MemoryUsage::GpuOnly => MemoryLocation::GpuOnly,
+            MemoryUsage::CpuToGpu => MemoryLocation::CpuToGpu,
+            MemoryUsage::GpuToCpu => MemoryLocation::GpuToCpu,
+        }
+    }
+}
+
+/// Shared handle to the engine's `gpu_allocator` instance. Every `Buffer` and `Image` keeps a
+/// clone and frees itself on drop, so all of them must be dropped before the device is destroyed.
+#[derive(Clone)]
+pub struct MemoryAllocator {
+    device: ash::Device,
+    allocator: Arc<Mutex<Allocator>>,
+}
+
+impl MemoryAllocator {
+    pub fn new(instance: &Instance, device: &ash::Device, physical_device: vk::PhysicalDevice) -> EngineResult<MemoryAllocator> {
+        let allocator = Allocator::new(&AllocatorCreateDesc {
+            instance: instance.clone(),
+            device: device.clone(),
+            physical_device,
+            debug_settings: AllocatorDebugSettings::default(),
+            buffer_device_address: false,
+            allocation_sizes: AllocationSizes::default(),
+        })?;
+
+        Ok(MemoryAllocator {
+            device: device.clone(),
+            allocator: Arc::new(Mutex::new(allocator)),
+        })
+    }
+
+    fn allocate(&self, name: &str, requirements: vk::MemoryRequirements, usage: MemoryUsage, linear: bool) -> EngineResult<Allocation> {
+        let allocation = self.allocator.lock().unwrap().allocate(&AllocationCreateDesc {
+            name,
+            requirements,
+            location: usage.location(),
+            linear,
+            allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+        })?;
+        Ok(allocation)
+    }
+
+    fn free(&self, allocation: Allocation) {
+        if let Err(e) = self.allocator.lock().unwrap().free(allocation) {
+            log::error!("Failed to free GPU allocation: {}", e);
+        }
+    }
+
+    pub fn create_buffer(&self, name: &str, size: vk::DeviceSize, usage: vk::BufferUsageFlags, memory_usage: MemoryUsage) -> EngineResult<Buffer> {
+        let buffer_create_info = vk::BufferCreateInfo {
+            s_type: vk::StructureType::BUFFER_CREATE_INFO,
+            size,
+            usage,
+            sharing_mode: vk::SharingMode::EXCLUSIVE,
+            ..Default::default()
+        };
+
+        let device = &self.device;
+        let handle = unsafe { device.create_buffer(&buffer_create_info, None).context("create buffer")? };
+        let requirements = unsafe { device.get_buffer_memory_requirements(handle) };
+        let allocation = self.allocate(name, requirements, memory_usage, true).and_then(|allocation| {
+            match unsafe { device.bind_buffer_memory(handle, allocation.memory(), allocation.offset()) } {
+                Ok(()) => Ok(allocation),
+                Err(e) => {
+                    self.free(allocation);
+                    Err(e).context("bind buffer memory")
+                }
+            }
+        });
+        let allocation = match allocation {
+            Ok(allocation) => allocation,
+            Err(e) => {
+                unsafe { device.destroy_buffer(handle, None) };
+                return Err(e);
+            }
+        };
+
+        Ok(Buffer {
+            handle,
+            size,
+            allocation: Some(allocation),
+            allocator: self.clone(),
+        })
+    }
+
+    /// Creates a 2D image with optimal tiling. Array layers and mip levels come from the caller so
+    /// texture arrays and mipmapped textures go through here as well.
+    pub fn create_image(
+        &self,
+        name: &str,
+        desc: ImageDesc,
+        memory_usage: MemoryUsage,
+    ) -> EngineResult<Image> {
+        let image_create_info = vk::ImageCreateInfo {
+            s_type: vk::StructureType::IMAGE_CREATE_INFO,
+            p_next: ptr::null(),
+            flags: vk::ImageCreateFlags::empty(),
+            image_type: vk::ImageType::TYPE_2D,
+            format: desc.format,
+            extent: vk::Extent3D {
+                width: desc.extent.width,
+                height: desc.extent.height,
+                depth: 1,
+            },
+            mip_levels: desc.mip_levels,
+            array_layers: desc.array_layers,
+            samples: desc.samples,
+            tiling: vk::ImageTiling::OPTIMAL,
+            usage: desc.usage,
+            sharing_mode: vk::SharingMode::EXCLUSIVE,
+            initial_layout: vk::ImageLayout::UNDEFINED,
+            ..Default::default()
+        };
+
+        let device = &self.device;
+        let handle = unsafe { device.create_image(&image_create_info, None).context("create image")? };
+        let requirements = unsafe { device.get_image_memory_requirements(handle) };
+        let allocation = self.allocate(name, requirements, memory_usage, false).and_then(|allocation| {
+            match unsafe { device.bind_image_memory(handle, allocation.memory(), allocation.offset()) } {
+                Ok(()) => Ok(allocation),
+                Err(e) => {
+                    self.free(allocation);
+                    Err(e).context("bind image memory")
+                }
+            }
+        });
+        let allocation = match allocation {
+            Ok(allocation) => allocation,
+            Err(e) => {
+                unsafe { device.destroy_image(handle, None) };
+                return Err(e);
+            }
+        };
+
+        Ok(Image {
+            handle,
+            desc,
+            allocation: Some(allocation),
+            allocator: self.clone(),
+        })
+    }
+
+    pub fn report(&self) -> AllocatorReport {
+        self.allocator.lock().unwrap().generate_report()
+    }
+
+    /// Prints totals followed by every live allocation, largest first.
+    pub fn print_report(&self) {
+        let report = self.report();
+        println!(
+            "GPU memory: {} allocations, {} bytes allocated in {} blocks ({} bytes reserved)",
+            report.allocations.len(),
+            report.total_allocated_bytes,
+            report.blocks.len(),
+            report.total_reserved_bytes,
+        );
+        println!("{:?}", report);
+    }
+}
+
+/// A buffer together with the memory backing it. Freed on drop.
+pub struct Buffer {
+    handle: vk::Buffer,
+    size: vk::DeviceSize,
+    allocation: Option<Allocation>,
+    allocator: MemoryAllocator,
+}
+
+impl Buffer {
+    pub fn handle(&self) -> vk::Buffer {
+        self.handle
+    }
+
+    pub fn size(&self) -> vk::DeviceSize {
+        self.size
+    }
+
+    /// The mapped contents; `None` for `MemoryUsage::GpuOnly` buffers.
+    pub fn mapped_slice(&self) -> Option<&[u8]> {
+        self.allocation.as_ref()?.mapped_slice()
+    }
+
+    pub fn mapped_slice_mut(&mut self) -> Option<&mut [u8]> {
+        self.allocation.as_mut()?.mapped_slice_mut()
+    }
+
+    /// Copies `data` into a host-visible buffer at `offset`. Panics if the buffer isn't mapped or
+    /// the data doesn't fit.
+    pub fn write<T: Pod>(&mut self, offset: usize, data: &[T]) {
+        let bytes = as_bytes(data);
+        let mapped = self.mapped_slice_mut().expect("buffer is not host visible");
+        mapped[offset..offset + bytes.len()].copy_from_slice(bytes);
+    }
+}
+
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        unsafe { self.allocator.device.destroy_buffer(self.handle, None) };
+        if let Some(allocation) = self.allocation.take() {
+            self.allocator.free(allocation);
+        }
+    }
+}
+
+/// Everything `MemoryAllocator::create_image` needs besides a name and memory location.
+#[derive(Debug, Clone, Copy)]
+pub struct ImageDesc {
+    pub format: vk::Format,
+    pub extent: vk::Extent2D,
+    pub usage: vk::ImageUsageFlags,
+    pub mip_levels: u32,
+    pub array_layers: u32,
+    pub samples: vk::SampleCountFlags,
+}
+
+impl ImageDesc {
+    /// A single-sampled image with one mip level and one layer.
+    pub fn new(format: vk::Format, extent: vk::Extent2D, usage: vk::ImageUsageFlags) -> ImageDesc {
+        ImageDesc {
+            format,
+            extent,
+            usage,
+            mip_levels: 1,
+            array_layers: 1,
+            samples: vk::SampleCountFlags::TYPE_1,
+        }
+    }
+}
+
+/// An image together with the memory backing it. Views are owned by the caller. Freed on drop.
+pub struct Image {
+    handle: vk::Image,
+    desc: ImageDesc,
+    allocation: Option<Allocation>,
+    allocator: MemoryAllocator,
+}
+
+impl Image {
+    pub fn handle(&self) -> vk::Image {
+        self.handle
+    }
+
+    pub fn format(&self) -> vk::Format {
+        self.desc.format
+    }
+
+    pub fn extent(&self) -> vk::Extent2D {
+        self.desc.extent
+    }
+
+    pub fn desc(&self) -> &ImageDesc {
+        &self.desc
+    }
+}
+
+impl Drop for Image {
+    fn drop(&mut self) {
+        unsafe { self.allocator.device.destroy_image(self.handle, None) };
+        if let Some(allocation) = self.allocation.take() {
+            self.allocator.free(allocation);
+        }
+    }
+}
@@ -0,0 +1,139 @@
+use std::mem::{offset_of, size_of};
+
+use ash::vk;
+
+use crate::vulkan::error::*;
+use crate::vulkan::memory::*;
+use crate::vulkan::upload::*;
+
+/// One vertex as consumed by `shaders/glsl.vert`: `inPosition` at location 0 and `inColor` at
+/// location 1, interleaved in binding 0.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Vertex {
+    pub position: [f32; 3],
+    pub color: [f32; 3],
+}
+
+// SAFETY: six `f32`s in a `repr(C)` struct leave no padding
+unsafe impl Pod for Vertex {}
+
+impl Vertex {
+    pub const fn new(position: [f32; 3], color: [f32; 3]) -> Vertex {
+        Vertex { position, color }
+    }
+
+    pub fn binding_description() -> vk::VertexInputBindingDescription {
+        vk::VertexInputBindingDescription {
+            binding: 0,
+            stride: size_of::<Vertex>() as u32,
+            input_rate: vk::VertexInputRate::VERTEX,
+        }
+    }
+
+    pub fn attribute_descriptions() -> [vk::VertexInputAttributeDescription; 2] {
+        [
+            vk::VertexInputAttributeDescription {
+                location: 0,
+                binding: 0,
+                format: vk::Format::R32G32B32_SFLOAT,
+                offset: offset_of!(Vertex, position) as u32,
+            },
+            vk::VertexInputAttributeDescription {
+                location: 1,
+                binding: 0,
+                format: vk::Format::R32G32B32_SFLOAT,
+                offset: offset_of!(Vertex, color) as u32,
+            },
+        ]
+    }
+}
+
+/// Chunk meshes easily pass 65536 vertices, so indices are always 32 bit.
+pub type Index = u32;
+pub const INDEX_TYPE: vk::IndexType = vk::IndexType::UINT32;
+
+/// Placeholder scene until chunks are meshed: a quad with one color per corner.
+pub const QUAD_VERTICES: [Vertex; 4] = [
+    Vertex::new([-0.5, -0.5, 0.0], [1.0, 0.0, 0.0]),
+    Vertex::new([0.5, -0.5, 0.0], [0.0, 1.0, 0.0]),
+    Vertex::new([0.5, 0.5, 0.0], [0.0, 0.0, 1.0]),
+    Vertex::new([-0.5, 0.5, 0.0], [1.0, 1.0, 1.0]),
+];
+pub const QUAD_INDICES: [Index; 6] = [0, 1, 2, 2, 3, 0];
+
+/// Checks that there is something to put in a `Mesh`. Vulkan has no zero-sized buffers, so
+/// empty geometry (a chunk with no visible faces, say) has to be skipped rather than uploaded.
+pub fn check_mesh_data(name: &str, vertices: &[Vertex], indices: &[Index]) -> EngineResult<()> {
+    if vertices.is_empty() || indices.is_empty() {
+        return Err(EngineError::Upload(format!(
+            "mesh {:?} has {} vertices and {} indices, it needs at least one of each",
+            name, vertices.len(), indices.len()
+        )));
+    }
+    Ok(())
+}
+
+/// Device-local vertex and index buffers for one piece of geometry.
+pub struct Mesh {
+    vertex_buffer: Buffer,
+    index_buffer: Buffer,
+    index_count: u32,
+}
+
+impl Mesh {
+    /// Creates the buffers and queues their contents on `uploads`. The data is only there once the
+    /// upload ticket from the next `flush` has completed. Empty geometry is rejected, see
+    /// `check_mesh_data`.
+    pub fn new(
+        allocator: &MemoryAllocator,
+        uploads: &mut UploadManager,
+        name: &str,
+        vertices: &[Vertex],
+        indices: &[Index],
+    ) -> EngineResult<Mesh> {
+        check_mesh_data(name, vertices, indices)?;
+        let vertex_buffer = allocator.create_buffer(
+            &format!("{} Vertices", name),
+            size_of_val(vertices) as vk::DeviceSize,
+            vk::BufferUsageFlags::VERTEX_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+            MemoryUsage::GpuOnly,
+        )?;
+        let index_buffer = allocator.create_buffer(
+            &format!("{} Indices", name),
+            size_of_val(indices) as vk::DeviceSize,
+            vk::BufferUsageFlags::INDEX_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+            MemoryUsage::GpuOnly,
+        )?;
+        uploads.upload_buffer(&vertex_buffer, 0, vertices)?;
+        uploads.upload_buffer(&index_buffer, 0, indices)?;
+
+        Ok(Mesh {
+            vertex_buffer,
+            index_buffer,
+            index_count: indices.len() as u32,
+        })
+    }
+
+    pub fn vertex_buffer(&self) -> vk::Buffer {
+        self.vertex_buffer.handle()
+    }
+
+    pub fn index_buffer(&self) -> vk::Buffer {
+        self.index_buffer.handle()
+    }
+
+    pub fn index_count(&self) -> u32 {
+        self.index_count
+    }
+
+    /// Binds both buffers and draws every index once. Must be recorded inside a render pass with a
+    /// pipeline using `Vertex`'s input layout.
+    pub fn draw(&self, device: &ash::Device, command_buffer: vk::CommandBuffer) {
+        unsafe {
+            device.cmd_bind_vertex_buffers(command_buffer, 0, &[self.vertex_buffer.handle()], &[0]);
+            device.cmd_bind_index_buffer(command_buffer, self.index_buffer.handle(), 0, INDEX_TYPE);
+            device.cmd_draw_indexed(command_buffer, self.index_count, 1, 0, 0, 0);
+        }
+    }
+}
@@ -1,3 +1,20 @@
 pub mod device;
 pub mod swapchain;
-pub mod other;
\ No newline at end of file
+pub mod other;
+pub mod headless;
+pub mod debug;
+pub mod error;
+pub mod deletion_queue;
+pub mod memory;
+pub mod upload;
+pub mod mesh;
+pub mod descriptors;
+pub mod depth;
+pub mod msaa;
+pub mod texture;
+pub mod shaders;
+pub mod reflection;
+pub mod pipeline_cache;
+pub mod pipeline;
+pub mod rendering;
+pub mod renderer;
@@ -0,0 +1,65 @@
+use ash::{vk, Instance};
+
+use crate::vulkan::error::*;
+use crate::vulkan::memory::*;
+
+/// Overrides the requested MSAA sample count, e.g. `VOXEL_MSAA=4`.
+pub const MSAA_ENV: &str = "VOXEL_MSAA";
+
+/// Sample counts the MSAA setting can take, lowest first.
+pub const SAMPLE_COUNTS: [vk::SampleCountFlags; 4] = [
+    vk::SampleCountFlags::TYPE_1,
+    vk::SampleCountFlags::TYPE_2,
+    vk::SampleCountFlags::TYPE_4,
+    vk::SampleCountFlags::TYPE_8,
+];
+
+/// Sample counts usable for both the color and the depth attachment of a framebuffer.
+pub fn supported_sample_counts(instance: &Instance, physical_device: vk::PhysicalDevice) -> vk::SampleCountFlags {
+    let limits = unsafe { instance.get_physical_device_properties(physical_device).limits };
+    limits.framebuffer_color_sample_counts & limits.framebuffer_depth_sample_counts
+}
+
+/// The highest supported sample count that doesn't exceed `requested`. Anything below 2 turns
+/// MSAA off.
+pub fn clamp_sample_count(requested: u32, supported: vk::SampleCountFlags) -> vk::SampleCountFlags {
+    SAMPLE_COUNTS
+        .iter()
+        .rev()
+        .copied()
+        .find(|&samples| samples.as_raw() <= requested && supported.contains(samples))
+        .unwrap_or(vk::SampleCountFlags::TYPE_1)
+}
+
+/// The next supported sample count after `current`, wrapping around to single sampling.
+pub fn next_sample_count(current: vk::SampleCountFlags, supported: vk::SampleCountFlags) -> vk::SampleCountFlags {
+    SAMPLE_COUNTS
+        .iter()
+        .copied()
+        .find(|&samples| samples.as_raw() > current.as_raw() && supported.contains(samples))
+        .unwrap_or(vk::SampleCountFlags::TYPE_1)
+}
+
+/// The requested sample count from `MSAA_ENV`, if set to a number.
+pub fn msaa_from_env() -> Option<u32> {
+    std::env::var(MSAA_ENV).ok()?.trim().parse().ok()
+}
+
+/// The multisampled color target that gets resolved into the swapchain image. Never read after
+/// the pass, so it is marked as a transient attachment. It still takes regular device-local
+/// memory: `gpu_allocator` has no way to ask for a lazily allocated memory type.
+pub fn create_msaa_color_image(
+    allocator: &MemoryAllocator,
+    name: &str,
+    format: vk::Format,
+    extent: vk::Extent2D,
+    samples: vk::SampleCountFlags,
+) -> EngineResult<Image> {
+    let mut desc = ImageDesc::new(
+        format,
+        extent,
+        vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSIENT_ATTACHMENT,
+    );
+    desc.samples = samples;
+    allocator.create_image(name, desc, MemoryUsage::GpuOnly)
+}
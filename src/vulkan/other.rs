@@ -1,11 +1,38 @@
-use std::{ffi::CString, fs::File, io::Read, path::Path, ptr};
+use std::ptr;
 
 use ash::vk;
-use winit::window::Window;
+use crate::vulkan::depth::*;
+use crate::vulkan::descriptors::*;
+use crate::vulkan::mesh::*;
+use crate::vulkan::msaa::*;
+use crate::vulkan::pipeline::*;
+use crate::vulkan::pipeline_cache::*;
+use crate::vulkan::reflection::*;
+use crate::vulkan::rendering::*;
+use crate::vulkan::renderer::*;
+use crate::vulkan::shaders::*;
+use crate::vulkan::error::*;
 use crate::vulkan::swapchain::*;
 use crate::AppEvents;
 
-pub const MAX_FRAMES_IN_FLIGHT: usize = 2;
+/// Overrides how many frames the CPU may record ahead of the GPU, e.g. `VOXEL_FRAMES_IN_FLIGHT=3`.
+pub const FRAMES_IN_FLIGHT_ENV: &str = "VOXEL_FRAMES_IN_FLIGHT";
+pub const DEFAULT_FRAMES_IN_FLIGHT: usize = 2;
+/// More than this only adds latency.
+pub const MAX_FRAMES_IN_FLIGHT: usize = 4;
+
+/// `requested` clamped to `1..=MAX_FRAMES_IN_FLIGHT`, with 0 meaning `DEFAULT_FRAMES_IN_FLIGHT`.
+pub fn clamp_frames_in_flight(requested: usize) -> usize {
+    match requested {
+        0 => DEFAULT_FRAMES_IN_FLIGHT,
+        requested => requested.min(MAX_FRAMES_IN_FLIGHT),
+    }
+}
+
+/// The requested frames in flight from `FRAMES_IN_FLIGHT_ENV`, if set to a number.
+pub fn frames_in_flight_from_env() -> Option<usize> {
+    std::env::var(FRAMES_IN_FLIGHT_ENV).ok()?.trim().parse().ok()
+}
 
 pub struct SyncObjects {
     pub image_available_semaphores: Vec<vk::Semaphore>,
@@ -13,307 +40,188 @@ pub struct SyncObjects {
     pub in_flight_fences: Vec<vk::Fence>,
 }
 
-pub fn create_image_views(device: &ash::Device, surface_format: vk::Format, images: &Vec<vk::Image>) -> Vec<vk::ImageView> {
+pub fn create_image_views(device: &ash::Device, surface_format: vk::Format, images: &[vk::Image]) -> EngineResult<Vec<vk::ImageView>> {
     let mut swapchain_imageviews = vec![];
 
     for &image in images.iter() {
-        let imageview_create_info = vk::ImageViewCreateInfo {
-            s_type: vk::StructureType::IMAGE_VIEW_CREATE_INFO,
-            flags: vk::ImageViewCreateFlags::empty(),
-            view_type: vk::ImageViewType::TYPE_2D,
-            format: surface_format,
-            components: vk::ComponentMapping {
-                r: vk::ComponentSwizzle::IDENTITY,
-                g: vk::ComponentSwizzle::IDENTITY,
-                b: vk::ComponentSwizzle::IDENTITY,
-                a: vk::ComponentSwizzle::IDENTITY,
-            },
-            subresource_range: vk::ImageSubresourceRange {
-                aspect_mask: vk::ImageAspectFlags::COLOR,
-                base_mip_level: 0,
-                level_count: 1,
-                base_array_layer: 0,
-                layer_count: 1,
-            },
-            image,
-            ..Default::default()
-        };
-
-        let imageview = unsafe {
-            device
-                .create_image_view(&imageview_create_info, None)
-                .expect("Failed to create Image View!")
-        };
-        swapchain_imageviews.push(imageview);
+        match create_image_view(device, image, surface_format, vk::ImageAspectFlags::COLOR) {
+            Ok(imageview) => swapchain_imageviews.push(imageview),
+            Err(e) => {
+                for imageview in swapchain_imageviews {
+                    unsafe { device.destroy_image_view(imageview, None) };
+                }
+                return Err(e);
+            }
+        }
     }
 
-    swapchain_imageviews
+    Ok(swapchain_imageviews)
 }
 
-pub fn create_graphics_pipeline(device: &ash::Device, render_pass: vk::RenderPass, swapchain_extent: vk::Extent2D) -> (vk::Pipeline, vk::PipelineLayout) {
-    // println!(" --- create_graphics_pipeline function debug info --- ");
-    let vert_shader_code = read_shader_code(Path::new("shaders/glsl.vert.spv"));
-    let frag_shader_code = read_shader_code(Path::new("shaders/glsl.frag.spv"));
-
-    let vert_shader_module = create_shader_module(device, vert_shader_code);
-    let frag_shader_module = create_shader_module(device, frag_shader_code);
-
-    let main_function_name = CString::new("main").unwrap();
-    // println!("main function name: {:?}", main_function_name);
-
-    let shader_stages = [
-        vk::PipelineShaderStageCreateInfo {
-            s_type: vk::StructureType::PIPELINE_SHADER_STAGE_CREATE_INFO,
-            flags: vk::PipelineShaderStageCreateFlags::empty(),
-            module: vert_shader_module,
-            p_name: main_function_name.as_ptr(),
-            stage: vk::ShaderStageFlags::VERTEX,
-            p_specialization_info: ptr::null(),
-            ..Default::default()
+/// A 2D view of the first mip level and layer of `image`.
+pub fn create_image_view(
+    device: &ash::Device,
+    image: vk::Image,
+    format: vk::Format,
+    aspect_mask: vk::ImageAspectFlags,
+) -> EngineResult<vk::ImageView> {
+    let imageview_create_info = vk::ImageViewCreateInfo {
+        s_type: vk::StructureType::IMAGE_VIEW_CREATE_INFO,
+        flags: vk::ImageViewCreateFlags::empty(),
+        view_type: vk::ImageViewType::TYPE_2D,
+        format,
+        components: vk::ComponentMapping {
+            r: vk::ComponentSwizzle::IDENTITY,
+            g: vk::ComponentSwizzle::IDENTITY,
+            b: vk::ComponentSwizzle::IDENTITY,
+            a: vk::ComponentSwizzle::IDENTITY,
         },
-        vk::PipelineShaderStageCreateInfo {
-            s_type: vk::StructureType::PIPELINE_SHADER_STAGE_CREATE_INFO,
-            flags: vk::PipelineShaderStageCreateFlags::empty(),
-            module: frag_shader_module,
-            p_name: main_function_name.as_ptr(),
-            stage: vk::ShaderStageFlags::FRAGMENT,
-            p_specialization_info: ptr::null(),
-            ..Default::default()
-        }
-    ];
-    // println!("Shader Stages: {:?}", shader_stages);
-
-    let vertex_input_state_create_info = vk::PipelineVertexInputStateCreateInfo {
-        s_type: vk::StructureType::PIPELINE_VERTEX_INPUT_STATE_CREATE_INFO,
-        p_next: ptr::null(),
-        flags: vk::PipelineVertexInputStateCreateFlags::empty(),
-        vertex_attribute_description_count: 0,
-        p_vertex_attribute_descriptions: ptr::null(),
-        vertex_binding_description_count: 0,
-        p_vertex_binding_descriptions: ptr::null(),
-        ..Default::default()
-    };
-    let vertex_input_assembly_state_info = vk::PipelineInputAssemblyStateCreateInfo {
-        s_type: vk::StructureType::PIPELINE_INPUT_ASSEMBLY_STATE_CREATE_INFO,
-        flags: vk::PipelineInputAssemblyStateCreateFlags::empty(),
-        p_next: ptr::null(),
-        primitive_restart_enable: vk::FALSE,
-        topology: vk::PrimitiveTopology::TRIANGLE_LIST,
-        ..Default::default()
-    };
-    // println!("Vertex input state create info: {:?}", vertex_input_state_create_info);
-    // println!("Vertex input assembly state info: {:?}", vertex_input_assembly_state_info);
-
-    let viewports = [vk::Viewport {
-        x: 0.0,
-        y: 0.0,
-        width: swapchain_extent.width as f32,
-        height: swapchain_extent.height as f32,
-        min_depth: 0.0,
-        max_depth: 1.0,
-    }];
-    // println!("Viewports: {:?}", viewports);
-
-    let scissors = [vk::Rect2D {
-        offset: vk::Offset2D { x: 0, y: 0 },
-        extent: swapchain_extent,
-    }];
-    // println!("Scissors: {:?}", scissors);
-
-    let viewport_state_create_info = vk::PipelineViewportStateCreateInfo {
-        s_type: vk::StructureType::PIPELINE_VIEWPORT_STATE_CREATE_INFO,
-        flags: vk::PipelineViewportStateCreateFlags::empty(),
-        scissor_count: scissors.len() as u32,
-        viewport_count: viewports.len() as u32,
-        p_scissors: scissors.as_ptr(),
-        p_viewports: viewports.as_ptr(),
-        ..Default::default()
-    };
-    // println!("Viewport state create info: {:?}", viewport_state_create_info);
-
-    let rasterization_state_create_info = vk::PipelineRasterizationStateCreateInfo {
-        s_type: vk::StructureType::PIPELINE_RASTERIZATION_STATE_CREATE_INFO,
-        flags: vk::PipelineRasterizationStateCreateFlags::empty(),
-        depth_clamp_enable: vk::FALSE,
-        cull_mode: vk::CullModeFlags::BACK,
-        front_face: vk::FrontFace::CLOCKWISE,
-        line_width: 1.0,
-        polygon_mode: vk::PolygonMode::FILL,
-        rasterizer_discard_enable: vk::FALSE,
-        depth_bias_clamp: 0.0,
-        depth_bias_constant_factor: 0.0,
-        depth_bias_enable: vk::FALSE,
-        depth_bias_slope_factor: 0.0,
-        ..Default::default()
-    };
-    // println!("Rasterization state create info: {:?}", rasterization_state_create_info);
-
-    let multisample_state_create_info = vk::PipelineMultisampleStateCreateInfo {
-        s_type: vk::StructureType::PIPELINE_MULTISAMPLE_STATE_CREATE_INFO,
-        flags: vk::PipelineMultisampleStateCreateFlags::empty(),
-        rasterization_samples: vk::SampleCountFlags::TYPE_1,
-        sample_shading_enable: vk::FALSE,
-        min_sample_shading: 0.0,
-        alpha_to_one_enable: vk::FALSE,
-        alpha_to_coverage_enable: vk::FALSE,
-        ..Default::default()
-    };
-    // println!("Multisample state create info: {:?}", multisample_state_create_info);
-
-    let stencil_state = vk::StencilOpState {
-        fail_op: vk::StencilOp::KEEP,
-        pass_op: vk::StencilOp::KEEP,
-        depth_fail_op: vk::StencilOp::KEEP,
-        compare_op: vk::CompareOp::ALWAYS,
-        compare_mask: 0,
-        write_mask: 0,
-        reference: 0,
-    };
-    // println!("Stencil state: {:?}", stencil_state);
-
-    let depth_state_create_info = vk::PipelineDepthStencilStateCreateInfo {
-        s_type: vk::StructureType::PIPELINE_DEPTH_STENCIL_STATE_CREATE_INFO,
-        flags: vk::PipelineDepthStencilStateCreateFlags::empty(),
-        depth_test_enable: vk::TRUE,
-        depth_write_enable: vk::TRUE,
-        depth_compare_op: vk::CompareOp::LESS_OR_EQUAL,
-        depth_bounds_test_enable: vk::FALSE,
-        stencil_test_enable: vk::FALSE,
-        front: stencil_state,
-        back: stencil_state,
-        max_depth_bounds: 1.0,
-        min_depth_bounds: 0.0,
-        ..Default::default()
-    };
-    // println!("Depth state create info: {:?}", depth_state_create_info);
-
-    let color_blend_attachment_states = [vk::PipelineColorBlendAttachmentState {
-        blend_enable: vk::FALSE,
-        color_write_mask: vk::ColorComponentFlags::R 
-            | vk::ColorComponentFlags::G 
-            | vk::ColorComponentFlags::B 
-            | vk::ColorComponentFlags::A,  // Enable all color components
-        src_color_blend_factor: vk::BlendFactor::ONE,
-        dst_color_blend_factor: vk::BlendFactor::ZERO,
-        color_blend_op: vk::BlendOp::ADD,
-        src_alpha_blend_factor: vk::BlendFactor::ONE,
-        dst_alpha_blend_factor: vk::BlendFactor::ZERO,
-        alpha_blend_op: vk::BlendOp::ADD,
-    }];
-    // println!("Color blend attachment states: {:?}", color_blend_attachment_states);
-
-    let color_blend_state = vk::PipelineColorBlendStateCreateInfo {
-        s_type: vk::StructureType::PIPELINE_COLOR_BLEND_STATE_CREATE_INFO,
-        flags: vk::PipelineColorBlendStateCreateFlags::empty(),
-        logic_op_enable: vk::FALSE,
-        logic_op: vk::LogicOp::COPY,
-        attachment_count: color_blend_attachment_states.len() as u32,
-        p_attachments: color_blend_attachment_states.as_ptr(),
-        blend_constants: [0.0, 0.0, 0.0, 0.0],
-        ..Default::default()
-    };
-    // println!("Color blend state create info: {:?}", color_blend_state);
-
-    let pipeline_layout_create_info = vk::PipelineLayoutCreateInfo {
-        s_type: vk::StructureType::PIPELINE_LAYOUT_CREATE_INFO,
-        flags: vk::PipelineLayoutCreateFlags::empty(),
-        set_layout_count: 0,
-        p_set_layouts: ptr::null(),
-        push_constant_range_count: 0,
-        p_push_constant_ranges: ptr::null(),
+        subresource_range: vk::ImageSubresourceRange {
+            aspect_mask,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: 1,
+        },
+        image,
         ..Default::default()
     };
-    // println!("Pipeline layout create info: {:?}", pipeline_layout_create_info);
 
-    let pipeline_layout = unsafe {
+    unsafe {
         device
-            .create_pipeline_layout(&pipeline_layout_create_info, None)
-            .expect("Failed to create pipeline layout!")
-    };
-    // println!("Pipeline layout: {:?}", pipeline_layout);
-
-    let graphic_pipeline_create_infos = [vk::GraphicsPipelineCreateInfo {
-        s_type: vk::StructureType::GRAPHICS_PIPELINE_CREATE_INFO,
-        flags: vk::PipelineCreateFlags::empty(),
-        stage_count: shader_stages.len() as u32,
-        p_stages: shader_stages.as_ptr(),
-        p_vertex_input_state: &vertex_input_state_create_info,
-        p_input_assembly_state: &vertex_input_assembly_state_info,
-        p_viewport_state: &viewport_state_create_info,
-        p_rasterization_state: &rasterization_state_create_info,
-        p_multisample_state: &multisample_state_create_info,
-        p_depth_stencil_state: &depth_state_create_info,
-        p_color_blend_state: &color_blend_state,
-        p_dynamic_state: ptr::null(),
-        layout: pipeline_layout,
-        render_pass,
-        subpass: 0,
-        base_pipeline_handle: vk::Pipeline::null(),
-        base_pipeline_index: -1,
-        ..Default::default()
-    }];
-    // println!("Graphics pipeline create infos: {:?}", graphic_pipeline_create_infos);
+            .create_image_view(&imageview_create_info, None)
+            .context("create image view")
+    }
+}
 
-    let graphics_pipelines = unsafe {
-        device
-            .create_graphics_pipelines(
-                vk::PipelineCache::null(),
-                &graphic_pipeline_create_infos,
-                None,
-            )
-            .expect("Failed to create Graphics Pipeline!")
+/// Reflects the scene shaders and checks them against `Vertex` and `scene_descriptor_layout`, so an
+/// edit to either side that breaks the other fails here instead of on the GPU.
+pub fn scene_pipeline_interface(vertex_code: &[u32], fragment_code: &[u32]) -> EngineResult<PipelineInterface> {
+    let reflect = |name: &str, code: &[u32]| {
+        ShaderReflection::new(code).map_err(|e| EngineError::ShaderInterface(format!("{}: {}", name, e)))
     };
-    // println!("Graphics pipelines: {:?}", graphics_pipelines);
-
-    unsafe {
-        device.destroy_shader_module(vert_shader_module, None);
-        device.destroy_shader_module(frag_shader_module, None);
+    let stages = vec![reflect(SCENE_VERTEX_SHADER, vertex_code)?, reflect(SCENE_FRAGMENT_SHADER, fragment_code)?];
+    let interface = PipelineInterface::new(stages).map_err(EngineError::ShaderInterface)?;
+
+    interface
+        .validate_vertex_input(&Vertex::attribute_descriptions())
+        .and_then(|()| interface.validate_set_layout(0, scene_descriptor_layout().bindings()))
+        .map_err(EngineError::ShaderInterface)?;
+    if let Some(binding) = interface.descriptor_bindings.iter().find(|binding| binding.set > 0) {
+        return Err(EngineError::ShaderInterface(format!("{} uses set {}, but only set 0 exists", binding.name, binding.set)));
     }
-
-    (graphics_pipelines[0], pipeline_layout)
+    Ok(interface)
 }
 
-fn create_shader_module(device: &ash::Device, code: Vec<u8>) -> vk::ShaderModule {
-    let shader_module_create_info = vk::ShaderModuleCreateInfo {
-        s_type: vk::StructureType::SHADER_MODULE_CREATE_INFO,
-        flags: vk::ShaderModuleCreateFlags::empty(),
-        code_size: code.len(),
-        p_code: code.as_ptr() as *const u32,
-        ..Default::default()
+/// The scene pipeline: `SCENE_VERTEX_SHADER` and `SCENE_FRAGMENT_SHADER` drawing `Vertex` meshes
+/// into `target`, with a layout derived from the shaders. Viewport and scissor are dynamic.
+pub fn create_graphics_pipeline(
+    device: &ash::Device,
+    pipeline_cache: vk::PipelineCache,
+    target: PipelineTarget,
+    set_layouts: &[vk::DescriptorSetLayout],
+    samples: vk::SampleCountFlags,
+) -> EngineResult<(vk::Pipeline, vk::PipelineLayout)> {
+    let vert_shader_code = load_shader(SCENE_VERTEX_SHADER)?;
+    let frag_shader_code = load_shader(SCENE_FRAGMENT_SHADER)?;
+    let interface = scene_pipeline_interface(&vert_shader_code, &frag_shader_code)?;
+
+    let vert_shader_module = create_shader_module(device, &vert_shader_code)?;
+    let frag_shader_module = match create_shader_module(device, &frag_shader_code) {
+        Ok(module) => module,
+        Err(e) => {
+            unsafe { device.destroy_shader_module(vert_shader_module, None) };
+            return Err(e);
+        }
     };
 
+    let result = create_pipeline_layout(device, set_layouts, &interface.push_constant_ranges()).and_then(|pipeline_layout| {
+        let pipeline = PipelineBuilder::new()
+            .shader(vk::ShaderStageFlags::VERTEX, vert_shader_module)
+            .shader(vk::ShaderStageFlags::FRAGMENT, frag_shader_module)
+            .vertex_input(&[Vertex::binding_description()], &Vertex::attribute_descriptions())
+            .samples(samples)
+            .build(device, pipeline_cache, pipeline_layout, target);
+        match pipeline {
+            Ok(pipeline) => Ok((pipeline, pipeline_layout)),
+            Err(e) => {
+                unsafe { device.destroy_pipeline_layout(pipeline_layout, None) };
+                Err(e)
+            }
+        }
+    });
+
     unsafe {
-        device
-            .create_shader_module(&shader_module_create_info, None)
-            .expect("Failed to create Shader Module!")
+        device.destroy_shader_module(vert_shader_module, None);
+        device.destroy_shader_module(frag_shader_module, None);
     }
+    result
 }
 
-fn read_shader_code(shader_path: &Path) -> Vec<u8> {
-    let spv_file = File::open(shader_path)
-    .expect(&format!("Failed to find spv file at {:?}", shader_path));
-    let bytes_code: Vec<u8> = spv_file.bytes().filter_map(|byte| byte.ok()).collect();
-
-    bytes_code
-}
+/// `final_layout` is `PRESENT_SRC_KHR` for the swapchain and `TRANSFER_SRC_OPTIMAL` for offscreen
+/// targets that are read back afterwards. Attachment 1 is a depth buffer in `depth_format` that is
+/// cleared every pass and discarded afterwards.
+///
+/// With more than one sample, attachment 0 is a multisampled color target that is resolved into
+/// attachment 2, the image that ends up in `final_layout`. Framebuffers have to match that order,
+/// see `create_framebuffers`.
+pub fn create_render_pass(
+    device: &ash::Device,
+    surface_format: vk::Format,
+    depth_format: vk::Format,
+    samples: vk::SampleCountFlags,
+    final_layout: vk::ImageLayout,
+) -> EngineResult<vk::RenderPass> {
+    let multisampled = samples != vk::SampleCountFlags::TYPE_1;
 
-pub fn create_render_pass(device: &ash::Device, surface_format: vk::Format) -> vk::RenderPass {
     let color_attachment = vk::AttachmentDescription {
         flags: vk::AttachmentDescriptionFlags::empty(),
         format: surface_format,
-        samples: vk::SampleCountFlags::TYPE_1,
+        samples,
         load_op: vk::AttachmentLoadOp::CLEAR,
-        store_op: vk::AttachmentStoreOp::STORE,
+        store_op: if multisampled { vk::AttachmentStoreOp::DONT_CARE } else { vk::AttachmentStoreOp::STORE },
+        stencil_load_op: vk::AttachmentLoadOp::DONT_CARE,
+        stencil_store_op: vk::AttachmentStoreOp::DONT_CARE,
+        initial_layout: vk::ImageLayout::UNDEFINED,
+        final_layout: if multisampled { vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL } else { final_layout },
+    };
+
+    let depth_attachment = vk::AttachmentDescription {
+        flags: vk::AttachmentDescriptionFlags::empty(),
+        format: depth_format,
+        samples,
+        load_op: vk::AttachmentLoadOp::CLEAR,
+        store_op: vk::AttachmentStoreOp::DONT_CARE,
         stencil_load_op: vk::AttachmentLoadOp::DONT_CARE,
         stencil_store_op: vk::AttachmentStoreOp::DONT_CARE,
         initial_layout: vk::ImageLayout::UNDEFINED,
-        final_layout: vk::ImageLayout::PRESENT_SRC_KHR,
+        final_layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
     };
 
     let color_attachment_ref = vk::AttachmentReference {
         attachment: 0,
         layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
     };
+    let depth_attachment_ref = vk::AttachmentReference {
+        attachment: 1,
+        layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+    };
+
+    let resolve_attachment = vk::AttachmentDescription {
+        flags: vk::AttachmentDescriptionFlags::empty(),
+        format: surface_format,
+        samples: vk::SampleCountFlags::TYPE_1,
+        load_op: vk::AttachmentLoadOp::DONT_CARE,
+        store_op: vk::AttachmentStoreOp::STORE,
+        stencil_load_op: vk::AttachmentLoadOp::DONT_CARE,
+        stencil_store_op: vk::AttachmentStoreOp::DONT_CARE,
+        initial_layout: vk::ImageLayout::UNDEFINED,
+        final_layout,
+    };
+    let resolve_attachment_ref = vk::AttachmentReference {
+        attachment: 2,
+        layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+    };
 
     let subpass = vk::SubpassDescription {
         flags: vk::SubpassDescriptionFlags::empty(),
@@ -322,22 +230,30 @@ pub fn create_render_pass(device: &ash::Device, surface_format: vk::Format) -> v
         p_input_attachments: ptr::null(),
         color_attachment_count: 1,
         p_color_attachments: &color_attachment_ref,
-        p_resolve_attachments: ptr::null(),
-        p_depth_stencil_attachment: ptr::null(),
+        p_resolve_attachments: if multisampled { &resolve_attachment_ref } else { ptr::null() },
+        p_depth_stencil_attachment: &depth_attachment_ref,
         preserve_attachment_count: 0,
         p_preserve_attachments: ptr::null(),
         _marker: std::marker::PhantomData
     };
 
-    let render_pass_attachments = [color_attachment];
+    let mut render_pass_attachments = vec![color_attachment, depth_attachment];
+    if multisampled {
+        render_pass_attachments.push(resolve_attachment);
+    }
 
+    // The depth buffer is shared by all frames in flight, so the previous frame's depth writes
+    // have to finish before this frame clears it.
     let subpass_dependencies = [vk::SubpassDependency {
         src_subpass: vk::SUBPASS_EXTERNAL,
         dst_subpass: 0,
-        src_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
-        dst_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
-        src_access_mask: vk::AccessFlags::empty(),
-        dst_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+        src_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+            | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+        dst_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+            | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+        src_access_mask: vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+        dst_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+            | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
         dependency_flags: vk::DependencyFlags::empty(),
     }];
 
@@ -358,11 +274,11 @@ pub fn create_render_pass(device: &ash::Device, surface_format: vk::Format) -> v
     unsafe {
         device
             .create_render_pass(&renderpass_create_info, None)
-            .expect("Failed to create render pass!")
+            .context("create render pass")
     }
 }
 
-pub fn create_sync_objects(device: &ash::Device) -> SyncObjects {
+pub fn create_sync_objects(device: &ash::Device, frame_count: usize) -> EngineResult<SyncObjects> {
     let mut sync_objects = SyncObjects {
         image_available_semaphores: vec![],
         render_finished_semaphores: vec![],
@@ -383,17 +299,17 @@ pub fn create_sync_objects(device: &ash::Device) -> SyncObjects {
         ..Default::default()
     };
 
-    for _ in 0..MAX_FRAMES_IN_FLIGHT {
+    for _ in 0..frame_count {
         unsafe {
             let image_available_semaphore = device
                 .create_semaphore(&semaphore_create_info, None)
-                .expect("Failed to create Semaphore Object!");
+                .context("create semaphore")?;
             let render_finished_semaphore = device
                 .create_semaphore(&semaphore_create_info, None)
-                .expect("Failed to create Semaphore Object!");
+                .context("create semaphore")?;
             let inflight_fence = device
                 .create_fence(&fence_create_info, None)
-                .expect("Failed to create Fence Object!");
+                .context("create fence")?;
 
             sync_objects
                 .image_available_semaphores
@@ -405,19 +321,26 @@ pub fn create_sync_objects(device: &ash::Device) -> SyncObjects {
         }
     }
 
-    sync_objects
+    Ok(sync_objects)
 }
 
+/// One framebuffer per color view, all sharing `depth_image_view`. With MSAA they also share
+/// `msaa_color_view`, which the pass resolves into the color view.
 pub fn create_framebuffers(
     device: &ash::Device,
     render_pass: vk::RenderPass,
-    image_views: &Vec<vk::ImageView>,
+    image_views: &[vk::ImageView],
+    depth_image_view: vk::ImageView,
+    msaa_color_view: Option<vk::ImageView>,
     swapchain_extent: &vk::Extent2D,
-) -> Vec<vk::Framebuffer> {
+) -> EngineResult<Vec<vk::Framebuffer>> {
     let mut framebuffers = vec![];
 
     for &image_view in image_views.iter() {
-        let attachments = [image_view];
+        let attachments = match msaa_color_view {
+            Some(msaa_color_view) => vec![msaa_color_view, depth_image_view, image_view],
+            None => vec![image_view, depth_image_view],
+        };
 
         let framebuffer_create_info = vk::FramebufferCreateInfo {
             s_type: vk::StructureType::FRAMEBUFFER_CREATE_INFO,
@@ -432,22 +355,24 @@ pub fn create_framebuffers(
             ..Default::default()
         };
 
-        let framebuffer = unsafe {
-            device
-                .create_framebuffer(&framebuffer_create_info, None)
-                .expect("Failed to create Framebuffer!")
-        };
-
-        framebuffers.push(framebuffer);
+        match unsafe { device.create_framebuffer(&framebuffer_create_info, None) } {
+            Ok(framebuffer) => framebuffers.push(framebuffer),
+            Err(e) => {
+                for framebuffer in framebuffers {
+                    unsafe { device.destroy_framebuffer(framebuffer, None) };
+                }
+                return Err(e).context("create framebuffer");
+            }
+        }
     }
 
-    framebuffers
+    Ok(framebuffers)
 }
 
 pub fn create_command_pool(
     device: &ash::Device,
     graphics_family: u32,
-) -> vk::CommandPool {
+) -> EngineResult<vk::CommandPool> {
     let command_pool_create_info = vk::CommandPoolCreateInfo {
         s_type: vk::StructureType::COMMAND_POOL_CREATE_INFO,
         p_next: ptr::null(),
@@ -459,164 +384,242 @@ pub fn create_command_pool(
     unsafe {
         device
             .create_command_pool(&command_pool_create_info, None)
-            .expect("Failed to create Command Pool!")
+            .context("create command pool")
     }
 }
 
-pub fn create_command_buffers(
-    device: &ash::Device,
-    command_pool: vk::CommandPool,
-    graphics_pipeline: vk::Pipeline,
-    framebuffers: &Vec<vk::Framebuffer>,
-    render_pass: vk::RenderPass,
-    surface_extent: vk::Extent2D,
-) -> Vec<vk::CommandBuffer> {
-    let command_buffer_allocate_info = vk::CommandBufferAllocateInfo {
-        s_type: vk::StructureType::COMMAND_BUFFER_ALLOCATE_INFO,
-        p_next: ptr::null(),
-        command_buffer_count: framebuffers.len() as u32,
-        command_pool,
-        level: vk::CommandBufferLevel::PRIMARY,
-        ..Default::default()
-    };
-
-    let command_buffers = unsafe {
-        device
-            .allocate_command_buffers(&command_buffer_allocate_info)
-            .expect("Failed to allocate Command Buffers!")
-    };
-
-    for (i, &command_buffer) in command_buffers.iter().enumerate() {
-        let command_buffer_begin_info = vk::CommandBufferBeginInfo {
-            s_type: vk::StructureType::COMMAND_BUFFER_BEGIN_INFO,
-            p_next: ptr::null(),
-            p_inheritance_info: ptr::null(),
-            flags: vk::CommandBufferUsageFlags::SIMULTANEOUS_USE,
-            ..Default::default()
-        };
+impl AppEvents {
+    /// Null until the device exists, which Vulkan accepts as "no cache".
+    pub(crate) fn pipeline_cache_handle(&self) -> vk::PipelineCache {
+        self.pipeline_cache.as_ref().map_or(vk::PipelineCache::null(), PipelineCache::handle)
+    }
 
-        unsafe {
-            device
-                .begin_command_buffer(command_buffer, &command_buffer_begin_info)
-                .expect("Failed to begin recording Command Buffer at beginning!");
+    /// The scene pipeline's subpass, or the swapchain and depth formats on the dynamic path.
+    pub(crate) fn scene_pipeline_target(&self) -> PipelineTarget<'_> {
+        match self.render_path {
+            RenderPath::RenderPass => PipelineTarget::RenderPass {
+                render_pass: self.render_pass,
+                subpass: 0,
+            },
+            RenderPath::Dynamic => PipelineTarget::Dynamic {
+                color_formats: std::slice::from_ref(&self.swapchain_format),
+                depth_format: self.depth_format,
+            },
         }
+    }
 
-        let clear_values = [vk::ClearValue {
-            color: vk::ClearColorValue {
-                float32: [0.0, 0.0, 0.0, 1.0],
+    /// What the main pass draws into for swapchain image `image_index`.
+    pub(crate) fn scene_target(&self, image_index: usize) -> SceneTarget {
+        match self.render_path {
+            RenderPath::RenderPass => SceneTarget::RenderPass {
+                render_pass: self.render_pass,
+                framebuffer: self.swapchain_framebuffers[image_index],
             },
-        }];
+            RenderPath::Dynamic => SceneTarget::Dynamic(RenderingAttachments {
+                target: self.swapchain_images[image_index],
+                target_view: self.swapchain_imageviews[image_index],
+                msaa_color: self.msaa_color_image.as_ref().map(|image| (image.handle(), self.msaa_color_view)),
+                depth: self.depth_image.as_ref().unwrap().handle(),
+                depth_view: self.depth_image_view,
+                depth_format: self.depth_format,
+                final_layout: vk::ImageLayout::PRESENT_SRC_KHR,
+            }),
+        }
+    }
 
-        let render_pass_begin_info = vk::RenderPassBeginInfo {
-            s_type: vk::StructureType::RENDER_PASS_BEGIN_INFO,
-            p_next: ptr::null(),
-            render_pass,
-            framebuffer: framebuffers[i],
-            render_area: vk::Rect2D {
-                offset: vk::Offset2D { x: 0, y: 0 },
-                extent: surface_extent,
-            },
-            clear_value_count: clear_values.len() as u32,
-            p_clear_values: clear_values.as_ptr(),
-            ..Default::default()
-        };
+    /// Destroys every object that depends on the current swapchain (and finally the swapchain itself
+    /// when `destroy_swapchain` is set). The device must be idle.
+    pub fn cleanup_swapchain(&mut self, destroy_swapchain: bool) {
+        let device = self.logical_device.as_ref().unwrap();
 
-        unsafe {
-            device.cmd_begin_render_pass(
-                command_buffer,
-                &render_pass_begin_info,
-                vk::SubpassContents::INLINE,
-            );
-            device.cmd_bind_pipeline(
-                command_buffer,
-                vk::PipelineBindPoint::GRAPHICS,
-                graphics_pipeline,
-            );
-
-            device.cmd_draw(command_buffer, 0, 1, 0, 0);
-            
-            device.cmd_end_render_pass(command_buffer);
-            
-            device
-            .end_command_buffer(command_buffer)
-            .expect("Failed to record Command Buffer at Ending!");
+        self.swapchain_deletion_queue.flush(device);
+        if let (true, Some(swapchain_loader)) = (destroy_swapchain, self.swapchain_loader.as_ref()) {
+            unsafe { swapchain_loader.destroy_swapchain(self.swapchain, None) };
+            self.swapchain = vk::SwapchainKHR::null();
         }
+
+        self.depth_image = None;
+        self.depth_image_view = vk::ImageView::null();
+        self.msaa_color_image = None;
+        self.msaa_color_view = vk::ImageView::null();
+        self.swapchain_framebuffers.clear();
+        self.swapchain_imageviews.clear();
     }
 
-    command_buffers
-}
+    /// Builds the render pass (on the render-pass path) and the scene pipeline unless the current
+    /// ones were built for the same render path, swapchain format and sample count. Viewport and
+    /// scissor are dynamic, so a resize alone keeps them. The device must be idle.
+    pub(crate) fn ensure_scene_pipeline(&mut self) -> EngineResult<()> {
+        let key = (self.render_path, self.swapchain_format, self.msaa_samples);
+        if self.scene_pipeline_key == Some(key) {
+            return Ok(());
+        }
 
-pub struct SurfaceStuff {
-    pub surface_loader: Option<ash::khr::surface::Instance>,
-    pub surface: vk::SurfaceKHR,
-    pub screen_width: u32,
-    pub screen_height: u32,
-}
+        let device = self.logical_device.as_ref().unwrap();
+        self.pipeline_deletion_queue.flush(device);
+        self.scene_pipeline_key = None;
+        self.render_pass = vk::RenderPass::null();
+        self.graphics_pipeline = vk::Pipeline::null();
+        self.pipeline_layout = vk::PipelineLayout::null();
+
+        if self.render_path == RenderPath::RenderPass {
+            self.render_pass = create_render_pass(
+                device,
+                self.swapchain_format,
+                self.depth_format,
+                self.msaa_samples,
+                vk::ImageLayout::PRESENT_SRC_KHR,
+            )?;
+            self.pipeline_deletion_queue.push(self.render_pass);
+            self.debug_names.name(self.render_pass, "Main Render Pass");
+        }
+        let (graphics_pipeline, pipeline_layout) = create_graphics_pipeline(
+            device,
+            self.pipeline_cache_handle(),
+            self.scene_pipeline_target(),
+            &[self.descriptor_set_layout],
+            self.msaa_samples,
+        )?;
+        self.graphics_pipeline = graphics_pipeline;
+        self.pipeline_layout = pipeline_layout;
+        self.pipeline_deletion_queue.push(self.pipeline_layout);
+        self.pipeline_deletion_queue.push(self.graphics_pipeline);
+        self.debug_names.name(self.pipeline_layout, "Triangle Pipeline Layout");
+        self.debug_names.name(self.graphics_pipeline, "Triangle Pipeline");
+
+        self.scene_pipeline_key = Some(key);
+        Ok(())
+    }
 
-impl AppEvents {
-    fn recreate_swapchain(&mut self, window: &Window) {
-        // Parameters
-        let surface_suff = SurfaceStuff {
-            surface_loader: self.surface_loader.clone(),
-            surface: self.surface,
-            screen_width: window.inner_size().width,
-            screen_height: window.inner_size().height,
-        };
+    /// Rebuilds the swapchain and everything that depends on it. Returns `Ok(false)` without
+    /// touching anything while the window has a zero-sized extent (e.g. minimized), in which case
+    /// `framebuffer_resized` stays set so the recreation is retried once the window is restored.
+    pub fn recreate_swapchain(&mut self) -> EngineResult<bool> {
+        let window = self.window.as_ref().unwrap();
+        let size = window.inner_size();
+        if size.width == 0 || size.height == 0 {
+            return Ok(false);
+        }
+        if surface_extent_is_zero(self.surface_loader.as_ref().unwrap(), self.physical_device, self.surface)? {
+            return Ok(false);
+        }
 
         unsafe {
             self.logical_device
                 .as_ref()
                 .unwrap()
                 .device_wait_idle()
-                .expect("Failed to wait device idle!")
+                .context("wait for device idle")?
         };
-        // self.cleanup_swapchain();
 
+        let old_swapchain = self.swapchain;
+        let device = self.logical_device.as_ref().unwrap();
         let swapchain_stuff = create_swap_chain(
             self.instance.as_ref().unwrap(),
-            self.logical_device.as_ref().unwrap().clone(),
+            device.device.clone(),
             self.physical_device,
             self.surface,
             self.surface_loader.as_ref().unwrap().clone(),
-            self.queue_family,
+            &device.queue_families,
             window,
+            self.swapchain_settings,
+            old_swapchain,
         );
+        // The old swapchain is retired by the create call, even when it fails
+        self.cleanup_swapchain(false);
+        unsafe { self.swapchain_loader.as_ref().unwrap().destroy_swapchain(old_swapchain, None) };
+        self.swapchain = vk::SwapchainKHR::null();
+        let swapchain_stuff = match swapchain_stuff {
+            // The surface shrank to nothing after the capabilities check above
+            Err(EngineError::Swapchain { result: vk::Result::ERROR_OUT_OF_DATE_KHR, .. }) => return Ok(false),
+            result => result?,
+        };
+
         self.swapchain_loader = Some(swapchain_stuff.swapchain_loader);
         self.swapchain = swapchain_stuff.swapchain;
-        // self.swapchain_images = swapchain_stuff.swapchain_images;
-        // self.swapchain_format = swapchain_stuff.swapchain_format;
-        // self.swapchain_extent = swapchain_stuff.swapchain_extent;
-
-        // self.swapchain_imageviews = create_image_views(
-        //     self.logical_device.as_ref().unwrap(),
-        //     self.swapchain_format,
-        //     &self.swapchain_images,
-        // );
-        // self.render_pass = create_render_pass(
-        //     self.logical_device.as_ref().unwrap(),
-        //     self.swapchain_format,
-        // );
-        // let (graphics_pipeline, pipeline_layout) = create_graphics_pipeline(
-        //     self.logical_device.as_ref().unwrap(),
-        //     self.render_pass,
-        //     self.swapchain_extent,
-        // );
-        // self.graphics_pipeline = graphics_pipeline;
-        // self.pipeline_layout = pipeline_layout;
-
-        // self.swapchain_framebuffers = create_framebuffers(
-        //     self.logical_device.as_ref().unwrap(),
-        //     self.render_pass,
-        //     &self.swapchain_imageviews,
-        //     &self.swapchain_extent,
-        // );
-        // self.command_buffers = create_command_buffers(
-        //     self.logical_device.as_ref().unwrap(),
-        //     self.command_pool,
-        //     self.graphics_pipeline,
-        //     &self.swapchain_framebuffers,
-        //     self.render_pass,
-        //     self.swapchain_extent,
-        // );
+        self.swapchain_images = swapchain_stuff.swapchain_images;
+        self.swapchain_format = swapchain_stuff.swapchain_format;
+        self.swapchain_extent = swapchain_stuff.swapchain_extent;
+        self.present_mode = swapchain_stuff.present_mode;
+        self.create_swapchain_resources()?;
+
+        self.framebuffer_resized = false;
+        println!("Swapchain recreated: {:?}, {:?} with {} images", self.swapchain_extent, self.present_mode, self.swapchain_images.len());
+        Ok(true)
+    }
+
+    /// Creates the image views, depth and MSAA targets and, on the render-pass path, framebuffers
+    /// for the current swapchain images and sample count, rebuilding the scene pipeline first if
+    /// those changed what it was built for. Each object is queued for deletion as soon as it exists
+    /// so `cleanup_swapchain` can tear down a partially built set after an error.
+    pub fn create_swapchain_resources(&mut self) -> EngineResult<()> {
+        let device = self.logical_device.as_ref().unwrap();
+
+        self.swapchain_imageviews = create_image_views(
+            device,
+            self.swapchain_format,
+            &self.swapchain_images,
+        )?;
+        self.swapchain_deletion_queue.push_all(&self.swapchain_imageviews);
+        let allocator = self.allocator.as_ref().unwrap();
+        let depth_image = self.depth_image.insert(create_depth_image(
+            allocator,
+            "Depth Image",
+            self.depth_format,
+            self.swapchain_extent,
+            self.msaa_samples,
+        )?);
+        self.depth_image_view = create_image_view(
+            device,
+            depth_image.handle(),
+            self.depth_format,
+            depth_aspect_mask(self.depth_format),
+        )?;
+        self.swapchain_deletion_queue.push(self.depth_image_view);
+        self.debug_names.name(depth_image.handle(), "Depth Image");
+        self.debug_names.name(self.depth_image_view, "Depth Image View");
+
+        let msaa_color_view = if self.msaa_samples != vk::SampleCountFlags::TYPE_1 {
+            let msaa_color_image = self.msaa_color_image.insert(create_msaa_color_image(
+                allocator,
+                "MSAA Color Image",
+                self.swapchain_format,
+                self.swapchain_extent,
+                self.msaa_samples,
+            )?);
+            self.msaa_color_view = create_image_view(
+                device,
+                msaa_color_image.handle(),
+                self.swapchain_format,
+                vk::ImageAspectFlags::COLOR,
+            )?;
+            self.swapchain_deletion_queue.push(self.msaa_color_view);
+            self.debug_names.name(msaa_color_image.handle(), "MSAA Color Image");
+            self.debug_names.name(self.msaa_color_view, "MSAA Color Image View");
+            Some(self.msaa_color_view)
+        } else {
+            None
+        };
+
+        self.ensure_scene_pipeline()?;
+
+        let device = self.logical_device.as_ref().unwrap();
+        if self.render_path == RenderPath::RenderPass {
+            self.swapchain_framebuffers = create_framebuffers(
+                device,
+                self.render_pass,
+                &self.swapchain_imageviews,
+                self.depth_image_view,
+                msaa_color_view,
+                &self.swapchain_extent,
+            )?;
+            self.swapchain_deletion_queue.push_all(&self.swapchain_framebuffers);
+        }
+
+        self.debug_names.name(self.swapchain, "Swapchain");
+        self.debug_names.name_all(&self.swapchain_images, "Swapchain Image");
+        self.debug_names.name_all(&self.swapchain_imageviews, "Swapchain Image View");
+        self.debug_names.name_all(&self.swapchain_framebuffers, "Swapchain Framebuffer");
+        Ok(())
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,518 @@
+use std::ffi::CString;
+use std::ptr;
+
+use ash::vk;
+
+use crate::vulkan::error::*;
+
+/// What a pipeline renders into: a subpass of a render pass, or the attachment formats of a
+/// dynamic-rendering pass.
+#[derive(Debug, Clone, Copy)]
+pub enum PipelineTarget<'a> {
+    RenderPass {
+        render_pass: vk::RenderPass,
+        subpass: u32,
+    },
+    Dynamic {
+        color_formats: &'a [vk::Format],
+        depth_format: vk::Format,
+    },
+}
+
+/// How a color attachment combines with what's already there.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BlendMode {
+    /// Overwrites the attachment.
+    #[default]
+    Opaque,
+    /// `src * src.a + dst * (1 - src.a)`, for straight alpha like water and glass.
+    Alpha,
+    /// `src + dst * (1 - src.a)`, for colors already multiplied by their alpha (UI).
+    PremultipliedAlpha,
+    /// `src + dst`, for particles and glows.
+    Additive,
+}
+
+impl BlendMode {
+    pub fn attachment_state(self) -> vk::PipelineColorBlendAttachmentState {
+        let (blend_enable, src_color_blend_factor, dst_color_blend_factor) = match self {
+            BlendMode::Opaque => (vk::FALSE, vk::BlendFactor::ONE, vk::BlendFactor::ZERO),
+            BlendMode::Alpha => (vk::TRUE, vk::BlendFactor::SRC_ALPHA, vk::BlendFactor::ONE_MINUS_SRC_ALPHA),
+            BlendMode::PremultipliedAlpha => (vk::TRUE, vk::BlendFactor::ONE, vk::BlendFactor::ONE_MINUS_SRC_ALPHA),
+            BlendMode::Additive => (vk::TRUE, vk::BlendFactor::ONE, vk::BlendFactor::ONE),
+        };
+        vk::PipelineColorBlendAttachmentState {
+            blend_enable,
+            color_write_mask: vk::ColorComponentFlags::RGBA,
+            src_color_blend_factor,
+            dst_color_blend_factor,
+            color_blend_op: vk::BlendOp::ADD,
+            src_alpha_blend_factor: vk::BlendFactor::ONE,
+            dst_alpha_blend_factor: if self == BlendMode::Additive {
+                vk::BlendFactor::ONE
+            } else {
+                dst_color_blend_factor
+            },
+            alpha_blend_op: vk::BlendOp::ADD,
+        }
+    }
+}
+
+/// Values for `layout(constant_id = N) const` in one shader stage, packed the way
+/// `vk::SpecializationInfo` wants them.
+#[derive(Debug, Clone, Default)]
+pub struct SpecializationConstants {
+    entries: Vec<vk::SpecializationMapEntry>,
+    data: Vec<u8>,
+}
+
+impl SpecializationConstants {
+    pub fn new() -> SpecializationConstants {
+        SpecializationConstants::default()
+    }
+
+    fn add_bytes(mut self, constant_id: u32, bytes: [u8; 4]) -> Self {
+        self.entries.push(vk::SpecializationMapEntry {
+            constant_id,
+            offset: self.data.len() as u32,
+            size: bytes.len(),
+        });
+        self.data.extend_from_slice(&bytes);
+        self
+    }
+
+    pub fn add_u32(self, constant_id: u32, value: u32) -> Self {
+        self.add_bytes(constant_id, value.to_ne_bytes())
+    }
+
+    pub fn add_i32(self, constant_id: u32, value: i32) -> Self {
+        self.add_bytes(constant_id, value.to_ne_bytes())
+    }
+
+    pub fn add_f32(self, constant_id: u32, value: f32) -> Self {
+        self.add_bytes(constant_id, value.to_ne_bytes())
+    }
+
+    /// GLSL `bool` constants are 32 bits wide.
+    pub fn add_bool(self, constant_id: u32, value: bool) -> Self {
+        self.add_u32(constant_id, value as vk::Bool32)
+    }
+
+    pub fn entries(&self) -> &[vk::SpecializationMapEntry] {
+        &self.entries
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[derive(Debug, Clone)]
+struct ShaderStage {
+    stage: vk::ShaderStageFlags,
+    module: vk::ShaderModule,
+    entry_point: CString,
+    specialization: SpecializationConstants,
+}
+
+/// Describes one graphics pipeline. Defaults match the scene: filled triangle lists, back faces
+/// culled with counter-clockwise fronts, reverse-Z depth test and write, one opaque color
+/// attachment, single-sampled, and viewport and scissor left as dynamic state so the pipeline
+/// doesn't depend on the target size. Set them with `set_viewport_and_scissor` after binding.
+#[derive(Debug, Clone)]
+pub struct PipelineBuilder {
+    stages: Vec<ShaderStage>,
+    vertex_bindings: Vec<vk::VertexInputBindingDescription>,
+    vertex_attributes: Vec<vk::VertexInputAttributeDescription>,
+    topology: vk::PrimitiveTopology,
+    primitive_restart: bool,
+    polygon_mode: vk::PolygonMode,
+    cull_mode: vk::CullModeFlags,
+    front_face: vk::FrontFace,
+    line_width: f32,
+    depth_test: bool,
+    depth_write: bool,
+    depth_compare_op: vk::CompareOp,
+    stencil: Option<(vk::StencilOpState, vk::StencilOpState)>,
+    color_attachments: Vec<BlendMode>,
+    samples: vk::SampleCountFlags,
+    fixed_extent: Option<vk::Extent2D>,
+    dynamic_states: Vec<vk::DynamicState>,
+}
+
+impl Default for PipelineBuilder {
+    fn default() -> Self {
+        PipelineBuilder {
+            stages: Vec::new(),
+            vertex_bindings: Vec::new(),
+            vertex_attributes: Vec::new(),
+            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+            primitive_restart: false,
+            polygon_mode: vk::PolygonMode::FILL,
+            cull_mode: vk::CullModeFlags::BACK,
+            // Counter-clockwise in world space, given the Y flip in every projection
+            front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+            line_width: 1.0,
+            depth_test: true,
+            depth_write: true,
+            depth_compare_op: vk::CompareOp::GREATER_OR_EQUAL,
+            stencil: None,
+            color_attachments: vec![BlendMode::Opaque],
+            samples: vk::SampleCountFlags::TYPE_1,
+            fixed_extent: None,
+            dynamic_states: vec![vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR],
+        }
+    }
+}
+
+impl PipelineBuilder {
+    pub fn new() -> PipelineBuilder {
+        PipelineBuilder::default()
+    }
+
+    /// Adds a stage with entry point `main`. The module only has to live until `build` returns.
+    pub fn shader(self, stage: vk::ShaderStageFlags, module: vk::ShaderModule) -> Self {
+        self.specialized_shader(stage, module, SpecializationConstants::new())
+    }
+
+    pub fn specialized_shader(
+        mut self,
+        stage: vk::ShaderStageFlags,
+        module: vk::ShaderModule,
+        specialization: SpecializationConstants,
+    ) -> Self {
+        self.stages.push(ShaderStage {
+            stage,
+            module,
+            entry_point: CString::new("main").unwrap(),
+            specialization,
+        });
+        self
+    }
+
+    pub fn vertex_input(
+        mut self,
+        bindings: &[vk::VertexInputBindingDescription],
+        attributes: &[vk::VertexInputAttributeDescription],
+    ) -> Self {
+        self.vertex_bindings = bindings.to_vec();
+        self.vertex_attributes = attributes.to_vec();
+        self
+    }
+
+    pub fn topology(mut self, topology: vk::PrimitiveTopology, primitive_restart: bool) -> Self {
+        self.topology = topology;
+        self.primitive_restart = primitive_restart;
+        self
+    }
+
+    /// `LINE` and `POINT` need the `fillModeNonSolid` feature.
+    pub fn polygon_mode(mut self, polygon_mode: vk::PolygonMode) -> Self {
+        self.polygon_mode = polygon_mode;
+        self
+    }
+
+    pub fn cull_mode(mut self, cull_mode: vk::CullModeFlags, front_face: vk::FrontFace) -> Self {
+        self.cull_mode = cull_mode;
+        self.front_face = front_face;
+        self
+    }
+
+    /// Widths other than 1 need the `wideLines` feature.
+    pub fn line_width(mut self, line_width: f32) -> Self {
+        self.line_width = line_width;
+        self
+    }
+
+    pub fn depth(mut self, test: bool, write: bool, compare_op: vk::CompareOp) -> Self {
+        self.depth_test = test;
+        self.depth_write = write;
+        self.depth_compare_op = compare_op;
+        self
+    }
+
+    /// Neither test nor write depth, e.g. for UI and full-screen passes.
+    pub fn no_depth(self) -> Self {
+        self.depth(false, false, vk::CompareOp::ALWAYS)
+    }
+
+    pub fn stencil(mut self, front: vk::StencilOpState, back: vk::StencilOpState) -> Self {
+        self.stencil = Some((front, back));
+        self
+    }
+
+    /// One entry per color attachment of the subpass.
+    pub fn color_attachments(mut self, blend_modes: &[BlendMode]) -> Self {
+        self.color_attachments = blend_modes.to_vec();
+        self
+    }
+
+    pub fn blend_mode(self, blend_mode: BlendMode) -> Self {
+        self.color_attachments(&[blend_mode])
+    }
+
+    pub fn samples(mut self, samples: vk::SampleCountFlags) -> Self {
+        self.samples = samples;
+        self
+    }
+
+    /// Bakes the viewport and scissor into the pipeline instead of leaving them dynamic.
+    pub fn fixed_extent(mut self, extent: vk::Extent2D) -> Self {
+        self.fixed_extent = Some(extent);
+        self.dynamic_states
+            .retain(|&state| state != vk::DynamicState::VIEWPORT && state != vk::DynamicState::SCISSOR);
+        self
+    }
+
+    pub fn dynamic_state(mut self, state: vk::DynamicState) -> Self {
+        if !self.dynamic_states.contains(&state) {
+            self.dynamic_states.push(state);
+        }
+        self
+    }
+
+    pub fn dynamic_states(&self) -> &[vk::DynamicState] {
+        &self.dynamic_states
+    }
+
+    pub fn rasterization_state(&self) -> vk::PipelineRasterizationStateCreateInfo<'static> {
+        vk::PipelineRasterizationStateCreateInfo {
+            s_type: vk::StructureType::PIPELINE_RASTERIZATION_STATE_CREATE_INFO,
+            depth_clamp_enable: vk::FALSE,
+            rasterizer_discard_enable: vk::FALSE,
+            polygon_mode: self.polygon_mode,
+            cull_mode: self.cull_mode,
+            front_face: self.front_face,
+            depth_bias_enable: vk::FALSE,
+            line_width: self.line_width,
+            ..Default::default()
+        }
+    }
+
+    pub fn depth_stencil_state(&self) -> vk::PipelineDepthStencilStateCreateInfo<'static> {
+        let keep = vk::StencilOpState {
+            fail_op: vk::StencilOp::KEEP,
+            pass_op: vk::StencilOp::KEEP,
+            depth_fail_op: vk::StencilOp::KEEP,
+            compare_op: vk::CompareOp::ALWAYS,
+            ..Default::default()
+        };
+        let (front, back) = self.stencil.unwrap_or((keep, keep));
+        vk::PipelineDepthStencilStateCreateInfo {
+            s_type: vk::StructureType::PIPELINE_DEPTH_STENCIL_STATE_CREATE_INFO,
+            depth_test_enable: self.depth_test as vk::Bool32,
+            depth_write_enable: self.depth_write as vk::Bool32,
+            depth_compare_op: self.depth_compare_op,
+            depth_bounds_test_enable: vk::FALSE,
+            stencil_test_enable: self.stencil.is_some() as vk::Bool32,
+            front,
+            back,
+            min_depth_bounds: 0.0,
+            max_depth_bounds: 1.0,
+            ..Default::default()
+        }
+    }
+
+    pub fn color_blend_attachments(&self) -> Vec<vk::PipelineColorBlendAttachmentState> {
+        self.color_attachments.iter().map(|mode| mode.attachment_state()).collect()
+    }
+
+    /// Creates the pipeline for `target`.
+    pub fn build(
+        &self,
+        device: &ash::Device,
+        pipeline_cache: vk::PipelineCache,
+        layout: vk::PipelineLayout,
+        target: PipelineTarget,
+    ) -> EngineResult<vk::Pipeline> {
+        let specialization_infos: Vec<_> = self
+            .stages
+            .iter()
+            .map(|stage| vk::SpecializationInfo {
+                map_entry_count: stage.specialization.entries.len() as u32,
+                p_map_entries: stage.specialization.entries.as_ptr(),
+                data_size: stage.specialization.data.len(),
+                p_data: stage.specialization.data.as_ptr().cast(),
+                ..Default::default()
+            })
+            .collect();
+        let shader_stages: Vec<_> = self
+            .stages
+            .iter()
+            .zip(&specialization_infos)
+            .map(|(stage, specialization_info)| vk::PipelineShaderStageCreateInfo {
+                s_type: vk::StructureType::PIPELINE_SHADER_STAGE_CREATE_INFO,
+                stage: stage.stage,
+                module: stage.module,
+                p_name: stage.entry_point.as_ptr(),
+                p_specialization_info: if stage.specialization.is_empty() {
+                    ptr::null()
+                } else {
+                    specialization_info
+                },
+                ..Default::default()
+            })
+            .collect();
+
+        let vertex_input_state = vk::PipelineVertexInputStateCreateInfo {
+            s_type: vk::StructureType::PIPELINE_VERTEX_INPUT_STATE_CREATE_INFO,
+            vertex_binding_description_count: self.vertex_bindings.len() as u32,
+            p_vertex_binding_descriptions: self.vertex_bindings.as_ptr(),
+            vertex_attribute_description_count: self.vertex_attributes.len() as u32,
+            p_vertex_attribute_descriptions: self.vertex_attributes.as_ptr(),
+            ..Default::default()
+        };
+        let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo {
+            s_type: vk::StructureType::PIPELINE_INPUT_ASSEMBLY_STATE_CREATE_INFO,
+            topology: self.topology,
+            primitive_restart_enable: self.primitive_restart as vk::Bool32,
+            ..Default::default()
+        };
+
+        // Ignored where dynamic, but the counts still have to be given
+        let extent = self.fixed_extent.unwrap_or_default();
+        let viewports = [full_viewport(extent)];
+        let scissors = [full_scissor(extent)];
+        let viewport_state = vk::PipelineViewportStateCreateInfo {
+            s_type: vk::StructureType::PIPELINE_VIEWPORT_STATE_CREATE_INFO,
+            viewport_count: viewports.len() as u32,
+            p_viewports: viewports.as_ptr(),
+            scissor_count: scissors.len() as u32,
+            p_scissors: scissors.as_ptr(),
+            ..Default::default()
+        };
+
+        let rasterization_state = self.rasterization_state();
+        let multisample_state = vk::PipelineMultisampleStateCreateInfo {
+            s_type: vk::StructureType::PIPELINE_MULTISAMPLE_STATE_CREATE_INFO,
+            rasterization_samples: self.samples,
+            sample_shading_enable: vk::FALSE,
+            min_sample_shading: 0.0,
+            alpha_to_coverage_enable: vk::FALSE,
+            alpha_to_one_enable: vk::FALSE,
+            ..Default::default()
+        };
+        let depth_stencil_state = self.depth_stencil_state();
+
+        let color_blend_attachments = self.color_blend_attachments();
+        let color_blend_state = vk::PipelineColorBlendStateCreateInfo {
+            s_type: vk::StructureType::PIPELINE_COLOR_BLEND_STATE_CREATE_INFO,
+            logic_op_enable: vk::FALSE,
+            logic_op: vk::LogicOp::COPY,
+            attachment_count: color_blend_attachments.len() as u32,
+            p_attachments: color_blend_attachments.as_ptr(),
+            blend_constants: [0.0; 4],
+            ..Default::default()
+        };
+
+        let dynamic_state = vk::PipelineDynamicStateCreateInfo {
+            s_type: vk::StructureType::PIPELINE_DYNAMIC_STATE_CREATE_INFO,
+            dynamic_state_count: self.dynamic_states.len() as u32,
+            p_dynamic_states: self.dynamic_states.as_ptr(),
+            ..Default::default()
+        };
+
+        let (render_pass, subpass, rendering_info) = match target {
+            PipelineTarget::RenderPass { render_pass, subpass } => (render_pass, subpass, None),
+            PipelineTarget::Dynamic { color_formats, depth_format } => (
+                vk::RenderPass::null(),
+                0,
+                Some(vk::PipelineRenderingCreateInfo {
+                    s_type: vk::StructureType::PIPELINE_RENDERING_CREATE_INFO,
+                    color_attachment_count: color_formats.len() as u32,
+                    p_color_attachment_formats: color_formats.as_ptr(),
+                    depth_attachment_format: depth_format,
+                    stencil_attachment_format: vk::Format::UNDEFINED,
+                    ..Default::default()
+                }),
+            ),
+        };
+
+        let pipeline_create_infos = [vk::GraphicsPipelineCreateInfo {
+            s_type: vk::StructureType::GRAPHICS_PIPELINE_CREATE_INFO,
+            p_next: match &rendering_info {
+                Some(rendering_info) => rendering_info as *const _ as *const std::ffi::c_void,
+                None => ptr::null(),
+            },
+            stage_count: shader_stages.len() as u32,
+            p_stages: shader_stages.as_ptr(),
+            p_vertex_input_state: &vertex_input_state,
+            p_input_assembly_state: &input_assembly_state,
+            p_viewport_state: &viewport_state,
+            p_rasterization_state: &rasterization_state,
+            p_multisample_state: &multisample_state,
+            p_depth_stencil_state: &depth_stencil_state,
+            p_color_blend_state: &color_blend_state,
+            p_dynamic_state: if self.dynamic_states.is_empty() {
+                ptr::null()
+            } else {
+                &dynamic_state
+            },
+            layout,
+            render_pass,
+            subpass,
+            base_pipeline_handle: vk::Pipeline::null(),
+            base_pipeline_index: -1,
+            ..Default::default()
+        }];
+
+        let pipelines = unsafe {
+            device
+                .create_graphics_pipelines(pipeline_cache, &pipeline_create_infos, None)
+                .map_err(|(_, result)| result)
+                .context("create graphics pipeline")?
+        };
+        Ok(pipelines[0])
+    }
+}
+
+pub fn create_pipeline_layout(
+    device: &ash::Device,
+    set_layouts: &[vk::DescriptorSetLayout],
+    push_constant_ranges: &[vk::PushConstantRange],
+) -> EngineResult<vk::PipelineLayout> {
+    let pipeline_layout_create_info = vk::PipelineLayoutCreateInfo {
+        s_type: vk::StructureType::PIPELINE_LAYOUT_CREATE_INFO,
+        set_layout_count: set_layouts.len() as u32,
+        p_set_layouts: set_layouts.as_ptr(),
+        push_constant_range_count: push_constant_ranges.len() as u32,
+        p_push_constant_ranges: push_constant_ranges.as_ptr(),
+        ..Default::default()
+    };
+
+    unsafe {
+        device
+            .create_pipeline_layout(&pipeline_layout_create_info, None)
+            .context("create pipeline layout")
+    }
+}
+
+pub fn full_viewport(extent: vk::Extent2D) -> vk::Viewport {
+    vk::Viewport {
+        x: 0.0,
+        y: 0.0,
+        width: extent.width as f32,
+        height: extent.height as f32,
+        min_depth: 0.0,
+        max_depth: 1.0,
+    }
+}
+
+pub fn full_scissor(extent: vk::Extent2D) -> vk::Rect2D {
+    vk::Rect2D {
+        offset: vk::Offset2D { x: 0, y: 0 },
+        extent,
+    }
+}
+
+/// Covers all of `extent`; needed after binding any pipeline built with the default dynamic state.
+pub fn set_viewport_and_scissor(device: &ash::Device, command_buffer: vk::CommandBuffer, extent: vk::Extent2D) {
+    unsafe {
+        device.cmd_set_viewport(command_buffer, 0, &[full_viewport(extent)]);
+        device.cmd_set_scissor(command_buffer, 0, &[full_scissor(extent)]);
+    }
+}
@@ -0,0 +1,195 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use ash::vk;
+
+use crate::vulkan::error::*;
+
+/// Overrides the directory the pipeline cache is kept in; an empty value disables the file.
+pub const PIPELINE_CACHE_DIR_ENV: &str = "VOXEL_PIPELINE_CACHE_DIR";
+
+pub const PIPELINE_CACHE_FILE: &str = "pipeline_cache.bin";
+
+const CACHE_FILE_MAGIC: [u8; 4] = *b"VXPC";
+const CACHE_FILE_VERSION: u32 = 1;
+/// Magic, version, vendor, device, driver version, UUID, data length and checksum.
+const CACHE_FILE_HEADER_SIZE: usize = 4 + 4 + 4 + 4 + 4 + vk::UUID_SIZE + 8 + 8;
+
+/// The device and driver a cache was written by. Drivers reject foreign data themselves, but not
+/// always gracefully, so a cache from anything else is dropped before it gets that far.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheKey {
+    pub vendor_id: u32,
+    pub device_id: u32,
+    pub driver_version: u32,
+    pub pipeline_cache_uuid: [u8; vk::UUID_SIZE],
+}
+
+impl CacheKey {
+    pub fn new(properties: &vk::PhysicalDeviceProperties) -> CacheKey {
+        CacheKey {
+            vendor_id: properties.vendor_id,
+            device_id: properties.device_id,
+            driver_version: properties.driver_version,
+            pipeline_cache_uuid: properties.pipeline_cache_uuid,
+        }
+    }
+}
+
+/// `PIPELINE_CACHE_DIR_ENV` if set, otherwise the per-user cache directory of the platform:
+/// `$XDG_CACHE_HOME` or `~/.cache` on Linux, `~/Library/Caches` on macOS and `%LOCALAPPDATA%` on
+/// Windows, each with a `voxel_engine` subdirectory.
+pub fn default_cache_dir() -> Option<PathBuf> {
+    if let Some(dir) = std::env::var_os(PIPELINE_CACHE_DIR_ENV) {
+        return (!dir.is_empty()).then(|| PathBuf::from(dir));
+    }
+    let env_dir = |name| std::env::var_os(name).filter(|dir| !dir.is_empty()).map(PathBuf::from);
+    let base = if cfg!(windows) {
+        env_dir("LOCALAPPDATA")?
+    } else if cfg!(target_os = "macos") {
+        env_dir("HOME")?.join("Library").join("Caches")
+    } else {
+        env_dir("XDG_CACHE_HOME").or_else(|| Some(env_dir("HOME")?.join(".cache")))?
+    };
+    Some(base.join("voxel_engine"))
+}
+
+/// FNV-1a, enough to notice a torn or bit-flipped file.
+fn checksum(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3))
+}
+
+/// Prefixes the driver's cache data with `key`, its length and a checksum.
+pub fn encode_cache_file(key: &CacheKey, data: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(CACHE_FILE_HEADER_SIZE + data.len());
+    bytes.extend_from_slice(&CACHE_FILE_MAGIC);
+    bytes.extend_from_slice(&CACHE_FILE_VERSION.to_le_bytes());
+    bytes.extend_from_slice(&key.vendor_id.to_le_bytes());
+    bytes.extend_from_slice(&key.device_id.to_le_bytes());
+    bytes.extend_from_slice(&key.driver_version.to_le_bytes());
+    bytes.extend_from_slice(&key.pipeline_cache_uuid);
+    bytes.extend_from_slice(&(data.len() as u64).to_le_bytes());
+    bytes.extend_from_slice(&checksum(data).to_le_bytes());
+    bytes.extend_from_slice(data);
+    bytes
+}
+
+/// The driver's cache data from a file written by `encode_cache_file`, if it was written for `key`
+/// and is intact.
+pub fn decode_cache_file<'a>(key: &CacheKey, bytes: &'a [u8]) -> Result<&'a [u8], String> {
+    if bytes.len() < CACHE_FILE_HEADER_SIZE || bytes[..4] != CACHE_FILE_MAGIC {
+        return Err("not a pipeline cache file".to_string());
+    }
+    let (header, data) = bytes.split_at(CACHE_FILE_HEADER_SIZE);
+    let u32_at = |offset: usize| u32::from_le_bytes(header[offset..offset + 4].try_into().unwrap());
+    let u64_at = |offset: usize| u64::from_le_bytes(header[offset..offset + 8].try_into().unwrap());
+
+    let version = u32_at(4);
+    if version != CACHE_FILE_VERSION {
+        return Err(format!("file version {} instead of {}", version, CACHE_FILE_VERSION));
+    }
+    let written_by = CacheKey {
+        vendor_id: u32_at(8),
+        device_id: u32_at(12),
+        driver_version: u32_at(16),
+        pipeline_cache_uuid: header[20..20 + vk::UUID_SIZE].try_into().unwrap(),
+    };
+    if written_by != *key {
+        return Err(format!("written by a different device or driver: {:?}", written_by));
+    }
+    let length = u64_at(20 + vk::UUID_SIZE);
+    if length != data.len() as u64 {
+        return Err(format!("expected {} bytes of data, found {}", length, data.len()));
+    }
+    if u64_at(28 + vk::UUID_SIZE) != checksum(data) {
+        return Err("checksum mismatch".to_string());
+    }
+    Ok(data)
+}
+
+fn create_pipeline_cache(device: &ash::Device, initial_data: &[u8]) -> EngineResult<vk::PipelineCache> {
+    let pipeline_cache_create_info = vk::PipelineCacheCreateInfo {
+        s_type: vk::StructureType::PIPELINE_CACHE_CREATE_INFO,
+        initial_data_size: initial_data.len(),
+        p_initial_data: initial_data.as_ptr().cast(),
+        ..Default::default()
+    };
+
+    unsafe {
+        device
+            .create_pipeline_cache(&pipeline_cache_create_info, None)
+            .context("create pipeline cache")
+    }
+}
+
+/// A `vk::PipelineCache` that starts from the file at `path` and is written back by `save`. A
+/// missing, foreign or corrupt file just means an empty cache. The handle is the caller's to
+/// destroy.
+pub struct PipelineCache {
+    handle: vk::PipelineCache,
+    key: CacheKey,
+    path: Option<PathBuf>,
+}
+
+impl PipelineCache {
+    /// `path` is usually `PIPELINE_CACHE_FILE` in `default_cache_dir`; `None` keeps the cache in
+    /// memory only.
+    pub fn load(device: &ash::Device, key: CacheKey, path: Option<PathBuf>) -> EngineResult<PipelineCache> {
+        let bytes = match path.as_deref().map(fs::read) {
+            Some(Ok(bytes)) => bytes,
+            Some(Err(e)) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Some(Err(e)) => {
+                log::warn!("Failed to read pipeline cache {:?}: {}", path.as_ref().unwrap(), e);
+                Vec::new()
+            }
+            None => Vec::new(),
+        };
+
+        let initial_data = match decode_cache_file(&key, &bytes) {
+            Ok(data) => data,
+            Err(_) if bytes.is_empty() => &[],
+            Err(e) => {
+                log::warn!("Discarding pipeline cache {:?}: {}", path.as_ref().unwrap(), e);
+                &[]
+            }
+        };
+        let handle = match create_pipeline_cache(device, initial_data) {
+            Err(e) if !initial_data.is_empty() => {
+                log::warn!("The driver rejected the pipeline cache, starting empty: {}", e);
+                create_pipeline_cache(device, &[])?
+            }
+            result => result?,
+        };
+        if !initial_data.is_empty() {
+            println!("Loaded pipeline cache ({} bytes)", initial_data.len());
+        }
+
+        Ok(PipelineCache { handle, key, path })
+    }
+
+    pub fn handle(&self) -> vk::PipelineCache {
+        self.handle
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Writes the current contents to `path`, through a temporary file so a crash halfway leaves
+    /// the previous cache in place.
+    pub fn save(&self, device: &ash::Device) -> EngineResult<()> {
+        let Some(path) = self.path.as_ref() else {
+            return Ok(());
+        };
+        let data = unsafe { device.get_pipeline_cache_data(self.handle).context("get pipeline cache data")? };
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let temp_path = path.with_extension("tmp");
+        fs::write(&temp_path, encode_cache_file(&self.key, &data))?;
+        fs::rename(&temp_path, path)?;
+        println!("Saved pipeline cache ({} bytes) to {}", data.len(), path.display());
+        Ok(())
+    }
+}
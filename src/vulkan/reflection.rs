@@ -0,0 +1,695 @@
+use std::collections::HashMap;
+
+use ash::vk;
+
+const SPIRV_MAGIC: u32 = 0x0723_0203;
+const HEADER_WORDS: usize = 5;
+
+// Opcodes
+const OP_NAME: u32 = 5;
+const OP_ENTRY_POINT: u32 = 15;
+const OP_TYPE_BOOL: u32 = 20;
+const OP_TYPE_INT: u32 = 21;
+const OP_TYPE_FLOAT: u32 = 22;
+const OP_TYPE_VECTOR: u32 = 23;
+const OP_TYPE_MATRIX: u32 = 24;
+const OP_TYPE_IMAGE: u32 = 25;
+const OP_TYPE_SAMPLER: u32 = 26;
+const OP_TYPE_SAMPLED_IMAGE: u32 = 27;
+const OP_TYPE_ARRAY: u32 = 28;
+const OP_TYPE_RUNTIME_ARRAY: u32 = 29;
+const OP_TYPE_STRUCT: u32 = 30;
+const OP_TYPE_POINTER: u32 = 32;
+const OP_CONSTANT: u32 = 43;
+const OP_VARIABLE: u32 = 59;
+const OP_DECORATE: u32 = 71;
+const OP_MEMBER_DECORATE: u32 = 72;
+const OP_TYPE_ACCELERATION_STRUCTURE: u32 = 5341;
+
+// Decorations
+const DECORATION_BLOCK: u32 = 2;
+const DECORATION_BUFFER_BLOCK: u32 = 3;
+const DECORATION_ARRAY_STRIDE: u32 = 6;
+const DECORATION_MATRIX_STRIDE: u32 = 7;
+const DECORATION_BUILT_IN: u32 = 11;
+const DECORATION_LOCATION: u32 = 30;
+const DECORATION_BINDING: u32 = 33;
+const DECORATION_DESCRIPTOR_SET: u32 = 34;
+const DECORATION_OFFSET: u32 = 35;
+
+// Storage classes
+const STORAGE_UNIFORM_CONSTANT: u32 = 0;
+const STORAGE_INPUT: u32 = 1;
+const STORAGE_UNIFORM: u32 = 2;
+const STORAGE_OUTPUT: u32 = 3;
+const STORAGE_PUSH_CONSTANT: u32 = 9;
+const STORAGE_STORAGE_BUFFER: u32 = 12;
+
+// Image dimensions
+const DIM_BUFFER: u32 = 5;
+const DIM_SUBPASS_DATA: u32 = 6;
+
+/// A `layout(location = N) in/out` variable.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InterfaceVariable {
+    pub location: u32,
+    /// The matching vertex attribute format, `UNDEFINED` for types without one (matrices,
+    /// arrays, structs).
+    pub format: vk::Format,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DescriptorBinding {
+    pub set: u32,
+    pub binding: u32,
+    pub descriptor_type: vk::DescriptorType,
+    /// Array length; 0 for runtime-sized arrays.
+    pub count: u32,
+    pub stages: vk::ShaderStageFlags,
+    pub name: String,
+}
+
+/// What one shader module expects from the pipeline around it.
+#[derive(Debug, Clone)]
+pub struct ShaderReflection {
+    pub stage: vk::ShaderStageFlags,
+    pub entry_point: String,
+    /// Sorted by location, built-ins left out.
+    pub inputs: Vec<InterfaceVariable>,
+    pub outputs: Vec<InterfaceVariable>,
+    /// Sorted by set and binding.
+    pub descriptor_bindings: Vec<DescriptorBinding>,
+    pub push_constants: Option<vk::PushConstantRange>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Type {
+    Bool,
+    Int { width: u32, signed: bool },
+    Float { width: u32 },
+    Vector { component: u32, count: u32 },
+    Matrix { column: u32, count: u32 },
+    Image { dim: u32, sampled: u32 },
+    Sampler,
+    SampledImage,
+    Array { element: u32, length: u32 },
+    RuntimeArray { element: u32 },
+    Struct { members: Vec<u32> },
+    Pointer { pointee: u32 },
+    AccelerationStructure,
+}
+
+#[derive(Debug, Default)]
+struct Decorations {
+    location: Option<u32>,
+    binding: Option<u32>,
+    set: Option<u32>,
+    array_stride: Option<u32>,
+    built_in: bool,
+    block: bool,
+    buffer_block: bool,
+}
+
+#[derive(Debug, Default)]
+struct MemberDecorations {
+    offset: Option<u32>,
+    matrix_stride: Option<u32>,
+    built_in: bool,
+}
+
+struct Variable {
+    id: u32,
+    pointer_type: u32,
+    storage_class: u32,
+}
+
+/// The parts of a module reflection needs, indexed by result id.
+#[derive(Default)]
+struct Module {
+    entry_point: Option<(u32, String)>,
+    names: HashMap<u32, String>,
+    types: HashMap<u32, Type>,
+    constants: HashMap<u32, u32>,
+    decorations: HashMap<u32, Decorations>,
+    member_decorations: HashMap<(u32, u32), MemberDecorations>,
+    variables: Vec<Variable>,
+}
+
+/// A nul-terminated UTF-8 string packed into words.
+fn parse_string(words: &[u32]) -> String {
+    let bytes: Vec<u8> = words
+        .iter()
+        .flat_map(|word| word.to_le_bytes())
+        .take_while(|&byte| byte != 0)
+        .collect();
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+impl Module {
+    fn parse(words: &[u32]) -> Result<Module, String> {
+        if words.len() < HEADER_WORDS || words[0] != SPIRV_MAGIC {
+            return Err("not a SPIR-V module".to_string());
+        }
+
+        let mut module = Module::default();
+        let mut position = HEADER_WORDS;
+        while position < words.len() {
+            let word_count = (words[position] >> 16) as usize;
+            let opcode = words[position] & 0xffff;
+            if word_count == 0 || position + word_count > words.len() {
+                return Err(format!("truncated instruction at word {}", position));
+            }
+            let operands = &words[position + 1..position + word_count];
+            position += word_count;
+
+            let operand = |index: usize| {
+                operands
+                    .get(index)
+                    .copied()
+                    .ok_or_else(|| format!("opcode {} is missing operand {}", opcode, index))
+            };
+            match opcode {
+                OP_ENTRY_POINT if module.entry_point.is_none() => {
+                    module.entry_point = Some((operand(0)?, parse_string(operands.get(2..).unwrap_or_default())));
+                }
+                OP_NAME => {
+                    module.names.insert(operand(0)?, parse_string(operands.get(1..).unwrap_or_default()));
+                }
+                OP_TYPE_BOOL => {
+                    module.types.insert(operand(0)?, Type::Bool);
+                }
+                OP_TYPE_INT => {
+                    module.types.insert(operand(0)?, Type::Int { width: operand(1)?, signed: operand(2)? != 0 });
+                }
+                OP_TYPE_FLOAT => {
+                    module.types.insert(operand(0)?, Type::Float { width: operand(1)? });
+                }
+                OP_TYPE_VECTOR => {
+                    module.types.insert(operand(0)?, Type::Vector { component: operand(1)?, count: operand(2)? });
+                }
+                OP_TYPE_MATRIX => {
+                    module.types.insert(operand(0)?, Type::Matrix { column: operand(1)?, count: operand(2)? });
+                }
+                OP_TYPE_IMAGE => {
+                    module.types.insert(operand(0)?, Type::Image { dim: operand(2)?, sampled: operand(6)? });
+                }
+                OP_TYPE_SAMPLER => {
+                    module.types.insert(operand(0)?, Type::Sampler);
+                }
+                OP_TYPE_SAMPLED_IMAGE => {
+                    module.types.insert(operand(0)?, Type::SampledImage);
+                }
+                OP_TYPE_ARRAY => {
+                    module.types.insert(operand(0)?, Type::Array { element: operand(1)?, length: operand(2)? });
+                }
+                OP_TYPE_RUNTIME_ARRAY => {
+                    module.types.insert(operand(0)?, Type::RuntimeArray { element: operand(1)? });
+                }
+                OP_TYPE_STRUCT => {
+                    module.types.insert(operand(0)?, Type::Struct { members: operands[1..].to_vec() });
+                }
+                OP_TYPE_POINTER => {
+                    module.types.insert(operand(0)?, Type::Pointer { pointee: operand(2)? });
+                }
+                OP_TYPE_ACCELERATION_STRUCTURE => {
+                    module.types.insert(operand(0)?, Type::AccelerationStructure);
+                }
+                // Only the low word matters: array lengths
+                OP_CONSTANT => {
+                    module.constants.insert(operand(1)?, operand(2)?);
+                }
+                OP_VARIABLE => module.variables.push(Variable {
+                    pointer_type: operand(0)?,
+                    id: operand(1)?,
+                    storage_class: operand(2)?,
+                }),
+                OP_DECORATE => {
+                    let decorations = module.decorations.entry(operand(0)?).or_default();
+                    match operand(1)? {
+                        DECORATION_BLOCK => decorations.block = true,
+                        DECORATION_BUFFER_BLOCK => decorations.buffer_block = true,
+                        DECORATION_ARRAY_STRIDE => decorations.array_stride = Some(operand(2)?),
+                        DECORATION_BUILT_IN => decorations.built_in = true,
+                        DECORATION_LOCATION => decorations.location = Some(operand(2)?),
+                        DECORATION_BINDING => decorations.binding = Some(operand(2)?),
+                        DECORATION_DESCRIPTOR_SET => decorations.set = Some(operand(2)?),
+                        _ => (),
+                    }
+                }
+                OP_MEMBER_DECORATE => {
+                    let decorations = module.member_decorations.entry((operand(0)?, operand(1)?)).or_default();
+                    match operand(2)? {
+                        DECORATION_OFFSET => decorations.offset = Some(operand(3)?),
+                        DECORATION_MATRIX_STRIDE => decorations.matrix_stride = Some(operand(3)?),
+                        DECORATION_BUILT_IN => decorations.built_in = true,
+                        _ => (),
+                    }
+                }
+                _ => (),
+            }
+        }
+        Ok(module)
+    }
+
+    fn get_type(&self, id: u32) -> Result<&Type, String> {
+        self.types.get(&id).ok_or_else(|| format!("unknown type %{}", id))
+    }
+
+    fn decorations(&self, id: u32) -> Option<&Decorations> {
+        self.decorations.get(&id)
+    }
+
+    fn name(&self, id: u32) -> String {
+        self.names.get(&id).filter(|name| !name.is_empty()).cloned().unwrap_or_else(|| format!("%{}", id))
+    }
+
+    fn pointee(&self, pointer_type: u32) -> Result<u32, String> {
+        match self.get_type(pointer_type)? {
+            Type::Pointer { pointee } => Ok(*pointee),
+            other => Err(format!("variable type %{} is not a pointer: {:?}", pointer_type, other)),
+        }
+    }
+
+    /// `gl_Position` and friends, either directly or as members of `gl_PerVertex`.
+    fn is_built_in(&self, variable: u32, type_id: u32) -> bool {
+        if self.decorations(variable).is_some_and(|decorations| decorations.built_in) {
+            return true;
+        }
+        let mut type_id = type_id;
+        while let Some(Type::Array { element, .. } | Type::RuntimeArray { element }) = self.types.get(&type_id) {
+            type_id = *element;
+        }
+        match self.types.get(&type_id) {
+            Some(Type::Struct { members }) => (0..members.len() as u32).any(|member| {
+                self.member_decorations
+                    .get(&(type_id, member))
+                    .is_some_and(|decorations| decorations.built_in)
+            }),
+            _ => false,
+        }
+    }
+
+    fn format(&self, type_id: u32) -> Result<vk::Format, String> {
+        let (component, count) = match self.get_type(type_id)? {
+            Type::Vector { component, count } => (*component, *count),
+            _ => (type_id, 1),
+        };
+        let format = match (self.get_type(component)?, count) {
+            (Type::Float { width: 32 }, 1) => vk::Format::R32_SFLOAT,
+            (Type::Float { width: 32 }, 2) => vk::Format::R32G32_SFLOAT,
+            (Type::Float { width: 32 }, 3) => vk::Format::R32G32B32_SFLOAT,
+            (Type::Float { width: 32 }, 4) => vk::Format::R32G32B32A32_SFLOAT,
+            (Type::Float { width: 64 }, 1) => vk::Format::R64_SFLOAT,
+            (Type::Float { width: 64 }, 2) => vk::Format::R64G64_SFLOAT,
+            (Type::Float { width: 64 }, 3) => vk::Format::R64G64B64_SFLOAT,
+            (Type::Float { width: 64 }, 4) => vk::Format::R64G64B64A64_SFLOAT,
+            (Type::Int { width: 32, signed: true }, 1) => vk::Format::R32_SINT,
+            (Type::Int { width: 32, signed: true }, 2) => vk::Format::R32G32_SINT,
+            (Type::Int { width: 32, signed: true }, 3) => vk::Format::R32G32B32_SINT,
+            (Type::Int { width: 32, signed: true }, 4) => vk::Format::R32G32B32A32_SINT,
+            (Type::Int { width: 32, signed: false }, 1) => vk::Format::R32_UINT,
+            (Type::Int { width: 32, signed: false }, 2) => vk::Format::R32G32_UINT,
+            (Type::Int { width: 32, signed: false }, 3) => vk::Format::R32G32B32_UINT,
+            (Type::Int { width: 32, signed: false }, 4) => vk::Format::R32G32B32A32_UINT,
+            _ => vk::Format::UNDEFINED,
+        };
+        Ok(format)
+    }
+
+    /// Size in bytes under the explicit layout the module declares (offsets and strides).
+    fn size(&self, type_id: u32, matrix_stride: Option<u32>) -> Result<u32, String> {
+        Ok(match self.get_type(type_id)? {
+            Type::Bool => 4,
+            Type::Int { width, .. } | Type::Float { width } => width / 8,
+            Type::Vector { component, count } => count * self.size(*component, None)?,
+            Type::Matrix { column, count } => match matrix_stride {
+                Some(stride) => count * stride,
+                None => count * self.size(*column, None)?,
+            },
+            Type::Array { element, length } => {
+                let length = self.constants.get(length).copied().unwrap_or(0);
+                let stride = match self.decorations(type_id).and_then(|decorations| decorations.array_stride) {
+                    Some(stride) => stride,
+                    None => self.size(*element, None)?,
+                };
+                length * stride
+            }
+            Type::Struct { members } => {
+                let mut size = 0;
+                for (index, &member) in members.iter().enumerate() {
+                    let decorations = self.member_decorations.get(&(type_id, index as u32));
+                    let offset = decorations.and_then(|decorations| decorations.offset).unwrap_or(size);
+                    let matrix_stride = decorations.and_then(|decorations| decorations.matrix_stride);
+                    size = size.max(offset + self.size(member, matrix_stride)?);
+                }
+                size
+            }
+            Type::RuntimeArray { .. } => 0,
+            other => return Err(format!("type %{} has no size: {:?}", type_id, other)),
+        })
+    }
+
+    /// The lowest member offset of a push constant block.
+    fn first_offset(&self, type_id: u32) -> u32 {
+        match self.types.get(&type_id) {
+            Some(Type::Struct { members }) => (0..members.len() as u32)
+                .filter_map(|member| self.member_decorations.get(&(type_id, member))?.offset)
+                .min()
+                .unwrap_or(0),
+            _ => 0,
+        }
+    }
+
+    fn descriptor_type(&self, storage_class: u32, type_id: u32) -> Result<vk::DescriptorType, String> {
+        let block = |id| self.decorations(id).is_some_and(|decorations| decorations.block);
+        let buffer_block = |id| self.decorations(id).is_some_and(|decorations| decorations.buffer_block);
+        Ok(match (storage_class, self.get_type(type_id)?) {
+            (STORAGE_STORAGE_BUFFER, _) => vk::DescriptorType::STORAGE_BUFFER,
+            (STORAGE_UNIFORM, _) if buffer_block(type_id) => vk::DescriptorType::STORAGE_BUFFER,
+            (STORAGE_UNIFORM, _) if block(type_id) => vk::DescriptorType::UNIFORM_BUFFER,
+            (_, Type::SampledImage) => vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            (_, Type::Sampler) => vk::DescriptorType::SAMPLER,
+            (_, Type::AccelerationStructure) => vk::DescriptorType::ACCELERATION_STRUCTURE_KHR,
+            (_, Type::Image { dim: DIM_BUFFER, sampled: 1 }) => vk::DescriptorType::UNIFORM_TEXEL_BUFFER,
+            (_, Type::Image { dim: DIM_BUFFER, .. }) => vk::DescriptorType::STORAGE_TEXEL_BUFFER,
+            (_, Type::Image { dim: DIM_SUBPASS_DATA, .. }) => vk::DescriptorType::INPUT_ATTACHMENT,
+            (_, Type::Image { sampled: 2, .. }) => vk::DescriptorType::STORAGE_IMAGE,
+            (_, Type::Image { .. }) => vk::DescriptorType::SAMPLED_IMAGE,
+            (_, other) => return Err(format!("type %{} is not a descriptor: {:?}", type_id, other)),
+        })
+    }
+}
+
+fn stage_for_execution_model(model: u32) -> Result<vk::ShaderStageFlags, String> {
+    Ok(match model {
+        0 => vk::ShaderStageFlags::VERTEX,
+        1 => vk::ShaderStageFlags::TESSELLATION_CONTROL,
+        2 => vk::ShaderStageFlags::TESSELLATION_EVALUATION,
+        3 => vk::ShaderStageFlags::GEOMETRY,
+        4 => vk::ShaderStageFlags::FRAGMENT,
+        5 => vk::ShaderStageFlags::COMPUTE,
+        _ => return Err(format!("unsupported execution model {}", model)),
+    })
+}
+
+impl ShaderReflection {
+    /// Reflects the first entry point of a SPIR-V module, as loaded by `load_shader`.
+    pub fn new(words: &[u32]) -> Result<ShaderReflection, String> {
+        let module = Module::parse(words)?;
+        let (execution_model, entry_point) = module.entry_point.clone().ok_or("no entry point")?;
+        let stage = stage_for_execution_model(execution_model)?;
+
+        let mut reflection = ShaderReflection {
+            stage,
+            entry_point,
+            inputs: Vec::new(),
+            outputs: Vec::new(),
+            descriptor_bindings: Vec::new(),
+            push_constants: None,
+        };
+        for variable in &module.variables {
+            let pointee = module.pointee(variable.pointer_type)?;
+            let decorations = module.decorations(variable.id);
+            match variable.storage_class {
+                STORAGE_INPUT | STORAGE_OUTPUT => {
+                    if module.is_built_in(variable.id, pointee) {
+                        continue;
+                    }
+                    let location = decorations
+                        .and_then(|decorations| decorations.location)
+                        .ok_or_else(|| format!("{} has no location", module.name(variable.id)))?;
+                    let interface_variable = InterfaceVariable {
+                        location,
+                        format: module.format(pointee)?,
+                        name: module.name(variable.id),
+                    };
+                    if variable.storage_class == STORAGE_INPUT {
+                        reflection.inputs.push(interface_variable);
+                    } else {
+                        reflection.outputs.push(interface_variable);
+                    }
+                }
+                STORAGE_UNIFORM_CONSTANT | STORAGE_UNIFORM | STORAGE_STORAGE_BUFFER => {
+                    let (Some(set), Some(binding)) = (
+                        decorations.and_then(|decorations| decorations.set),
+                        decorations.and_then(|decorations| decorations.binding),
+                    ) else {
+                        return Err(format!("{} has no descriptor set or binding", module.name(variable.id)));
+                    };
+                    let (element, count) = match module.get_type(pointee)? {
+                        Type::Array { element, length } => (*element, module.constants.get(length).copied().unwrap_or(1)),
+                        Type::RuntimeArray { element } => (*element, 0),
+                        _ => (pointee, 1),
+                    };
+                    reflection.descriptor_bindings.push(DescriptorBinding {
+                        set,
+                        binding,
+                        descriptor_type: module.descriptor_type(variable.storage_class, element)?,
+                        count,
+                        stages: stage,
+                        name: module.name(variable.id),
+                    });
+                }
+                STORAGE_PUSH_CONSTANT => {
+                    let offset = module.first_offset(pointee);
+                    reflection.push_constants = Some(vk::PushConstantRange {
+                        stage_flags: stage,
+                        offset,
+                        size: module.size(pointee, None)? - offset,
+                    });
+                }
+                _ => (),
+            }
+        }
+        reflection.inputs.sort_by_key(|variable| variable.location);
+        reflection.outputs.sort_by_key(|variable| variable.location);
+        reflection.descriptor_bindings.sort_by_key(|binding| (binding.set, binding.binding));
+        Ok(reflection)
+    }
+
+    /// Tightly packed attributes for every input of a vertex shader, in location order, and the
+    /// resulting stride.
+    pub fn vertex_attributes(&self, binding: u32) -> Result<(Vec<vk::VertexInputAttributeDescription>, u32), String> {
+        let mut offset = 0;
+        let mut attributes = Vec::new();
+        for input in &self.inputs {
+            let size = format_size(input.format)
+                .ok_or_else(|| format!("vertex input {} has no attribute format", input.name))?;
+            attributes.push(vk::VertexInputAttributeDescription {
+                location: input.location,
+                binding,
+                format: input.format,
+                offset,
+            });
+            offset += size;
+        }
+        Ok((attributes, offset))
+    }
+}
+
+/// Bytes per element of the formats `ShaderReflection` reports.
+fn format_size(format: vk::Format) -> Option<u32> {
+    Some(match format {
+        vk::Format::R32_SFLOAT | vk::Format::R32_SINT | vk::Format::R32_UINT => 4,
+        vk::Format::R32G32_SFLOAT | vk::Format::R32G32_SINT | vk::Format::R32G32_UINT | vk::Format::R64_SFLOAT => 8,
+        vk::Format::R32G32B32_SFLOAT | vk::Format::R32G32B32_SINT | vk::Format::R32G32B32_UINT => 12,
+        vk::Format::R32G32B32A32_SFLOAT
+        | vk::Format::R32G32B32A32_SINT
+        | vk::Format::R32G32B32A32_UINT
+        | vk::Format::R64G64_SFLOAT => 16,
+        vk::Format::R64G64B64_SFLOAT => 24,
+        vk::Format::R64G64B64A64_SFLOAT => 32,
+        _ => return None,
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum NumericType {
+    Float,
+    SInt,
+    UInt,
+}
+
+/// What a shader reading an attribute of `format` sees. Normalized and scaled formats read as
+/// floats. Covers the formats usable as vertex attributes.
+fn numeric_type(format: vk::Format) -> Option<NumericType> {
+    use vk::Format as F;
+    Some(match format {
+        F::R8_SINT | F::R8G8_SINT | F::R8G8B8_SINT | F::B8G8R8_SINT | F::R8G8B8A8_SINT | F::B8G8R8A8_SINT
+        | F::A8B8G8R8_SINT_PACK32
+        | F::A2R10G10B10_SINT_PACK32
+        | F::A2B10G10R10_SINT_PACK32
+        | F::R16_SINT | F::R16G16_SINT | F::R16G16B16_SINT | F::R16G16B16A16_SINT
+        | F::R32_SINT | F::R32G32_SINT | F::R32G32B32_SINT | F::R32G32B32A32_SINT
+        | F::R64_SINT | F::R64G64_SINT | F::R64G64B64_SINT | F::R64G64B64A64_SINT => NumericType::SInt,
+
+        F::R8_UINT | F::R8G8_UINT | F::R8G8B8_UINT | F::B8G8R8_UINT | F::R8G8B8A8_UINT | F::B8G8R8A8_UINT
+        | F::A8B8G8R8_UINT_PACK32
+        | F::A2R10G10B10_UINT_PACK32
+        | F::A2B10G10R10_UINT_PACK32
+        | F::R16_UINT | F::R16G16_UINT | F::R16G16B16_UINT | F::R16G16B16A16_UINT
+        | F::R32_UINT | F::R32G32_UINT | F::R32G32B32_UINT | F::R32G32B32A32_UINT
+        | F::R64_UINT | F::R64G64_UINT | F::R64G64B64_UINT | F::R64G64B64A64_UINT => NumericType::UInt,
+
+        F::R8_UNORM | F::R8_SNORM | F::R8_USCALED | F::R8_SSCALED | F::R8_SRGB
+        | F::R8G8_UNORM | F::R8G8_SNORM | F::R8G8_USCALED | F::R8G8_SSCALED | F::R8G8_SRGB
+        | F::R8G8B8_UNORM | F::R8G8B8_SNORM | F::R8G8B8_USCALED | F::R8G8B8_SSCALED | F::R8G8B8_SRGB
+        | F::B8G8R8_UNORM | F::B8G8R8_SNORM | F::B8G8R8_USCALED | F::B8G8R8_SSCALED | F::B8G8R8_SRGB
+        | F::R8G8B8A8_UNORM | F::R8G8B8A8_SNORM | F::R8G8B8A8_USCALED | F::R8G8B8A8_SSCALED | F::R8G8B8A8_SRGB
+        | F::B8G8R8A8_UNORM | F::B8G8R8A8_SNORM | F::B8G8R8A8_USCALED | F::B8G8R8A8_SSCALED | F::B8G8R8A8_SRGB
+        | F::A8B8G8R8_UNORM_PACK32
+        | F::A8B8G8R8_SNORM_PACK32
+        | F::A8B8G8R8_USCALED_PACK32
+        | F::A8B8G8R8_SSCALED_PACK32
+        | F::A8B8G8R8_SRGB_PACK32
+        | F::A2R10G10B10_UNORM_PACK32
+        | F::A2R10G10B10_SNORM_PACK32
+        | F::A2R10G10B10_USCALED_PACK32
+        | F::A2R10G10B10_SSCALED_PACK32
+        | F::A2B10G10R10_UNORM_PACK32
+        | F::A2B10G10R10_SNORM_PACK32
+        | F::A2B10G10R10_USCALED_PACK32
+        | F::A2B10G10R10_SSCALED_PACK32
+        | F::B10G11R11_UFLOAT_PACK32
+        | F::R16_UNORM | F::R16_SNORM | F::R16_USCALED | F::R16_SSCALED | F::R16_SFLOAT
+        | F::R16G16_UNORM | F::R16G16_SNORM | F::R16G16_USCALED | F::R16G16_SSCALED | F::R16G16_SFLOAT
+        | F::R16G16B16_UNORM | F::R16G16B16_SNORM | F::R16G16B16_USCALED | F::R16G16B16_SSCALED | F::R16G16B16_SFLOAT
+        | F::R16G16B16A16_UNORM
+        | F::R16G16B16A16_SNORM
+        | F::R16G16B16A16_USCALED
+        | F::R16G16B16A16_SSCALED
+        | F::R16G16B16A16_SFLOAT
+        | F::R32_SFLOAT | F::R32G32_SFLOAT | F::R32G32B32_SFLOAT | F::R32G32B32A32_SFLOAT
+        | F::R64_SFLOAT | F::R64G64_SFLOAT | F::R64G64B64_SFLOAT | F::R64G64B64A64_SFLOAT => NumericType::Float,
+
+        _ => return None,
+    })
+}
+
+/// The combined interface of all shader stages of one pipeline.
+#[derive(Debug, Clone)]
+pub struct PipelineInterface {
+    pub stages: Vec<ShaderReflection>,
+    /// Bindings used by several stages appear once, with all their stages.
+    pub descriptor_bindings: Vec<DescriptorBinding>,
+}
+
+impl PipelineInterface {
+    /// Merges the stages, given in pipeline order, and checks that each stage's inputs are
+    /// written by the stage before it and that shared bindings agree.
+    pub fn new(stages: Vec<ShaderReflection>) -> Result<PipelineInterface, String> {
+        for pair in stages.windows(2) {
+            let (previous, next) = (&pair[0], &pair[1]);
+            for input in &next.inputs {
+                match previous.outputs.iter().find(|output| output.location == input.location) {
+                    Some(output) if output.format == input.format => (),
+                    Some(output) => {
+                        return Err(format!(
+                            "{:?} input {} at location {} is {:?}, but {:?} writes {:?}",
+                            next.stage, input.name, input.location, input.format, previous.stage, output.format
+                        ))
+                    }
+                    None => {
+                        return Err(format!(
+                            "{:?} input {} at location {} isn't written by {:?}",
+                            next.stage, input.name, input.location, previous.stage
+                        ))
+                    }
+                }
+            }
+        }
+
+        let mut descriptor_bindings: Vec<DescriptorBinding> = Vec::new();
+        for binding in stages.iter().flat_map(|stage| &stage.descriptor_bindings) {
+            match descriptor_bindings
+                .iter_mut()
+                .find(|existing| (existing.set, existing.binding) == (binding.set, binding.binding))
+            {
+                Some(existing) if (existing.descriptor_type, existing.count) == (binding.descriptor_type, binding.count) => {
+                    existing.stages |= binding.stages;
+                }
+                Some(existing) => {
+                    return Err(format!(
+                        "set {} binding {} is {} {:?} in {:?} but {} {:?} in {:?}",
+                        binding.set,
+                        binding.binding,
+                        existing.count,
+                        existing.descriptor_type,
+                        existing.stages,
+                        binding.count,
+                        binding.descriptor_type,
+                        binding.stages
+                    ))
+                }
+                None => descriptor_bindings.push(binding.clone()),
+            }
+        }
+        descriptor_bindings.sort_by_key(|binding| (binding.set, binding.binding));
+
+        Ok(PipelineInterface { stages, descriptor_bindings })
+    }
+
+    pub fn stage(&self, stage: vk::ShaderStageFlags) -> Option<&ShaderReflection> {
+        self.stages.iter().find(|reflection| reflection.stage == stage)
+    }
+
+    /// One range per stage that uses push constants, ready for `vk::PipelineLayoutCreateInfo`.
+    pub fn push_constant_ranges(&self) -> Vec<vk::PushConstantRange> {
+        self.stages.iter().filter_map(|stage| stage.push_constants).collect()
+    }
+
+    /// Checks that every binding the shaders use in `set` is in `layout` with a compatible type,
+    /// enough descriptors and visible to the stages that use it.
+    pub fn validate_set_layout(&self, set: u32, layout: &[vk::DescriptorSetLayoutBinding]) -> Result<(), String> {
+        for binding in self.descriptor_bindings.iter().filter(|binding| binding.set == set) {
+            let Some(layout_binding) = layout.iter().find(|layout_binding| layout_binding.binding == binding.binding) else {
+                return Err(format!("{} (set {} binding {}) is missing from the layout", binding.name, set, binding.binding));
+            };
+            let layout_type = match layout_binding.descriptor_type {
+                vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC => vk::DescriptorType::UNIFORM_BUFFER,
+                vk::DescriptorType::STORAGE_BUFFER_DYNAMIC => vk::DescriptorType::STORAGE_BUFFER,
+                other => other,
+            };
+            if layout_type != binding.descriptor_type {
+                return Err(format!(
+                    "{} (set {} binding {}) is {:?} in the shaders but {:?} in the layout",
+                    binding.name, set, binding.binding, binding.descriptor_type, layout_binding.descriptor_type
+                ));
+            }
+            if layout_binding.descriptor_count < binding.count.max(1) {
+                return Err(format!(
+                    "{} (set {} binding {}) needs {} descriptors but the layout has {}",
+                    binding.name, set, binding.binding, binding.count, layout_binding.descriptor_count
+                ));
+            }
+            if !layout_binding.stage_flags.contains(binding.stages) {
+                return Err(format!(
+                    "{} (set {} binding {}) is used in {:?} but the layout only covers {:?}",
+                    binding.name, set, binding.binding, binding.stages, layout_binding.stage_flags
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that every vertex shader input has an attribute of the same numeric type. Unused
+    /// attributes and differing component counts are fine, as in Vulkan itself.
+    pub fn validate_vertex_input(&self, attributes: &[vk::VertexInputAttributeDescription]) -> Result<(), String> {
+        let Some(vertex) = self.stage(vk::ShaderStageFlags::VERTEX) else {
+            return Ok(());
+        };
+        for input in &vertex.inputs {
+            let Some(attribute) = attributes.iter().find(|attribute| attribute.location == input.location) else {
+                return Err(format!("vertex input {} at location {} has no attribute", input.name, input.location));
+            };
+            if numeric_type(attribute.format).is_none() || numeric_type(attribute.format) != numeric_type(input.format) {
+                return Err(format!(
+                    "vertex input {} at location {} is {:?} but its attribute is {:?}",
+                    input.name, input.location, input.format, attribute.format
+                ));
+            }
+        }
+        Ok(())
+    }
+}
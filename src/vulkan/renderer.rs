@@ -0,0 +1,232 @@
+use std::ptr;
+use std::time::Duration;
+
+use ash::vk;
+
+use crate::vulkan::depth::*;
+use crate::vulkan::descriptors::*;
+use crate::vulkan::error::*;
+use crate::vulkan::mesh::*;
+use crate::vulkan::pipeline::*;
+use crate::vulkan::rendering::*;
+
+/// Anything that draws into the main pass: the world, entities, UI. `record` is called once per
+/// frame while the command buffer is being recorded, so what gets drawn can change every frame.
+pub trait RenderSubsystem {
+    /// Advances the subsystem by one fixed simulation step.
+    fn tick(&mut self, _dt: Duration) {}
+
+    fn record(&mut self, frame: &mut FrameContext<'_>);
+}
+
+/// What a `RenderSubsystem` gets to record with. The scene pipeline and set 0 (camera) are
+/// already bound.
+pub struct FrameContext<'a> {
+    pub device: &'a ash::Device,
+    pub command_buffer: vk::CommandBuffer,
+    /// Index of the frame in flight being recorded, for per-frame resources.
+    pub frame_index: usize,
+    /// Position between the previous and the latest simulation tick, for interpolating motion.
+    pub alpha: f32,
+    pub extent: vk::Extent2D,
+    pub pipeline_layout: vk::PipelineLayout,
+}
+
+impl FrameContext<'_> {
+    pub fn draw_mesh(&mut self, mesh: &Mesh) {
+        mesh.draw(self.device, self.command_buffer);
+    }
+}
+
+/// Meshes that are drawn every frame as they are. Stands in for the world until chunks exist.
+#[derive(Default)]
+pub struct StaticScene {
+    pub meshes: Vec<Mesh>,
+}
+
+impl RenderSubsystem for StaticScene {
+    fn record(&mut self, frame: &mut FrameContext<'_>) {
+        for mesh in &self.meshes {
+            frame.draw_mesh(mesh);
+        }
+    }
+}
+
+/// Where the main pass draws, depending on the `RenderPath`.
+#[derive(Debug, Clone, Copy)]
+pub enum SceneTarget {
+    RenderPass {
+        render_pass: vk::RenderPass,
+        framebuffer: vk::Framebuffer,
+    },
+    Dynamic(RenderingAttachments),
+}
+
+/// The target and pipeline state one frame is recorded against.
+#[derive(Debug, Clone, Copy)]
+pub struct ScenePass {
+    pub target: SceneTarget,
+    pub pipeline: vk::Pipeline,
+    pub pipeline_layout: vk::PipelineLayout,
+    pub descriptor_set: vk::DescriptorSet,
+    pub extent: vk::Extent2D,
+}
+
+/// A command pool with a single primary command buffer for one frame in flight. The pool is reset
+/// wholesale before every recording.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FrameCommands {
+    pub command_pool: vk::CommandPool,
+    pub command_buffer: vk::CommandBuffer,
+}
+
+impl FrameCommands {
+    /// Creates `count` pools on `queue_family`. The buffers are freed with their pools.
+    pub fn create(device: &ash::Device, queue_family: u32, count: usize) -> EngineResult<Vec<FrameCommands>> {
+        let mut frames: Vec<FrameCommands> = Vec::with_capacity(count);
+        for _ in 0..count {
+            match FrameCommands::create_one(device, queue_family) {
+                Ok(frame) => frames.push(frame),
+                Err(e) => {
+                    for frame in frames {
+                        unsafe { device.destroy_command_pool(frame.command_pool, None) };
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        Ok(frames)
+    }
+
+    fn create_one(device: &ash::Device, queue_family: u32) -> EngineResult<FrameCommands> {
+        let command_pool_create_info = vk::CommandPoolCreateInfo {
+            s_type: vk::StructureType::COMMAND_POOL_CREATE_INFO,
+            flags: vk::CommandPoolCreateFlags::TRANSIENT,
+            queue_family_index: queue_family,
+            ..Default::default()
+        };
+        let command_pool = unsafe {
+            device
+                .create_command_pool(&command_pool_create_info, None)
+                .context("create frame command pool")?
+        };
+
+        let command_buffer_allocate_info = vk::CommandBufferAllocateInfo {
+            s_type: vk::StructureType::COMMAND_BUFFER_ALLOCATE_INFO,
+            command_buffer_count: 1,
+            command_pool,
+            level: vk::CommandBufferLevel::PRIMARY,
+            ..Default::default()
+        };
+        match unsafe { device.allocate_command_buffers(&command_buffer_allocate_info) } {
+            Ok(buffers) => Ok(FrameCommands { command_pool, command_buffer: buffers[0] }),
+            Err(e) => {
+                unsafe { device.destroy_command_pool(command_pool, None) };
+                Err(e).context("allocate frame command buffer")
+            }
+        }
+    }
+
+    /// Resets the pool and starts recording. The frame's previous submission must have finished.
+    pub fn begin(&self, device: &ash::Device) -> EngineResult<vk::CommandBuffer> {
+        let command_buffer_begin_info = vk::CommandBufferBeginInfo {
+            s_type: vk::StructureType::COMMAND_BUFFER_BEGIN_INFO,
+            flags: vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT,
+            ..Default::default()
+        };
+
+        unsafe {
+            device
+                .reset_command_pool(self.command_pool, vk::CommandPoolResetFlags::empty())
+                .context("reset frame command pool")?;
+            device
+                .begin_command_buffer(self.command_buffer, &command_buffer_begin_info)
+                .context("begin recording command buffer")?;
+        }
+        Ok(self.command_buffer)
+    }
+
+    pub fn end(&self, device: &ash::Device) -> EngineResult<()> {
+        unsafe {
+            device
+                .end_command_buffer(self.command_buffer)
+                .context("end recording command buffer")
+        }
+    }
+}
+
+/// Records the main pass into `command_buffer`: clears `pass.target` and its depth buffer, binds
+/// the scene pipeline, its viewport and scissor and the camera set, then lets every subsystem add
+/// its draws. Shared by the window and the headless renderer so both draw the same thing.
+pub fn record_scene(
+    device: &ash::Device,
+    command_buffer: vk::CommandBuffer,
+    pass: &ScenePass,
+    frame_index: usize,
+    alpha: f32,
+    subsystems: &mut [Box<dyn RenderSubsystem>],
+) {
+    let clear_color = [0.0, 0.0, 0.0, 1.0];
+    match &pass.target {
+        SceneTarget::RenderPass { render_pass, framebuffer } => {
+            let clear_values = [
+                vk::ClearValue {
+                    color: vk::ClearColorValue { float32: clear_color },
+                },
+                vk::ClearValue {
+                    depth_stencil: DEPTH_CLEAR_VALUE,
+                },
+            ];
+            let render_pass_begin_info = vk::RenderPassBeginInfo {
+                s_type: vk::StructureType::RENDER_PASS_BEGIN_INFO,
+                p_next: ptr::null(),
+                render_pass: *render_pass,
+                framebuffer: *framebuffer,
+                render_area: vk::Rect2D {
+                    offset: vk::Offset2D { x: 0, y: 0 },
+                    extent: pass.extent,
+                },
+                clear_value_count: clear_values.len() as u32,
+                p_clear_values: clear_values.as_ptr(),
+                ..Default::default()
+            };
+            unsafe {
+                device.cmd_begin_render_pass(
+                    command_buffer,
+                    &render_pass_begin_info,
+                    vk::SubpassContents::INLINE,
+                );
+            }
+        }
+        SceneTarget::Dynamic(attachments) => {
+            begin_rendering(device, command_buffer, attachments, pass.extent, clear_color);
+        }
+    }
+
+    unsafe {
+        device.cmd_bind_pipeline(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            pass.pipeline,
+        );
+    }
+    set_viewport_and_scissor(device, command_buffer, pass.extent);
+    bind_descriptor_set(device, command_buffer, pass.pipeline_layout, pass.descriptor_set);
+
+    let mut frame = FrameContext {
+        device,
+        command_buffer,
+        frame_index,
+        alpha,
+        extent: pass.extent,
+        pipeline_layout: pass.pipeline_layout,
+    };
+    for subsystem in subsystems.iter_mut() {
+        subsystem.record(&mut frame);
+    }
+
+    match &pass.target {
+        SceneTarget::RenderPass { .. } => unsafe { device.cmd_end_render_pass(command_buffer) },
+        SceneTarget::Dynamic(attachments) => end_rendering(device, command_buffer, attachments),
+    }
+}
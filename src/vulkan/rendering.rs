@@ -0,0 +1,233 @@
+use ash::vk;
+
+use crate::vulkan::depth::*;
+
+/// Forces how passes are recorded: `renderpass` or `dynamic`, e.g. `VOXEL_RENDER_PATH=renderpass`.
+pub const RENDER_PATH_ENV: &str = "VOXEL_RENDER_PATH";
+
+/// How a pass gets its attachments: a `vk::RenderPass` with framebuffers built ahead of time, or
+/// Vulkan 1.3 dynamic rendering, which takes image views when recording and needs neither.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RenderPath {
+    #[default]
+    RenderPass,
+    Dynamic,
+}
+
+impl RenderPath {
+    pub fn parse(value: &str) -> Option<RenderPath> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "renderpass" | "render_pass" | "legacy" => Some(RenderPath::RenderPass),
+            "dynamic" | "dynamic_rendering" => Some(RenderPath::Dynamic),
+            _ => None,
+        }
+    }
+
+    /// Dynamic rendering wherever the device supports it, unless `requested` says otherwise.
+    /// Requesting it on a device without support falls back to render passes.
+    pub fn select(requested: Option<RenderPath>, dynamic_supported: bool) -> RenderPath {
+        match requested {
+            Some(RenderPath::Dynamic) if !dynamic_supported => {
+                log::warn!("Dynamic rendering requested but not supported, using render passes");
+                RenderPath::RenderPass
+            }
+            Some(path) => path,
+            None if dynamic_supported => RenderPath::Dynamic,
+            None => RenderPath::RenderPass,
+        }
+    }
+
+    /// `select` with the request from `RENDER_PATH_ENV`.
+    pub fn from_env(dynamic_supported: bool) -> RenderPath {
+        let requested = std::env::var(RENDER_PATH_ENV).ok().and_then(|value| {
+            let path = RenderPath::parse(&value);
+            if path.is_none() {
+                log::warn!("Ignoring unknown {}={}", RENDER_PATH_ENV, value);
+            }
+            path
+        });
+        RenderPath::select(requested, dynamic_supported)
+    }
+}
+
+/// The images one dynamic-rendering pass draws into.
+#[derive(Debug, Clone, Copy)]
+pub struct RenderingAttachments {
+    /// What the pass produces: the swapchain image or the offscreen target. Cleared and stored,
+    /// or resolved into when `msaa_color` is set.
+    pub target: vk::Image,
+    pub target_view: vk::ImageView,
+    /// Multisampled color that is resolved into `target` and then discarded.
+    pub msaa_color: Option<(vk::Image, vk::ImageView)>,
+    pub depth: vk::Image,
+    pub depth_view: vk::ImageView,
+    pub depth_format: vk::Format,
+    /// Layout `target` is left in: `PRESENT_SRC_KHR` or `TRANSFER_SRC_OPTIMAL`.
+    pub final_layout: vk::ImageLayout,
+}
+
+fn color_range() -> vk::ImageSubresourceRange {
+    vk::ImageSubresourceRange {
+        aspect_mask: vk::ImageAspectFlags::COLOR,
+        base_mip_level: 0,
+        level_count: 1,
+        base_array_layer: 0,
+        layer_count: 1,
+    }
+}
+
+/// Color attachments start from `UNDEFINED` every frame since they are cleared anyway. Waiting on
+/// `COLOR_ATTACHMENT_OUTPUT` chains with the swapchain acquire semaphore, which is waited on at
+/// that stage.
+fn color_attachment_barrier(image: vk::Image) -> vk::ImageMemoryBarrier2<'static> {
+    vk::ImageMemoryBarrier2 {
+        s_type: vk::StructureType::IMAGE_MEMORY_BARRIER_2,
+        src_stage_mask: vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+        src_access_mask: vk::AccessFlags2::NONE,
+        dst_stage_mask: vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+        dst_access_mask: vk::AccessFlags2::COLOR_ATTACHMENT_WRITE,
+        old_layout: vk::ImageLayout::UNDEFINED,
+        new_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+        dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+        image,
+        subresource_range: color_range(),
+        ..Default::default()
+    }
+}
+
+impl RenderingAttachments {
+    /// Moves every attachment into its attachment layout. The depth buffer is shared between
+    /// frames in flight, so the previous frame's depth writes have to finish first.
+    pub fn begin_barriers(&self) -> Vec<vk::ImageMemoryBarrier2<'static>> {
+        let depth_stages = vk::PipelineStageFlags2::EARLY_FRAGMENT_TESTS | vk::PipelineStageFlags2::LATE_FRAGMENT_TESTS;
+        let mut barriers = vec![color_attachment_barrier(self.target)];
+        if let Some((msaa_color, _)) = self.msaa_color {
+            barriers.push(color_attachment_barrier(msaa_color));
+        }
+        barriers.push(vk::ImageMemoryBarrier2 {
+            s_type: vk::StructureType::IMAGE_MEMORY_BARRIER_2,
+            src_stage_mask: depth_stages,
+            src_access_mask: vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            dst_stage_mask: depth_stages,
+            dst_access_mask: vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_READ | vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            old_layout: vk::ImageLayout::UNDEFINED,
+            new_layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            image: self.depth,
+            subresource_range: vk::ImageSubresourceRange {
+                aspect_mask: depth_aspect_mask(self.depth_format),
+                ..color_range()
+            },
+            ..Default::default()
+        });
+        barriers
+    }
+
+    /// Moves `target` into `final_layout` once the pass has written it.
+    pub fn end_barrier(&self) -> vk::ImageMemoryBarrier2<'static> {
+        let (dst_stage_mask, dst_access_mask) = match self.final_layout {
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL => (vk::PipelineStageFlags2::TRANSFER, vk::AccessFlags2::TRANSFER_READ),
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL => {
+                (vk::PipelineStageFlags2::FRAGMENT_SHADER, vk::AccessFlags2::SHADER_SAMPLED_READ)
+            }
+            // Presentation is ordered by the render-finished semaphore
+            _ => (vk::PipelineStageFlags2::NONE, vk::AccessFlags2::NONE),
+        };
+        vk::ImageMemoryBarrier2 {
+            s_type: vk::StructureType::IMAGE_MEMORY_BARRIER_2,
+            src_stage_mask: vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+            src_access_mask: vk::AccessFlags2::COLOR_ATTACHMENT_WRITE,
+            dst_stage_mask,
+            dst_access_mask,
+            old_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            new_layout: self.final_layout,
+            src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            image: self.target,
+            subresource_range: color_range(),
+            ..Default::default()
+        }
+    }
+}
+
+fn pipeline_barrier(device: &ash::Device, command_buffer: vk::CommandBuffer, barriers: &[vk::ImageMemoryBarrier2]) {
+    let dependency_info = vk::DependencyInfo {
+        s_type: vk::StructureType::DEPENDENCY_INFO,
+        image_memory_barrier_count: barriers.len() as u32,
+        p_image_memory_barriers: barriers.as_ptr(),
+        ..Default::default()
+    };
+    unsafe { device.cmd_pipeline_barrier2(command_buffer, &dependency_info) };
+}
+
+/// Transitions the attachments and begins rendering into them, clearing color to `clear_color`
+/// and depth to `DEPTH_CLEAR_VALUE`. Pair with `end_rendering`.
+pub fn begin_rendering(
+    device: &ash::Device,
+    command_buffer: vk::CommandBuffer,
+    attachments: &RenderingAttachments,
+    extent: vk::Extent2D,
+    clear_color: [f32; 4],
+) {
+    pipeline_barrier(device, command_buffer, &attachments.begin_barriers());
+
+    let clear_color = vk::ClearValue {
+        color: vk::ClearColorValue { float32: clear_color },
+    };
+    let color_attachment = match attachments.msaa_color {
+        Some((_, msaa_view)) => vk::RenderingAttachmentInfo {
+            s_type: vk::StructureType::RENDERING_ATTACHMENT_INFO,
+            image_view: msaa_view,
+            image_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            resolve_mode: vk::ResolveModeFlags::AVERAGE,
+            resolve_image_view: attachments.target_view,
+            resolve_image_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            load_op: vk::AttachmentLoadOp::CLEAR,
+            store_op: vk::AttachmentStoreOp::DONT_CARE,
+            clear_value: clear_color,
+            ..Default::default()
+        },
+        None => vk::RenderingAttachmentInfo {
+            s_type: vk::StructureType::RENDERING_ATTACHMENT_INFO,
+            image_view: attachments.target_view,
+            image_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            load_op: vk::AttachmentLoadOp::CLEAR,
+            store_op: vk::AttachmentStoreOp::STORE,
+            clear_value: clear_color,
+            ..Default::default()
+        },
+    };
+    let depth_attachment = vk::RenderingAttachmentInfo {
+        s_type: vk::StructureType::RENDERING_ATTACHMENT_INFO,
+        image_view: attachments.depth_view,
+        image_layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+        load_op: vk::AttachmentLoadOp::CLEAR,
+        store_op: vk::AttachmentStoreOp::DONT_CARE,
+        clear_value: vk::ClearValue {
+            depth_stencil: DEPTH_CLEAR_VALUE,
+        },
+        ..Default::default()
+    };
+    let color_attachments = [color_attachment];
+    let rendering_info = vk::RenderingInfo {
+        s_type: vk::StructureType::RENDERING_INFO,
+        render_area: vk::Rect2D {
+            offset: vk::Offset2D { x: 0, y: 0 },
+            extent,
+        },
+        layer_count: 1,
+        color_attachment_count: color_attachments.len() as u32,
+        p_color_attachments: color_attachments.as_ptr(),
+        p_depth_attachment: &depth_attachment,
+        ..Default::default()
+    };
+
+    unsafe { device.cmd_begin_rendering(command_buffer, &rendering_info) };
+}
+
+pub fn end_rendering(device: &ash::Device, command_buffer: vk::CommandBuffer, attachments: &RenderingAttachments) {
+    unsafe { device.cmd_end_rendering(command_buffer) };
+    pipeline_barrier(device, command_buffer, &[attachments.end_barrier()]);
+}
@@ -0,0 +1,270 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, Instant, SystemTime};
+
+use ash::vk;
+
+use crate::vulkan::error::*;
+
+/// Shader sources and their prebuilt `.spv` files, relative to the repository root. `build.rs`
+/// compiles them and embeds the result in the binary; the prebuilt files are only embedded when
+/// `VOXEL_PREBUILT_SHADERS=1` is set on a machine without `glslc`.
+pub const SHADER_DIR: &str = "shaders";
+
+/// Loads `<name>.spv` from this directory instead of the embedded copy, and hot-reloads it when it
+/// changes. For development, e.g. `VOXEL_SHADER_DIR=shaders`.
+pub const SHADER_DIR_ENV: &str = "VOXEL_SHADER_DIR";
+
+/// Shaders of the scene pipeline, by source file name.
+pub const SCENE_VERTEX_SHADER: &str = "glsl.vert";
+pub const SCENE_FRAGMENT_SHADER: &str = "glsl.frag";
+
+/// Overrides the GLSL compiler used for hot-reload; `glslc` from `PATH` otherwise.
+pub const GLSLC_ENV: &str = "VOXEL_GLSLC";
+
+/// How often the shader directory is checked for changes.
+pub const SHADER_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+const SPIRV_MAGIC: u32 = 0x0723_0203;
+
+/// Keeps embedded SPIR-V aligned for viewing as `u32` words.
+#[repr(C, align(4))]
+struct AlignedSpirv<const N: usize>([u8; N]);
+
+// `EMBEDDED_SHADERS`: (source file name, SPIR-V) for every shader in `SHADER_DIR`
+include!(concat!(env!("OUT_DIR"), "/embedded_shaders.rs"));
+
+/// The SPIR-V compiled at build time from the shader source `name`.
+pub fn embedded_shader(name: &str) -> Option<&'static [u32]> {
+    let &(_, bytes) = EMBEDDED_SHADERS.iter().find(|&&(embedded, _)| embedded == name)?;
+    // `AlignedSpirv` keeps the bytes aligned and build.rs checked the length is a multiple of 4
+    let (prefix, words, suffix) = unsafe { bytes.align_to::<u32>() };
+    debug_assert!(prefix.is_empty() && suffix.is_empty());
+    Some(words)
+}
+
+/// The directory from `SHADER_DIR_ENV`, if set.
+pub fn shader_override_dir() -> Option<PathBuf> {
+    std::env::var_os(SHADER_DIR_ENV).filter(|dir| !dir.is_empty()).map(PathBuf::from)
+}
+
+/// Where the compiled SPIR-V for the shader source `name` lives in `dir`.
+pub fn shader_path(dir: &Path, name: &str) -> PathBuf {
+    dir.join(format!("{}.spv", name))
+}
+
+/// `glsl.vert` compiles to `glsl.vert.spv` next to it.
+pub fn spv_path(source: &Path) -> PathBuf {
+    let mut file_name = source.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".spv");
+    source.with_file_name(file_name)
+}
+
+pub fn is_glsl_source(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|extension| extension.to_str()),
+        Some("vert" | "frag" | "comp" | "geom" | "tesc" | "tese")
+    )
+}
+
+/// Cheap sanity checks before handing bytes to the driver: word-aligned size, a complete header
+/// and the SPIR-V magic number.
+pub fn validate_spirv(bytes: &[u8]) -> Result<(), String> {
+    if !bytes.len().is_multiple_of(4) {
+        return Err(format!("{} bytes is not a whole number of words", bytes.len()));
+    }
+    if bytes.len() < 20 {
+        return Err(format!("{} bytes is too short for a SPIR-V header", bytes.len()));
+    }
+    let magic = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    if magic != SPIRV_MAGIC {
+        return Err(format!("bad magic number {:#010x}", magic));
+    }
+    Ok(())
+}
+
+/// Little-endian SPIR-V bytes as words.
+pub fn spirv_words(bytes: &[u8]) -> Vec<u32> {
+    bytes
+        .chunks_exact(4)
+        .map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]]))
+        .collect()
+}
+
+pub(crate) fn read_shader_code(shader_path: &Path) -> EngineResult<Vec<u32>> {
+    let shader_error = |reason: String| EngineError::ShaderLoading {
+        path: shader_path.to_path_buf(),
+        reason,
+    };
+
+    let bytes_code = fs::read(shader_path).map_err(|e| shader_error(e.to_string()))?;
+    validate_spirv(&bytes_code).map_err(shader_error)?;
+
+    Ok(spirv_words(&bytes_code))
+}
+
+/// The SPIR-V for the shader source `name`: from the override directory if it has the file,
+/// embedded otherwise.
+pub(crate) fn load_shader(name: &str) -> EngineResult<Cow<'static, [u32]>> {
+    if let Some(dir) = shader_override_dir() {
+        let path = shader_path(&dir, name);
+        if path.is_file() {
+            return read_shader_code(&path).map(Cow::Owned);
+        }
+    }
+    embedded_shader(name).map(Cow::Borrowed).ok_or_else(|| EngineError::ShaderLoading {
+        path: Path::new(SHADER_DIR).join(name),
+        reason: "not embedded at build time".to_string(),
+    })
+}
+
+pub(crate) fn create_shader_module(device: &ash::Device, code: &[u32]) -> EngineResult<vk::ShaderModule> {
+    let shader_module_create_info = vk::ShaderModuleCreateInfo {
+        s_type: vk::StructureType::SHADER_MODULE_CREATE_INFO,
+        flags: vk::ShaderModuleCreateFlags::empty(),
+        code_size: std::mem::size_of_val(code),
+        p_code: code.as_ptr(),
+        ..Default::default()
+    };
+
+    unsafe {
+        device
+            .create_shader_module(&shader_module_create_info, None)
+            .context("create shader module")
+    }
+}
+
+/// Runs `glslc` on `source`, writing SPIR-V to `output`. Returns the compiler's diagnostics on
+/// failure; `output` is left alone in that case.
+pub fn compile_glsl(glslc: &Path, source: &Path, output: &Path) -> Result<(), String> {
+    let result = Command::new(glslc)
+        .arg(source)
+        .arg("-o")
+        .arg(output)
+        .output()
+        .map_err(|e| format!("failed to run {}: {}", glslc.display(), e))?;
+    if !result.status.success() {
+        return Err(String::from_utf8_lossy(&result.stderr).trim().to_string());
+    }
+    Ok(())
+}
+
+/// Notices new and modified files in one directory by comparing modification times. Polling a
+/// handful of files keeps this free of platform-specific watcher APIs.
+pub struct ShaderWatcher {
+    dir: PathBuf,
+    interval: Duration,
+    next_poll: Option<Instant>,
+    modified: HashMap<PathBuf, SystemTime>,
+}
+
+impl ShaderWatcher {
+    /// Files that already exist don't count as changed.
+    pub fn new(dir: impl Into<PathBuf>, interval: Duration) -> ShaderWatcher {
+        let dir = dir.into();
+        let modified = scan(&dir);
+        ShaderWatcher {
+            dir,
+            interval,
+            next_poll: None,
+            modified,
+        }
+    }
+
+    /// Files that appeared or changed since the last scan, sorted. Doesn't scan at all until
+    /// `interval` has passed since the previous one.
+    pub fn poll(&mut self, now: Instant) -> Vec<PathBuf> {
+        if self.next_poll.is_some_and(|next_poll| now < next_poll) {
+            return Vec::new();
+        }
+        self.next_poll = Some(now + self.interval);
+
+        let current = scan(&self.dir);
+        let mut changed: Vec<_> = current
+            .iter()
+            .filter(|&(path, modified)| self.modified.get(path) != Some(modified))
+            .map(|(path, _)| path.clone())
+            .collect();
+        changed.sort();
+        self.modified = current;
+        changed
+    }
+
+    /// Records the current state of `path` so a change made by us isn't reported again.
+    pub fn refresh(&mut self, path: &Path) {
+        if let Ok(modified) = fs::metadata(path).and_then(|metadata| metadata.modified()) {
+            self.modified.insert(path.to_path_buf(), modified);
+        }
+    }
+}
+
+fn scan(dir: &Path) -> HashMap<PathBuf, SystemTime> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return HashMap::new();
+    };
+    entries
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let metadata = entry.metadata().ok()?;
+            metadata.is_file().then_some((entry.path(), metadata.modified().ok()?))
+        })
+        .collect()
+}
+
+/// Watches the shader directory: recompiles GLSL sources that change and reports SPIR-V files
+/// that changed and look valid, so the pipelines using them can be rebuilt.
+pub struct ShaderManager {
+    watcher: ShaderWatcher,
+    glslc: PathBuf,
+}
+
+impl ShaderManager {
+    pub fn new(dir: impl Into<PathBuf>) -> ShaderManager {
+        ShaderManager {
+            watcher: ShaderWatcher::new(dir, SHADER_POLL_INTERVAL),
+            glslc: std::env::var_os(GLSLC_ENV).map_or_else(|| PathBuf::from("glslc"), PathBuf::from),
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.watcher.dir
+    }
+
+    /// Uses `glslc` instead of the compiler from `GLSLC_ENV` or `PATH`.
+    pub fn with_glslc(mut self, glslc: impl Into<PathBuf>) -> ShaderManager {
+        self.glslc = glslc.into();
+        self
+    }
+
+    /// Returns the `.spv` files to reload. A source that fails to compile or a `.spv` that fails
+    /// validation is logged and left out, so whatever uses the old version keeps working.
+    pub fn poll(&mut self, now: Instant) -> Vec<PathBuf> {
+        let mut reloaded = Vec::new();
+        for path in self.watcher.poll(now) {
+            let spv = if is_glsl_source(&path) {
+                let output = spv_path(&path);
+                if let Err(e) = compile_glsl(&self.glslc, &path, &output) {
+                    log::error!("Failed to compile {}: {}", path.display(), e);
+                    continue;
+                }
+                println!("Compiled {}", path.display());
+                self.watcher.refresh(&output);
+                output
+            } else if path.extension().is_some_and(|extension| extension == "spv") {
+                path
+            } else {
+                continue;
+            };
+
+            match fs::read(&spv).map_err(|e| e.to_string()).and_then(|bytes| validate_spirv(&bytes)) {
+                Ok(()) if !reloaded.contains(&spv) => reloaded.push(spv),
+                Ok(()) => (),
+                Err(e) => log::error!("Ignoring invalid SPIR-V {}: {}", spv.display(), e),
+            }
+        }
+        reloaded
+    }
+}
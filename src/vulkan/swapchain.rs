@@ -5,6 +5,86 @@ use ash::vk;
 use ash::Instance;
 use winit::window::Window;
 
+use crate::vulkan::device::QueueFamilies;
+use crate::vulkan::error::*;
+
+/// Overrides the present mode: `vsync`, `low_latency`, `uncapped` or `adaptive`.
+pub const PRESENT_MODE_ENV: &str = "VOXEL_PRESENT_MODE";
+/// Overrides how many images the swapchain asks for, e.g. `VOXEL_SWAPCHAIN_IMAGES=3`.
+pub const SWAPCHAIN_IMAGES_ENV: &str = "VOXEL_SWAPCHAIN_IMAGES";
+
+/// How presentation is paced. Anything the surface doesn't offer falls back to `Vsync`, the one
+/// mode every surface supports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PresentMode {
+    /// `FIFO`: waits for vertical blank, never tears.
+    Vsync,
+    /// `MAILBOX`: renders as fast as possible and presents the newest frame at vertical blank.
+    #[default]
+    LowLatency,
+    /// `IMMEDIATE`: presents right away and may tear.
+    Uncapped,
+    /// `FIFO_RELAXED`: vsync, but a late frame is presented right away and may tear.
+    Adaptive,
+}
+
+impl PresentMode {
+    pub const ALL: [PresentMode; 4] = [PresentMode::Vsync, PresentMode::LowLatency, PresentMode::Uncapped, PresentMode::Adaptive];
+
+    pub fn parse(value: &str) -> Option<PresentMode> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "vsync" | "fifo" => Some(PresentMode::Vsync),
+            "low_latency" | "mailbox" => Some(PresentMode::LowLatency),
+            "uncapped" | "immediate" => Some(PresentMode::Uncapped),
+            "adaptive" | "fifo_relaxed" => Some(PresentMode::Adaptive),
+            _ => None,
+        }
+    }
+
+    pub fn vk_mode(self) -> vk::PresentModeKHR {
+        match self {
+            PresentMode::Vsync => vk::PresentModeKHR::FIFO,
+            PresentMode::LowLatency => vk::PresentModeKHR::MAILBOX,
+            PresentMode::Uncapped => vk::PresentModeKHR::IMMEDIATE,
+            PresentMode::Adaptive => vk::PresentModeKHR::FIFO_RELAXED,
+        }
+    }
+
+    /// The next mode in `ALL`, wrapping around.
+    pub fn next(self) -> PresentMode {
+        let index = PresentMode::ALL.iter().position(|&mode| mode == self).unwrap();
+        PresentMode::ALL[(index + 1) % PresentMode::ALL.len()]
+    }
+}
+
+/// What the swapchain is created with; changing either takes a swapchain recreation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SwapchainSettings {
+    pub present_mode: PresentMode,
+    /// Images to ask for, clamped to what the surface allows. `None` asks for one more than the
+    /// minimum so the driver never has to wait for us to release one.
+    pub image_count: Option<u32>,
+}
+
+impl SwapchainSettings {
+    /// Applies `PRESENT_MODE_ENV` and `SWAPCHAIN_IMAGES_ENV` on top of these settings.
+    pub fn with_env_overrides(mut self) -> SwapchainSettings {
+        if let Ok(value) = std::env::var(PRESENT_MODE_ENV) {
+            match PresentMode::parse(&value) {
+                Some(present_mode) => self.present_mode = present_mode,
+                None => log::warn!("Ignoring unknown {}={}", PRESENT_MODE_ENV, value),
+            }
+        }
+        if let Ok(value) = std::env::var(SWAPCHAIN_IMAGES_ENV) {
+            match value.trim().parse() {
+                Ok(image_count) => self.image_count = Some(image_count),
+                Err(_) => log::warn!("Ignoring invalid {}={}", SWAPCHAIN_IMAGES_ENV, value),
+            }
+        }
+        self
+    }
+}
+
 pub struct SwapChainSupportDetails {
     pub capabilities: vk::SurfaceCapabilitiesKHR,
     pub formats: Vec<vk::SurfaceFormatKHR>,
@@ -18,49 +98,80 @@ pub struct SwapChainStuff {
     pub swapchain_images: Vec<vk::Image>,
     pub swapchain_format: vk::Format,
     pub swapchain_extent: vk::Extent2D,
+    /// What `SwapchainSettings::present_mode` ended up as on this surface.
+    pub present_mode: vk::PresentModeKHR,
 }
 
-fn query_swapchain_support(physical_device: vk::PhysicalDevice, surface: vk::SurfaceKHR, surface_loader: ash::khr::surface::Instance) -> SwapChainSupportDetails {
+fn query_swapchain_support(physical_device: vk::PhysicalDevice, surface: vk::SurfaceKHR, surface_loader: ash::khr::surface::Instance) -> EngineResult<SwapChainSupportDetails> {
     unsafe {
         let capabilities = surface_loader
             .get_physical_device_surface_capabilities(physical_device, surface)
-            .expect("Failed to query for surface capabilities.");
+            .surface_context("query surface capabilities")?;
         let formats = surface_loader
             .get_physical_device_surface_formats(physical_device, surface)
-            .expect("Failed to query for surface formats.");
+            .surface_context("query surface formats")?;
         let present_modes = surface_loader
             .get_physical_device_surface_present_modes(physical_device, surface)
-            .expect("Failed to query for surface present mode.");
+            .surface_context("query surface present modes")?;
 
-        SwapChainSupportDetails {
+        if formats.is_empty() {
+            return Err(EngineError::Surface {
+                context: "find a surface format",
+                result: vk::Result::ERROR_FORMAT_NOT_SUPPORTED,
+            });
+        }
+
+        Ok(SwapChainSupportDetails {
             capabilities,
             formats,
             present_modes
-        }
+        })
     }
 }
 
-fn choose_swapchain_format(available_formats: &Vec<vk::SurfaceFormatKHR>) -> vk::SurfaceFormatKHR {
+fn choose_swapchain_format(available_formats: &[vk::SurfaceFormatKHR]) -> vk::SurfaceFormatKHR {
     for available_format in available_formats {
         if available_format.format == vk::Format::B8G8R8A8_SRGB && available_format.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR {
-            return available_format.clone();
+            return *available_format;
         }
     }
-    return available_formats.first().unwrap().clone(); // Default
+    *available_formats.first().unwrap() // Default
 }
 
-fn choose_swapchain_present_mode(available_present_modes: &Vec<vk::PresentModeKHR>) -> vk::PresentModeKHR {
-    for &available_present_mode in available_present_modes {
-        if available_present_mode == vk::PresentModeKHR::MAILBOX {
-            return available_present_mode;
-        }
+/// `requested` if the surface offers it, otherwise `FIFO`.
+pub fn choose_swapchain_present_mode(requested: PresentMode, available_present_modes: &[vk::PresentModeKHR]) -> vk::PresentModeKHR {
+    if available_present_modes.contains(&requested.vk_mode()) {
+        requested.vk_mode()
+    } else {
+        vk::PresentModeKHR::FIFO // Default
     }
-    vk::PresentModeKHR::FIFO // Default
+}
+
+/// `requested` (or one more than the minimum) clamped to the surface's limits. A
+/// `max_image_count` of 0 means there is no upper limit.
+pub fn choose_swapchain_image_count(requested: Option<u32>, capabilities: &vk::SurfaceCapabilitiesKHR) -> u32 {
+    let image_count = requested.unwrap_or(capabilities.min_image_count + 1).max(capabilities.min_image_count);
+    if capabilities.max_image_count > 0 {
+        image_count.min(capabilities.max_image_count)
+    } else {
+        image_count
+    }
+}
+
+/// Whether the surface currently has no area, as it does while the window is minimized. No
+/// swapchain can be created for it until it grows again.
+pub fn surface_extent_is_zero(surface_loader: &ash::khr::surface::Instance, physical_device: vk::PhysicalDevice, surface: vk::SurfaceKHR) -> EngineResult<bool> {
+    let capabilities = unsafe {
+        surface_loader
+            .get_physical_device_surface_capabilities(physical_device, surface)
+            .surface_context("query surface capabilities")?
+    };
+    Ok(capabilities.current_extent.width == 0 || capabilities.current_extent.height == 0)
 }
 
 fn choose_swapchain_extent(capabilities: &vk::SurfaceCapabilitiesKHR, window: &Window) -> vk::Extent2D {
     // println!("Capabilities: {:?}", capabilities);
-    if capabilities.current_extent.width != u32::max_value() {
+    if capabilities.current_extent.width != u32::MAX {
         capabilities.current_extent
     } else {
         let size = window.inner_size();
@@ -79,36 +190,40 @@ fn choose_swapchain_extent(capabilities: &vk::SurfaceCapabilitiesKHR, window: &W
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn create_swap_chain(
     instance: &Instance, 
     device: ash::Device, 
     physical_device: vk::PhysicalDevice, 
     surface: vk::SurfaceKHR, 
     surface_loader: ash::khr::surface::Instance,
-    queue_family: (u32, u32),
-    window: &Window
-) -> SwapChainStuff {
-    let swapchain_support = query_swapchain_support(physical_device, surface, surface_loader.clone());
+    queue_families: &QueueFamilies,
+    window: &Window,
+    settings: SwapchainSettings,
+    old_swapchain: vk::SwapchainKHR,
+) -> EngineResult<SwapChainStuff> {
+    let swapchain_support = query_swapchain_support(physical_device, surface, surface_loader.clone())?;
 
     let surface_format = choose_swapchain_format(&swapchain_support.formats);
-    let present_mode = choose_swapchain_present_mode(&swapchain_support.present_modes);
+    let present_mode = choose_swapchain_present_mode(settings.present_mode, &swapchain_support.present_modes);
     let extent = choose_swapchain_extent(&swapchain_support.capabilities, window);
-
-    let image_count = swapchain_support.capabilities.min_image_count + 1;
-    let image_count = if swapchain_support.capabilities.max_image_count > 0 {
-        image_count.min(swapchain_support.capabilities.max_image_count)
-    } else {
-        image_count
-    };
+    let image_count = choose_swapchain_image_count(settings.image_count, &swapchain_support.capabilities);
+    if extent.width == 0 || extent.height == 0 {
+        // A zero imageExtent is invalid usage, so report the surface as out of date instead
+        return Err(EngineError::Swapchain {
+            context: "create swapchain for a zero-sized surface",
+            result: vk::Result::ERROR_OUT_OF_DATE_KHR,
+        });
+    }
 
     let (image_sharing_mode, queue_family_index_count, queue_family_indices) =
-    if queue_family.0 != queue_family.1 {
+    if queue_families.graphics != queue_families.present {
         (
             vk::SharingMode::CONCURRENT,
             2,
             vec![
-                queue_family.0,
-                queue_family.1,
+                queue_families.graphics,
+                queue_families.present,
             ],
         )
     } else {
@@ -132,7 +247,7 @@ pub fn create_swap_chain(
         composite_alpha: vk::CompositeAlphaFlagsKHR::OPAQUE,
         present_mode,
         clipped: vk::TRUE,
-        old_swapchain: vk::SwapchainKHR::null(),
+        old_swapchain,
         image_array_layers: 1,
         _marker: PhantomData
     };
@@ -144,20 +259,21 @@ pub fn create_swap_chain(
     let swapchain = unsafe {
         swapchain_loader
             .create_swapchain(&swapchain_create_info, None)
-            .expect("Failed to create Swapchain!")
+            .swapchain_context("create swapchain")?
     };
 
     let swapchain_images = unsafe {
         swapchain_loader
             .get_swapchain_images(swapchain)
-            .expect("Failed to create Swapchain Images!")
+            .swapchain_context("get swapchain images")?
     };
 
-    SwapChainStuff {
+    Ok(SwapChainStuff {
         swapchain_loader,
         swapchain,
         swapchain_format: surface_format.format,
         swapchain_extent: extent,
-        swapchain_images
-    }
+        swapchain_images,
+        present_mode,
+    })
 }
\ No newline at end of file
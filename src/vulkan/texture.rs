@@ -0,0 +1,506 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::path::Path;
+use std::ptr;
+
+use ash::{vk, Instance};
+
+use crate::vulkan::device::*;
+use crate::vulkan::error::*;
+use crate::vulkan::memory::*;
+use crate::vulkan::renderer::*;
+use crate::vulkan::upload::*;
+
+/// Where the windowed renderer looks for block face textures, relative to the working directory.
+pub const BLOCK_TEXTURE_DIR: &str = "textures/blocks";
+
+/// Block textures are authored in sRGB; sampling converts them to linear.
+pub const BLOCK_TEXTURE_FORMAT: vk::Format = vk::Format::R8G8B8A8_SRGB;
+
+/// Upper bound on anisotropic filtering, even where the device allows more.
+pub const MAX_ANISOTROPY: f32 = 16.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BlockFace {
+    Top,
+    Bottom,
+    North,
+    South,
+    East,
+    West,
+}
+
+impl BlockFace {
+    pub const ALL: [BlockFace; 6] = [
+        BlockFace::Top,
+        BlockFace::Bottom,
+        BlockFace::North,
+        BlockFace::South,
+        BlockFace::East,
+        BlockFace::West,
+    ];
+
+    /// File name suffix for a texture that only applies to this face, e.g. `grass_top.png`.
+    pub fn suffix(self) -> &'static str {
+        match self {
+            BlockFace::Top => "top",
+            BlockFace::Bottom => "bottom",
+            BlockFace::North => "north",
+            BlockFace::South => "south",
+            BlockFace::East => "east",
+            BlockFace::West => "west",
+        }
+    }
+
+    pub fn is_side(self) -> bool {
+        !matches!(self, BlockFace::Top | BlockFace::Bottom)
+    }
+}
+
+/// Maps texture names (file stems) to array layers, and block faces to the texture they use.
+#[derive(Debug, Clone, Default)]
+pub struct BlockLayers {
+    layers: HashMap<String, u32>,
+}
+
+impl BlockLayers {
+    /// Layer `i` holds the texture called `names[i]`.
+    pub fn new(names: &[String]) -> BlockLayers {
+        BlockLayers {
+            layers: names.iter().enumerate().map(|(i, name)| (name.clone(), i as u32)).collect(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.layers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.layers.is_empty()
+    }
+
+    pub fn layer(&self, name: &str) -> Option<u32> {
+        self.layers.get(name).copied()
+    }
+
+    /// The layer for one face of `block`. Looks for `<block>_<face>` first, then `<block>_side`
+    /// for the four side faces, then plain `<block>`.
+    pub fn face_layer(&self, block: &str, face: BlockFace) -> Option<u32> {
+        self.layer(&format!("{}_{}", block, face.suffix()))
+            .or_else(|| face.is_side().then(|| self.layer(&format!("{}_side", block))).flatten())
+            .or_else(|| self.layer(block))
+    }
+}
+
+/// Decoded block face images, all the same size, ready to upload with one array layer each.
+pub struct BlockImages {
+    pub width: u32,
+    pub height: u32,
+    /// Texture names in layer order.
+    pub names: Vec<String>,
+    /// Tightly packed RGBA8 layers, one after another.
+    pub pixels: Vec<u8>,
+}
+
+/// Loads every `.png` in `dir`, sorted by file name so layer indices are stable between runs.
+pub fn load_block_images(dir: &Path) -> EngineResult<BlockImages> {
+    let texture_error = |path: &Path, reason: String| EngineError::TextureLoading {
+        path: path.to_path_buf(),
+        reason,
+    };
+
+    let mut paths: Vec<_> = fs::read_dir(dir)
+        .map_err(|e| texture_error(dir, e.to_string()))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("png")))
+        .collect();
+    paths.sort();
+    if paths.is_empty() {
+        return Err(texture_error(dir, "no PNG files in directory".to_string()));
+    }
+
+    let mut images = BlockImages {
+        width: 0,
+        height: 0,
+        names: Vec::with_capacity(paths.len()),
+        pixels: Vec::new(),
+    };
+    for path in &paths {
+        let (width, height, pixels) = read_png_rgba(path).map_err(|reason| texture_error(path, reason))?;
+        if images.names.is_empty() {
+            images.width = width;
+            images.height = height;
+        } else if (width, height) != (images.width, images.height) {
+            return Err(texture_error(path, format!(
+                "{}x{} doesn't match the {}x{} of the other block textures",
+                width, height, images.width, images.height,
+            )));
+        }
+
+        images.names.push(path.file_stem().unwrap().to_string_lossy().into_owned());
+        images.pixels.extend_from_slice(&pixels);
+    }
+
+    Ok(images)
+}
+
+/// Decodes a PNG of any color type and bit depth into RGBA8.
+fn read_png_rgba(path: &Path) -> Result<(u32, u32, Vec<u8>), String> {
+    let file = File::open(path).map_err(|e| e.to_string())?;
+    let mut decoder = png::Decoder::new(file);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().map_err(|e| e.to_string())?;
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer).map_err(|e| e.to_string())?;
+    let bytes = &buffer[..info.buffer_size()];
+
+    let pixels = match info.color_type {
+        png::ColorType::Rgba => bytes.to_vec(),
+        png::ColorType::Rgb => bytes.chunks_exact(3).flat_map(|p| [p[0], p[1], p[2], 255]).collect(),
+        png::ColorType::GrayscaleAlpha => bytes.chunks_exact(2).flat_map(|p| [p[0], p[0], p[0], p[1]]).collect(),
+        png::ColorType::Grayscale => bytes.iter().flat_map(|&g| [g, g, g, 255]).collect(),
+        png::ColorType::Indexed => return Err("indexed color was not expanded".to_string()),
+    };
+    Ok((info.width, info.height, pixels))
+}
+
+/// Number of levels in a full mip chain down to 1x1.
+pub fn mip_level_count(width: u32, height: u32) -> u32 {
+    32 - width.max(height).max(1).leading_zeros()
+}
+
+/// All block face textures in one 2D array image with a full mip chain, plus the samplers to read
+/// it with. Destroys its view and samplers on drop; the image is freed with it.
+pub struct BlockTextures {
+    device: ash::Device,
+    image: Image,
+    view: vk::ImageView,
+    nearest_sampler: vk::Sampler,
+    anisotropic_sampler: vk::Sampler,
+    layers: BlockLayers,
+}
+
+impl BlockTextures {
+    /// Loads the textures in `dir`, uploads them and generates mipmaps on the graphics queue.
+    /// Blocks until the image is ready for sampling.
+    pub fn load(
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        device: &DeviceContext,
+        allocator: &MemoryAllocator,
+        uploads: &mut UploadManager,
+        dir: &Path,
+    ) -> EngineResult<BlockTextures> {
+        let images = load_block_images(dir)?;
+        let extent = vk::Extent2D { width: images.width, height: images.height };
+        let layer_count = images.names.len() as u32;
+
+        // Mipmaps are made by linear blits, which the format has to support
+        let format_properties = unsafe {
+            instance.get_physical_device_format_properties(physical_device, BLOCK_TEXTURE_FORMAT)
+        };
+        let blit_features = vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR
+            | vk::FormatFeatureFlags::BLIT_SRC
+            | vk::FormatFeatureFlags::BLIT_DST;
+        let mip_levels = if format_properties.optimal_tiling_features.contains(blit_features) {
+            mip_level_count(extent.width, extent.height)
+        } else {
+            log::warn!("{:?} doesn't support linear blits, block textures get no mipmaps", BLOCK_TEXTURE_FORMAT);
+            1
+        };
+
+        let mut desc = ImageDesc::new(
+            BLOCK_TEXTURE_FORMAT,
+            extent,
+            vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::TRANSFER_SRC,
+        );
+        desc.mip_levels = mip_levels;
+        desc.array_layers = layer_count;
+        let image = allocator.create_image("Block Textures", desc, MemoryUsage::GpuOnly)?;
+
+        if mip_levels > 1 {
+            uploads.upload_image(&image, &images.pixels, vk::ImageLayout::TRANSFER_DST_OPTIMAL)?;
+            uploads.flush_and_wait()?;
+            submit_and_wait(device, |command_buffer| {
+                record_mipmaps(device, command_buffer, image.handle(), extent, mip_levels, layer_count);
+            })?;
+        } else {
+            uploads.upload_image(&image, &images.pixels, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)?;
+            uploads.flush_and_wait()?;
+        }
+
+        let mut textures = BlockTextures {
+            device: device.device.clone(),
+            image,
+            view: vk::ImageView::null(),
+            nearest_sampler: vk::Sampler::null(),
+            anisotropic_sampler: vk::Sampler::null(),
+            layers: BlockLayers::new(&images.names),
+        };
+        textures.view = create_array_view(device, textures.image.handle(), mip_levels, layer_count)?;
+        textures.nearest_sampler = create_block_sampler(device, mip_levels, None)?;
+        let max_anisotropy = if device.enabled_features.sampler_anisotropy == vk::TRUE {
+            let limits = unsafe { instance.get_physical_device_properties(physical_device).limits };
+            Some(limits.max_sampler_anisotropy.min(MAX_ANISOTROPY))
+        } else {
+            None
+        };
+        textures.anisotropic_sampler = create_block_sampler(device, mip_levels, max_anisotropy)?;
+
+        println!(
+            "Block textures: {} layers of {}x{}, {} mip levels",
+            layer_count, extent.width, extent.height, mip_levels,
+        );
+        Ok(textures)
+    }
+
+    pub fn image(&self) -> vk::Image {
+        self.image.handle()
+    }
+
+    /// A `TYPE_2D_ARRAY` view over every layer and mip level.
+    pub fn view(&self) -> vk::ImageView {
+        self.view
+    }
+
+    /// Point sampling at every distance; crisp, but shimmers far away.
+    pub fn nearest_sampler(&self) -> vk::Sampler {
+        self.nearest_sampler
+    }
+
+    /// Point sampling up close, trilinear and anisotropic filtering in the distance.
+    pub fn anisotropic_sampler(&self) -> vk::Sampler {
+        self.anisotropic_sampler
+    }
+
+    pub fn layers(&self) -> &BlockLayers {
+        &self.layers
+    }
+
+    pub fn mip_levels(&self) -> u32 {
+        self.image.desc().mip_levels
+    }
+}
+
+impl Drop for BlockTextures {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.destroy_sampler(self.anisotropic_sampler, None);
+            self.device.destroy_sampler(self.nearest_sampler, None);
+            self.device.destroy_image_view(self.view, None);
+        }
+    }
+}
+
+fn create_array_view(device: &ash::Device, image: vk::Image, mip_levels: u32, layer_count: u32) -> EngineResult<vk::ImageView> {
+    let imageview_create_info = vk::ImageViewCreateInfo {
+        s_type: vk::StructureType::IMAGE_VIEW_CREATE_INFO,
+        view_type: vk::ImageViewType::TYPE_2D_ARRAY,
+        format: BLOCK_TEXTURE_FORMAT,
+        components: vk::ComponentMapping::default(),
+        subresource_range: vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: 0,
+            level_count: mip_levels,
+            base_array_layer: 0,
+            layer_count,
+        },
+        image,
+        ..Default::default()
+    };
+
+    unsafe {
+        device
+            .create_image_view(&imageview_create_info, None)
+            .context("create texture array view")
+    }
+}
+
+/// Without `max_anisotropy` the sampler filters with the nearest texel and mip level only.
+pub fn create_block_sampler(device: &ash::Device, mip_levels: u32, max_anisotropy: Option<f32>) -> EngineResult<vk::Sampler> {
+    let (min_filter, mipmap_mode) = match max_anisotropy {
+        Some(_) => (vk::Filter::LINEAR, vk::SamplerMipmapMode::LINEAR),
+        None => (vk::Filter::NEAREST, vk::SamplerMipmapMode::NEAREST),
+    };
+    let sampler_create_info = vk::SamplerCreateInfo {
+        s_type: vk::StructureType::SAMPLER_CREATE_INFO,
+        p_next: ptr::null(),
+        mag_filter: vk::Filter::NEAREST,
+        min_filter,
+        mipmap_mode,
+        address_mode_u: vk::SamplerAddressMode::REPEAT,
+        address_mode_v: vk::SamplerAddressMode::REPEAT,
+        address_mode_w: vk::SamplerAddressMode::REPEAT,
+        mip_lod_bias: 0.0,
+        anisotropy_enable: max_anisotropy.is_some() as vk::Bool32,
+        max_anisotropy: max_anisotropy.unwrap_or(1.0),
+        compare_enable: vk::FALSE,
+        compare_op: vk::CompareOp::ALWAYS,
+        min_lod: 0.0,
+        max_lod: mip_levels as f32,
+        border_color: vk::BorderColor::INT_OPAQUE_BLACK,
+        unnormalized_coordinates: vk::FALSE,
+        ..Default::default()
+    };
+
+    unsafe {
+        device
+            .create_sampler(&sampler_create_info, None)
+            .context("create sampler")
+    }
+}
+
+/// Fills mip levels 1.. of every layer by blitting each level down from the one above it. Level 0
+/// must hold the image and every level must be in `TRANSFER_DST_OPTIMAL`; afterwards the whole
+/// image is in `SHADER_READ_ONLY_OPTIMAL`.
+fn record_mipmaps(
+    device: &ash::Device,
+    command_buffer: vk::CommandBuffer,
+    image: vk::Image,
+    extent: vk::Extent2D,
+    mip_levels: u32,
+    layer_count: u32,
+) {
+    let barrier = |level: u32, old_layout, new_layout, src_access_mask, dst_access_mask| vk::ImageMemoryBarrier {
+        s_type: vk::StructureType::IMAGE_MEMORY_BARRIER,
+        src_access_mask,
+        dst_access_mask,
+        old_layout,
+        new_layout,
+        src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+        dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+        image,
+        subresource_range: vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: level,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count,
+        },
+        ..Default::default()
+    };
+    let layers = |mip_level| vk::ImageSubresourceLayers {
+        aspect_mask: vk::ImageAspectFlags::COLOR,
+        mip_level,
+        base_array_layer: 0,
+        layer_count,
+    };
+
+    let (mut width, mut height) = (extent.width as i32, extent.height as i32);
+    for level in 1..mip_levels {
+        let (next_width, next_height) = ((width / 2).max(1), (height / 2).max(1));
+        let to_src = barrier(
+            level - 1,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            vk::AccessFlags::TRANSFER_WRITE,
+            vk::AccessFlags::TRANSFER_READ,
+        );
+        let blit = vk::ImageBlit {
+            src_subresource: layers(level - 1),
+            src_offsets: [vk::Offset3D { x: 0, y: 0, z: 0 }, vk::Offset3D { x: width, y: height, z: 1 }],
+            dst_subresource: layers(level),
+            dst_offsets: [vk::Offset3D { x: 0, y: 0, z: 0 }, vk::Offset3D { x: next_width, y: next_height, z: 1 }],
+        };
+        let to_shader = barrier(
+            level - 1,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            vk::AccessFlags::TRANSFER_READ,
+            vk::AccessFlags::SHADER_READ,
+        );
+
+        unsafe {
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[to_src],
+            );
+            device.cmd_blit_image(
+                command_buffer,
+                image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[blit],
+                vk::Filter::LINEAR,
+            );
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[to_shader],
+            );
+        }
+        (width, height) = (next_width, next_height);
+    }
+
+    let last_to_shader = barrier(
+        mip_levels - 1,
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        vk::AccessFlags::TRANSFER_WRITE,
+        vk::AccessFlags::SHADER_READ,
+    );
+    unsafe {
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[last_to_shader],
+        );
+    }
+}
+
+/// Records one command buffer with `record`, runs it on the graphics queue and waits for it.
+fn submit_and_wait(device: &DeviceContext, record: impl FnOnce(vk::CommandBuffer)) -> EngineResult<()> {
+    let commands = FrameCommands::create(device, device.queue_families.graphics, 1)?[0];
+    let fence_create_info = vk::FenceCreateInfo {
+        s_type: vk::StructureType::FENCE_CREATE_INFO,
+        ..Default::default()
+    };
+    let fence = match unsafe { device.create_fence(&fence_create_info, None) } {
+        Ok(fence) => fence,
+        Err(e) => {
+            unsafe { device.destroy_command_pool(commands.command_pool, None) };
+            return Err(e).context("create fence");
+        }
+    };
+
+    let result = commands.begin(device).and_then(|command_buffer| {
+        record(command_buffer);
+        commands.end(device)?;
+
+        let command_buffers = [command_buffer];
+        let submit_info = vk::SubmitInfo {
+            s_type: vk::StructureType::SUBMIT_INFO,
+            command_buffer_count: 1,
+            p_command_buffers: command_buffers.as_ptr(),
+            ..Default::default()
+        };
+        unsafe {
+            device
+                .queue_submit(device.graphics_queue, &[submit_info], fence)
+                .context("submit one-time command buffer")?;
+            device
+                .wait_for_fences(&[fence], true, u64::MAX)
+                .context("wait for one-time command buffer")
+        }
+    });
+
+    unsafe {
+        device.destroy_fence(fence, None);
+        device.destroy_command_pool(commands.command_pool, None);
+    }
+    result
+}
@@ -0,0 +1,667 @@
+use std::collections::VecDeque;
+
+use ash::vk;
+
+use crate::vulkan::device::*;
+use crate::vulkan::error::*;
+use crate::vulkan::memory::*;
+
+/// Default size of the staging ring. Uploads bigger than this are split (buffers) or rejected
+/// (images).
+pub const STAGING_RING_SIZE: vk::DeviceSize = 16 * 1024 * 1024;
+
+/// Offsets into the staging ring are aligned to this, which covers `optimalBufferCopyOffsetAlignment`
+/// on every desktop driver and the texel size of every format we upload.
+const STAGING_ALIGNMENT: vk::DeviceSize = 16;
+
+/// Bytes per texel of the uncompressed color formats `upload_image` accepts.
+pub fn texel_size(format: vk::Format) -> Option<u32> {
+    Some(match format {
+        vk::Format::R8_UNORM | vk::Format::R8_SRGB | vk::Format::R8_UINT | vk::Format::R8_SINT => 1,
+        vk::Format::R8G8_UNORM | vk::Format::R8G8_SRGB | vk::Format::R16_SFLOAT | vk::Format::R16_UNORM => 2,
+        vk::Format::R8G8B8A8_UNORM
+        | vk::Format::R8G8B8A8_SRGB
+        | vk::Format::B8G8R8A8_UNORM
+        | vk::Format::B8G8R8A8_SRGB
+        | vk::Format::A2B10G10R10_UNORM_PACK32
+        | vk::Format::R16G16_SFLOAT
+        | vk::Format::R32_SFLOAT
+        | vk::Format::R32_UINT => 4,
+        vk::Format::R16G16B16A16_SFLOAT | vk::Format::R16G16B16A16_UNORM | vk::Format::R32G32_SFLOAT => 8,
+        vk::Format::R32G32B32A32_SFLOAT => 16,
+        _ => return None,
+    })
+}
+
+/// Checks that `len` bytes are exactly mip level 0 of every layer of an image described by
+/// `desc`, which is what `upload_image` copies.
+pub fn check_image_upload(desc: &ImageDesc, len: usize) -> EngineResult<()> {
+    let texel_size = texel_size(desc.format)
+        .ok_or_else(|| EngineError::Upload(format!("no texel size known for {:?}", desc.format)))?;
+    let expected = desc.extent.width as u64 * desc.extent.height as u64 * desc.array_layers as u64 * texel_size as u64;
+    if len as u64 != expected {
+        return Err(EngineError::Upload(format!(
+            "{} bytes for a {}x{}x{} {:?} image, which takes {}",
+            len, desc.extent.width, desc.extent.height, desc.array_layers, desc.format, expected
+        )));
+    }
+    Ok(())
+}
+
+/// Sub-allocates a fixed-size region first in, first out. Space is handed back per submitted
+/// batch once the GPU is done with it, so `used` always covers one contiguous (possibly wrapped)
+/// range starting `used` bytes behind `head`.
+#[derive(Debug, Clone)]
+pub struct RingAllocator {
+    capacity: vk::DeviceSize,
+    head: vk::DeviceSize,
+    used: vk::DeviceSize,
+    unsubmitted: vk::DeviceSize,
+}
+
+impl RingAllocator {
+    pub fn new(capacity: vk::DeviceSize) -> RingAllocator {
+        RingAllocator { capacity, head: 0, used: 0, unsubmitted: 0 }
+    }
+
+    pub fn capacity(&self) -> vk::DeviceSize {
+        self.capacity
+    }
+
+    pub fn used(&self) -> vk::DeviceSize {
+        self.used
+    }
+
+    /// Returns the offset of `size` free bytes aligned to `alignment`, or `None` if they don't fit
+    /// until older batches are released. Padding skipped at the end of the ring counts as used.
+    pub fn allocate(&mut self, size: vk::DeviceSize, alignment: vk::DeviceSize) -> Option<vk::DeviceSize> {
+        if size == 0 || size > self.capacity {
+            return None;
+        }
+
+        let mut offset = self.head.next_multiple_of(alignment);
+        if offset + size > self.capacity {
+            offset = 0;
+        }
+        let consumed = if offset >= self.head {
+            offset + size - self.head
+        } else {
+            self.capacity - self.head + offset + size
+        };
+        if self.used + consumed > self.capacity {
+            return None;
+        }
+
+        self.head = (offset + size) % self.capacity;
+        self.used += consumed;
+        self.unsubmitted += consumed;
+        Some(offset)
+    }
+
+    /// Closes the current batch and returns how many bytes it holds, to be passed to `release`
+    /// once the GPU has finished with it.
+    pub fn submit(&mut self) -> vk::DeviceSize {
+        std::mem::take(&mut self.unsubmitted)
+    }
+
+    /// Frees the oldest submitted batch.
+    pub fn release(&mut self, batch_size: vk::DeviceSize) {
+        debug_assert!(batch_size <= self.used);
+        self.used -= batch_size;
+    }
+}
+
+/// Identifies one `UploadManager::flush`. Tickets complete in order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct UploadTicket(u64);
+
+enum PendingCopy {
+    Buffer {
+        src_offset: vk::DeviceSize,
+        dst: vk::Buffer,
+        dst_offset: vk::DeviceSize,
+        size: vk::DeviceSize,
+    },
+    Image {
+        src_offset: vk::DeviceSize,
+        dst: vk::Image,
+        extent: vk::Extent2D,
+        mip_levels: u32,
+        array_layers: u32,
+        final_layout: vk::ImageLayout,
+    },
+}
+
+/// Command buffers and sync objects for one submission, recycled once it completes.
+struct Batch {
+    transfer_command_buffer: vk::CommandBuffer,
+    /// Acquires ownership on the graphics queue; only used with a dedicated transfer family.
+    graphics_command_buffer: vk::CommandBuffer,
+    semaphore: vk::Semaphore,
+    fence: vk::Fence,
+    ticket: UploadTicket,
+    staging_bytes: vk::DeviceSize,
+    /// Set once the transfer half of an ownership transfer is submitted. If the acquire then fails
+    /// the transfer queue may still be using the batch and its semaphore stays signaled, so it
+    /// can't be recycled.
+    release_submitted: bool,
+}
+
+/// Gets data into `MemoryUsage::GpuOnly` buffers and images. Data is copied into a persistently
+/// mapped staging ring right away; the copies are recorded and submitted on the transfer queue by
+/// `flush`. With a dedicated transfer family each resource is released by the transfer queue and
+/// acquired by the graphics queue, so it is ready for rendering once its ticket completes.
+pub struct UploadManager {
+    device: ash::Device,
+    queue_families: QueueFamilies,
+    transfer_queue: vk::Queue,
+    graphics_queue: vk::Queue,
+    staging: Buffer,
+    ring: RingAllocator,
+    transfer_command_pool: vk::CommandPool,
+    graphics_command_pool: vk::CommandPool,
+    pending: Vec<PendingCopy>,
+    in_flight: VecDeque<Batch>,
+    free_batches: Vec<Batch>,
+    last_ticket: UploadTicket,
+    completed_ticket: UploadTicket,
+}
+
+impl UploadManager {
+    pub fn new(device: &DeviceContext, allocator: &MemoryAllocator, staging_size: vk::DeviceSize) -> EngineResult<UploadManager> {
+        let staging = allocator.create_buffer(
+            "Staging Ring",
+            staging_size,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            MemoryUsage::CpuToGpu,
+        )?;
+
+        let create_pool = |queue_family_index| {
+            let command_pool_create_info = vk::CommandPoolCreateInfo {
+                s_type: vk::StructureType::COMMAND_POOL_CREATE_INFO,
+                flags: vk::CommandPoolCreateFlags::TRANSIENT | vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER,
+                queue_family_index,
+                ..Default::default()
+            };
+            unsafe {
+                device
+                    .create_command_pool(&command_pool_create_info, None)
+                    .context("create upload command pool")
+            }
+        };
+        let transfer_command_pool = create_pool(device.queue_families.transfer)?;
+        let graphics_command_pool = match create_pool(device.queue_families.graphics) {
+            Ok(pool) => pool,
+            Err(e) => {
+                unsafe { device.destroy_command_pool(transfer_command_pool, None) };
+                return Err(e);
+            }
+        };
+
+        Ok(UploadManager {
+            device: device.device.clone(),
+            queue_families: device.queue_families,
+            transfer_queue: device.transfer_queue,
+            graphics_queue: device.graphics_queue,
+            staging,
+            ring: RingAllocator::new(staging_size),
+            transfer_command_pool,
+            graphics_command_pool,
+            pending: Vec::new(),
+            in_flight: VecDeque::new(),
+            free_batches: Vec::new(),
+            last_ticket: UploadTicket::default(),
+            completed_ticket: UploadTicket::default(),
+        })
+    }
+
+    fn needs_ownership_transfer(&self) -> bool {
+        self.queue_families.has_dedicated_transfer()
+    }
+
+    /// Reserves `size` bytes of staging memory, flushing and waiting for older uploads when the
+    /// ring is full.
+    fn allocate_staging(&mut self, size: vk::DeviceSize) -> EngineResult<vk::DeviceSize> {
+        loop {
+            if let Some(offset) = self.ring.allocate(size, STAGING_ALIGNMENT) {
+                return Ok(offset);
+            }
+            if !self.pending.is_empty() {
+                self.flush()?;
+            }
+            let Some(oldest) = self.in_flight.front().map(|batch| batch.ticket) else {
+                // Nothing left to wait for and it still doesn't fit
+                return Err(EngineError::Allocation {
+                    context: "reserve staging memory",
+                    result: vk::Result::ERROR_OUT_OF_DEVICE_MEMORY,
+                });
+            };
+            self.wait(oldest)?;
+        }
+    }
+
+    /// Queues a copy of `data` into `dst` at byte offset `dst_offset`. Data larger than the staging
+    /// ring is split across several batches.
+    pub fn upload_buffer<T: Pod>(&mut self, dst: &Buffer, dst_offset: vk::DeviceSize, data: &[T]) -> EngineResult<()> {
+        let bytes = as_bytes(data);
+        let end = dst_offset.checked_add(bytes.len() as vk::DeviceSize);
+        if end.is_none_or(|end| end > dst.size()) {
+            return Err(EngineError::Upload(format!(
+                "{} bytes at offset {} overrun a {} byte buffer",
+                bytes.len(), dst_offset, dst.size()
+            )));
+        }
+
+        let max_chunk = (self.ring.capacity() / 2) as usize;
+        let mut written = 0;
+        for chunk in bytes.chunks(max_chunk.max(1)) {
+            let src_offset = self.allocate_staging(chunk.len() as vk::DeviceSize)?;
+            self.staging.write(src_offset as usize, chunk);
+            self.pending.push(PendingCopy::Buffer {
+                src_offset,
+                dst: dst.handle(),
+                dst_offset: dst_offset + written,
+                size: chunk.len() as vk::DeviceSize,
+            });
+            written += chunk.len() as vk::DeviceSize;
+        }
+        Ok(())
+    }
+
+    /// Queues a copy of `data` into mip level 0 of every layer of `dst`, one tightly packed layer
+    /// after another, and leaves the whole image in `final_layout`. `data` must cover exactly that,
+    /// see `check_image_upload`.
+    pub fn upload_image(&mut self, dst: &Image, data: &[u8], final_layout: vk::ImageLayout) -> EngineResult<()> {
+        check_image_upload(dst.desc(), data.len())?;
+        let src_offset = self.allocate_staging(data.len() as vk::DeviceSize)?;
+        self.staging.write(src_offset as usize, data);
+        self.pending.push(PendingCopy::Image {
+            src_offset,
+            dst: dst.handle(),
+            extent: dst.extent(),
+            mip_levels: dst.desc().mip_levels,
+            array_layers: dst.desc().array_layers,
+            final_layout,
+        });
+        Ok(())
+    }
+
+    fn take_batch(&mut self) -> EngineResult<Batch> {
+        if let Some(batch) = self.free_batches.pop() {
+            return Ok(batch);
+        }
+
+        let allocate = |command_pool| {
+            let command_buffer_allocate_info = vk::CommandBufferAllocateInfo {
+                s_type: vk::StructureType::COMMAND_BUFFER_ALLOCATE_INFO,
+                command_buffer_count: 1,
+                command_pool,
+                level: vk::CommandBufferLevel::PRIMARY,
+                ..Default::default()
+            };
+            unsafe {
+                self.device
+                    .allocate_command_buffers(&command_buffer_allocate_info)
+                    .context("allocate upload command buffer")
+                    .map(|buffers| buffers[0])
+            }
+        };
+        let semaphore_create_info = vk::SemaphoreCreateInfo {
+            s_type: vk::StructureType::SEMAPHORE_CREATE_INFO,
+            ..Default::default()
+        };
+        let fence_create_info = vk::FenceCreateInfo {
+            s_type: vk::StructureType::FENCE_CREATE_INFO,
+            ..Default::default()
+        };
+
+        // Command buffers go back with their pools; only the sync objects need undoing on failure
+        let transfer_command_buffer = allocate(self.transfer_command_pool)?;
+        let graphics_command_buffer = allocate(self.graphics_command_pool)?;
+        let semaphore = unsafe {
+            self.device
+                .create_semaphore(&semaphore_create_info, None)
+                .context("create upload semaphore")?
+        };
+        let fence = match unsafe { self.device.create_fence(&fence_create_info, None) } {
+            Ok(fence) => fence,
+            Err(e) => {
+                unsafe { self.device.destroy_semaphore(semaphore, None) };
+                return Err(e).context("create upload fence");
+            }
+        };
+
+        Ok(Batch {
+            transfer_command_buffer,
+            graphics_command_buffer,
+            semaphore,
+            fence,
+            ticket: UploadTicket::default(),
+            staging_bytes: 0,
+            release_submitted: false,
+        })
+    }
+
+    /// Records and submits everything queued since the last flush. Returns the ticket to wait on
+    /// before the uploaded resources are used; with nothing queued that is the last ticket issued.
+    pub fn flush(&mut self) -> EngineResult<UploadTicket> {
+        if self.pending.is_empty() {
+            return Ok(self.last_ticket);
+        }
+
+        let mut batch = self.take_batch()?;
+        let result = self.record_and_submit(&mut batch);
+        if let Err(e) = result {
+            if batch.release_submitted {
+                self.discard_batch(batch);
+            } else {
+                self.free_batches.push(batch);
+            }
+            return Err(e);
+        }
+
+        self.last_ticket = UploadTicket(self.last_ticket.0 + 1);
+        batch.ticket = self.last_ticket;
+        batch.staging_bytes = self.ring.submit();
+        self.in_flight.push_back(batch);
+        self.pending.clear();
+        Ok(self.last_ticket)
+    }
+
+    /// Drops a batch whose release went in without the matching acquire once the transfer queue
+    /// is done with it. If even that wait fails the batch is leaked rather than destroyed in use.
+    fn discard_batch(&mut self, batch: Batch) {
+        if let Err(e) = unsafe { self.device.queue_wait_idle(self.transfer_queue) } {
+            log::error!("Failed to wait for the transfer queue, leaking an upload batch: {}", e);
+            return;
+        }
+        unsafe {
+            self.device.destroy_fence(batch.fence, None);
+            self.device.destroy_semaphore(batch.semaphore, None);
+            self.device.free_command_buffers(self.transfer_command_pool, &[batch.transfer_command_buffer]);
+            self.device.free_command_buffers(self.graphics_command_pool, &[batch.graphics_command_buffer]);
+        }
+    }
+
+    fn record_and_submit(&self, batch: &mut Batch) -> EngineResult<()> {
+        let device = &self.device;
+        let ownership_transfer = self.needs_ownership_transfer();
+        let (src_family, dst_family) = if ownership_transfer {
+            (self.queue_families.transfer, self.queue_families.graphics)
+        } else {
+            (vk::QUEUE_FAMILY_IGNORED, vk::QUEUE_FAMILY_IGNORED)
+        };
+
+        let mut to_transfer_dst = Vec::new();
+        let mut release_buffers = Vec::new();
+        let mut release_images = Vec::new();
+        for copy in &self.pending {
+            match *copy {
+                PendingCopy::Buffer { dst, dst_offset, size, .. } => {
+                    release_buffers.push(vk::BufferMemoryBarrier {
+                        s_type: vk::StructureType::BUFFER_MEMORY_BARRIER,
+                        src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+                        dst_access_mask: if ownership_transfer { vk::AccessFlags::empty() } else { vk::AccessFlags::MEMORY_READ },
+                        src_queue_family_index: src_family,
+                        dst_queue_family_index: dst_family,
+                        buffer: dst,
+                        offset: dst_offset,
+                        size,
+                        ..Default::default()
+                    });
+                }
+                PendingCopy::Image { dst, mip_levels, array_layers, final_layout, .. } => {
+                    let subresource_range = vk::ImageSubresourceRange {
+                        aspect_mask: vk::ImageAspectFlags::COLOR,
+                        base_mip_level: 0,
+                        level_count: mip_levels,
+                        base_array_layer: 0,
+                        layer_count: array_layers,
+                    };
+                    to_transfer_dst.push(vk::ImageMemoryBarrier {
+                        s_type: vk::StructureType::IMAGE_MEMORY_BARRIER,
+                        src_access_mask: vk::AccessFlags::empty(),
+                        dst_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+                        old_layout: vk::ImageLayout::UNDEFINED,
+                        new_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                        src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                        dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                        image: dst,
+                        subresource_range,
+                        ..Default::default()
+                    });
+                    release_images.push(vk::ImageMemoryBarrier {
+                        s_type: vk::StructureType::IMAGE_MEMORY_BARRIER,
+                        src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+                        dst_access_mask: if ownership_transfer { vk::AccessFlags::empty() } else { vk::AccessFlags::MEMORY_READ },
+                        old_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                        new_layout: final_layout,
+                        src_queue_family_index: src_family,
+                        dst_queue_family_index: dst_family,
+                        image: dst,
+                        subresource_range,
+                        ..Default::default()
+                    });
+                }
+            }
+        }
+
+        let begin_info = vk::CommandBufferBeginInfo {
+            s_type: vk::StructureType::COMMAND_BUFFER_BEGIN_INFO,
+            flags: vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT,
+            ..Default::default()
+        };
+        let command_buffer = batch.transfer_command_buffer;
+        unsafe {
+            device
+                .begin_command_buffer(command_buffer, &begin_info)
+                .context("begin recording upload command buffer")?;
+            if !to_transfer_dst.is_empty() {
+                device.cmd_pipeline_barrier(
+                    command_buffer,
+                    vk::PipelineStageFlags::TOP_OF_PIPE,
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &[],
+                    &to_transfer_dst,
+                );
+            }
+
+            for copy in &self.pending {
+                match *copy {
+                    PendingCopy::Buffer { src_offset, dst, dst_offset, size } => {
+                        let region = vk::BufferCopy { src_offset, dst_offset, size };
+                        device.cmd_copy_buffer(command_buffer, self.staging.handle(), dst, &[region]);
+                    }
+                    PendingCopy::Image { src_offset, dst, extent, array_layers, .. } => {
+                        let region = vk::BufferImageCopy {
+                            buffer_offset: src_offset,
+                            buffer_row_length: 0,
+                            buffer_image_height: 0,
+                            image_subresource: vk::ImageSubresourceLayers {
+                                aspect_mask: vk::ImageAspectFlags::COLOR,
+                                mip_level: 0,
+                                base_array_layer: 0,
+                                layer_count: array_layers,
+                            },
+                            image_offset: vk::Offset3D { x: 0, y: 0, z: 0 },
+                            image_extent: vk::Extent3D { width: extent.width, height: extent.height, depth: 1 },
+                        };
+                        device.cmd_copy_buffer_to_image(
+                            command_buffer,
+                            self.staging.handle(),
+                            dst,
+                            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                            &[region],
+                        );
+                    }
+                }
+            }
+
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                if ownership_transfer { vk::PipelineStageFlags::BOTTOM_OF_PIPE } else { vk::PipelineStageFlags::ALL_COMMANDS },
+                vk::DependencyFlags::empty(),
+                &[],
+                &release_buffers,
+                &release_images,
+            );
+            device
+                .end_command_buffer(command_buffer)
+                .context("end recording upload command buffer")?;
+        }
+
+        let transfer_command_buffers = [command_buffer];
+        let semaphores = [batch.semaphore];
+        if !ownership_transfer {
+            let submit_info = vk::SubmitInfo {
+                s_type: vk::StructureType::SUBMIT_INFO,
+                command_buffer_count: 1,
+                p_command_buffers: transfer_command_buffers.as_ptr(),
+                ..Default::default()
+            };
+            return unsafe {
+                device
+                    .queue_submit(self.transfer_queue, &[submit_info], batch.fence)
+                    .context("submit upload command buffer")
+            };
+        }
+
+        // The acquiring half repeats each release barrier with the access masks on the other side
+        let acquire_buffers: Vec<_> = release_buffers.iter().map(|barrier| vk::BufferMemoryBarrier {
+            src_access_mask: vk::AccessFlags::empty(),
+            dst_access_mask: vk::AccessFlags::MEMORY_READ,
+            ..*barrier
+        }).collect();
+        let acquire_images: Vec<_> = release_images.iter().map(|barrier| vk::ImageMemoryBarrier {
+            src_access_mask: vk::AccessFlags::empty(),
+            dst_access_mask: vk::AccessFlags::MEMORY_READ,
+            ..*barrier
+        }).collect();
+
+        let graphics_command_buffer = batch.graphics_command_buffer;
+        unsafe {
+            device
+                .begin_command_buffer(graphics_command_buffer, &begin_info)
+                .context("begin recording upload acquire command buffer")?;
+            device.cmd_pipeline_barrier(
+                graphics_command_buffer,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                vk::PipelineStageFlags::ALL_COMMANDS,
+                vk::DependencyFlags::empty(),
+                &[],
+                &acquire_buffers,
+                &acquire_images,
+            );
+            device
+                .end_command_buffer(graphics_command_buffer)
+                .context("end recording upload acquire command buffer")?;
+        }
+
+        let release_submit = vk::SubmitInfo {
+            s_type: vk::StructureType::SUBMIT_INFO,
+            command_buffer_count: 1,
+            p_command_buffers: transfer_command_buffers.as_ptr(),
+            signal_semaphore_count: 1,
+            p_signal_semaphores: semaphores.as_ptr(),
+            ..Default::default()
+        };
+        let graphics_command_buffers = [graphics_command_buffer];
+        let wait_stages = [vk::PipelineStageFlags::ALL_COMMANDS];
+        let acquire_submit = vk::SubmitInfo {
+            s_type: vk::StructureType::SUBMIT_INFO,
+            wait_semaphore_count: 1,
+            p_wait_semaphores: semaphores.as_ptr(),
+            p_wait_dst_stage_mask: wait_stages.as_ptr(),
+            command_buffer_count: 1,
+            p_command_buffers: graphics_command_buffers.as_ptr(),
+            ..Default::default()
+        };
+        unsafe {
+            device
+                .queue_submit(self.transfer_queue, &[release_submit], vk::Fence::null())
+                .context("submit upload command buffer")?;
+        }
+        batch.release_submitted = true;
+        unsafe {
+            device
+                .queue_submit(self.graphics_queue, &[acquire_submit], batch.fence)
+                .context("submit upload acquire command buffer")
+        }
+    }
+
+    /// Retires every finished batch and returns the newest completed ticket.
+    pub fn poll(&mut self) -> EngineResult<UploadTicket> {
+        while let Some(batch) = self.in_flight.front() {
+            let done = unsafe {
+                self.device
+                    .get_fence_status(batch.fence)
+                    .context("query upload fence")?
+            };
+            if !done {
+                break;
+            }
+            self.retire_oldest()?;
+        }
+        Ok(self.completed_ticket)
+    }
+
+    pub fn is_complete(&self, ticket: UploadTicket) -> bool {
+        ticket <= self.completed_ticket
+    }
+
+    /// Blocks until `ticket` (and everything before it) has completed.
+    pub fn wait(&mut self, ticket: UploadTicket) -> EngineResult<()> {
+        while !self.is_complete(ticket) {
+            let Some(batch) = self.in_flight.front() else {
+                break;
+            };
+            unsafe {
+                self.device
+                    .wait_for_fences(&[batch.fence], true, u64::MAX)
+                    .context("wait for upload fence")?
+            };
+            self.retire_oldest()?;
+        }
+        Ok(())
+    }
+
+    /// Flushes anything queued and waits for all of it.
+    pub fn flush_and_wait(&mut self) -> EngineResult<()> {
+        let ticket = self.flush()?;
+        self.wait(ticket)
+    }
+
+    fn retire_oldest(&mut self) -> EngineResult<()> {
+        let mut batch = self.in_flight.pop_front().unwrap();
+        unsafe {
+            self.device
+                .reset_fences(&[batch.fence])
+                .context("reset upload fence")?
+        };
+        self.ring.release(batch.staging_bytes);
+        self.completed_ticket = batch.ticket;
+        batch.staging_bytes = 0;
+        self.free_batches.push(batch);
+        Ok(())
+    }
+}
+
+impl Drop for UploadManager {
+    fn drop(&mut self) {
+        if let Err(e) = self.flush_and_wait() {
+            log::error!("Failed to finish pending uploads: {}", e);
+        }
+        unsafe {
+            for batch in self.in_flight.drain(..).chain(self.free_batches.drain(..)) {
+                self.device.destroy_fence(batch.fence, None);
+                self.device.destroy_semaphore(batch.semaphore, None);
+            }
+            // Frees the command buffers as well
+            self.device.destroy_command_pool(self.transfer_command_pool, None);
+            self.device.destroy_command_pool(self.graphics_command_pool, None);
+        }
+    }
+}
@@ -1,87 +1,283 @@
+use std::path::Path;
+use std::time::Instant;
+
 use winit::application::ApplicationHandler;
-use winit::event::WindowEvent;
+use winit::event::{DeviceEvent, DeviceId, ElementState, KeyEvent, MouseButton, WindowEvent};
+use winit::event_loop::ControlFlow;
+use winit::keyboard::{KeyCode, PhysicalKey};
+use winit::window::CursorGrabMode;
 use winit::{event_loop::ActiveEventLoop, window::{Window, WindowId}};
 
 
+use crate::camera::*;
+use crate::game_loop::*;
+use crate::vulkan::debug::*;
+use crate::vulkan::deletion_queue::*;
+use crate::vulkan::depth::*;
+use crate::vulkan::descriptors::*;
 use crate::vulkan::device::*;
+use crate::vulkan::error::*;
+use crate::vulkan::memory::*;
+use crate::vulkan::mesh::*;
+use crate::vulkan::msaa::*;
+use crate::vulkan::pipeline_cache::*;
+use crate::vulkan::rendering::*;
+use crate::vulkan::upload::*;
+use crate::vulkan::shaders::*;
 use crate::vulkan::swapchain::*;
+use crate::vulkan::texture::*;
 use crate::vulkan::other::*;
+use crate::vulkan::renderer::*;
 use ash::{vk, Entry, Instance};
-use ash_window;
 use raw_window_handle::{HasDisplayHandle, HasWindowHandle};
-use std::{ffi::CString, os::raw::c_char};
+use std::os::raw::c_char;
 
 #[derive(Default)]
 pub struct AppEvents {
-    window: Option<Window>,
     entry: Option<Entry>,
     pub instance: Option<Instance>,
     pub surface: vk::SurfaceKHR,
     pub surface_loader: Option<ash::khr::surface::Instance>,
     pub physical_device: vk::PhysicalDevice,
+    /// Device to use instead of the best scoring one, by index or name (see `DEVICE_ENV`).
+    pub device_preference: Option<String>,
     pub swapchain: vk::SwapchainKHR,
     pub swapchain_loader: Option<ash::khr::swapchain::Device>,
-    pub logical_device: Option<ash::Device>,
-    swapchain_imageviews: Vec<vk::ImageView>,
-    render_pass: vk::RenderPass,
-    pipeline_layout: vk::PipelineLayout,
-    graphics_pipeline: vk::Pipeline,
-    command_buffers: Vec<vk::CommandBuffer>,
-    queue: vk::Queue,
-    pub queue_family: (u32, u32),
+    /// Requested present mode and image count. See `set_present_mode`, `set_swapchain_image_count`
+    /// and `PRESENT_MODE_ENV`.
+    pub swapchain_settings: SwapchainSettings,
+    /// The present mode the current swapchain actually uses.
+    pub(crate) present_mode: vk::PresentModeKHR,
+    pub logical_device: Option<DeviceContext>,
+    pub(crate) window: Option<Window>,
+    pub(crate) swapchain_images: Vec<vk::Image>,
+    pub(crate) swapchain_format: vk::Format,
+    pub(crate) swapchain_extent: vk::Extent2D,
+    pub(crate) swapchain_imageviews: Vec<vk::ImageView>,
+    pub(crate) swapchain_framebuffers: Vec<vk::Framebuffer>,
+    /// Chosen once per device; the image itself is recreated with the swapchain.
+    pub(crate) depth_format: vk::Format,
+    pub(crate) depth_image: Option<Image>,
+    pub(crate) depth_image_view: vk::ImageView,
+    /// Requested MSAA sample count; 0 or 1 turns it off. See `set_msaa` and `MSAA_ENV`.
+    pub msaa: u32,
+    /// Sample counts the device supports for color and depth together.
+    pub(crate) supported_samples: vk::SampleCountFlags,
+    /// `msaa` clamped to `supported_samples`, what the render pass actually uses.
+    pub(crate) msaa_samples: vk::SampleCountFlags,
+    pub(crate) msaa_color_image: Option<Image>,
+    pub(crate) msaa_color_view: vk::ImageView,
+    /// Chosen once per device, see `RENDER_PATH_ENV`. `render_pass` and `swapchain_framebuffers`
+    /// stay empty on the dynamic path.
+    pub(crate) render_path: RenderPath,
+    pub(crate) render_pass: vk::RenderPass,
+    pub(crate) pipeline_layout: vk::PipelineLayout,
+    pub(crate) graphics_pipeline: vk::Pipeline,
+    /// Render path, swapchain format and sample count the render pass and pipeline were built for;
+    /// `None` until they exist. See `ensure_scene_pipeline`.
+    pub(crate) scene_pipeline_key: Option<(RenderPath, vk::Format, vk::SampleCountFlags)>,
+    /// Requested frames in flight; 0 means `DEFAULT_FRAMES_IN_FLIGHT`. See `set_frames_in_flight`
+    /// and `FRAMES_IN_FLIGHT_ENV`.
+    pub frames_in_flight: usize,
+    /// One command pool and buffer per frame in flight, re-recorded every frame.
+    pub(crate) frame_commands: Vec<FrameCommands>,
 
     image_available_semaphores: Vec<vk::Semaphore>,
     render_finished_semaphores: Vec<vk::Semaphore>,
     in_flight_fences: Vec<vk::Fence>,
     current_frame: usize,
+    pub(crate) debug_messenger: Option<DebugMessenger>,
+    pub(crate) debug_names: DebugNames,
+    /// Source of every buffer and image; dropped right before the device.
+    pub(crate) allocator: Option<MemoryAllocator>,
+    /// Staging uploads into device-local memory; dropped before the allocator.
+    pub(crate) uploads: Option<UploadManager>,
+    /// Everything that records draws into the main pass, in order.
+    pub(crate) subsystems: Vec<Box<dyn RenderSubsystem>>,
+    /// Watches the `SHADER_DIR_ENV` directory for edits; `None` when it isn't set.
+    pub(crate) shader_manager: Option<ShaderManager>,
+    /// Loaded from `BLOCK_TEXTURE_DIR` when it exists.
+    pub(crate) block_textures: Option<BlockTextures>,
+    pub(crate) descriptor_set_layout: vk::DescriptorSetLayout,
+    /// Shared by every pipeline; loaded from and saved to `default_cache_dir`.
+    pub(crate) pipeline_cache: Option<PipelineCache>,
+    pub(crate) descriptor_allocator: DescriptorAllocator,
+    /// Camera matrices for each frame in flight.
+    pub(crate) frame_uniforms: FrameUniforms,
+    /// Objects that live as long as the logical device.
+    pub(crate) deletion_queue: DeletionQueue,
+    /// Objects that have to be rebuilt with the swapchain.
+    pub(crate) swapchain_deletion_queue: DeletionQueue,
+    /// The render pass and scene pipeline, which outlive swapchain recreations.
+    pub(crate) pipeline_deletion_queue: DeletionQueue,
+    /// Command pools and sync objects, rebuilt when the number of frames in flight changes.
+    pub(crate) frame_deletion_queue: DeletionQueue,
+    /// Set when the surface no longer matches the swapchain or `swapchain_settings` changed;
+    /// handled before the next frame.
+    pub(crate) framebuffer_resized: bool,
+    /// Simulation rate, frame cap and frame timing.
+    pub game_loop: GameLoop,
+    pub camera: Camera,
+    /// Moves `camera` every frame; click to grab the cursor, Escape to release it.
+    pub camera_controller: FlyController,
 }
 
 impl ApplicationHandler for AppEvents {
     fn resumed(&mut self, event_loop: &ActiveEventLoop) {
+        if self.window.is_some() {
+            return;
+        }
+
         let attributes = Window::default_attributes()
             .with_title("Sage Zinnia (Beta)");
-        self.window = Some(event_loop.create_window(attributes.clone()).unwrap());
-        let window = self.window.as_ref().unwrap();
+        self.window = Some(event_loop.create_window(attributes).unwrap());
+
+        if let Err(e) = self.init_vulkan() {
+            log::error!("Failed to initialize the renderer: {}", e);
+            event_loop.exit();
+        }
+    }
+
+    fn window_event(&mut self, event_loop: &ActiveEventLoop, _id: WindowId, event: WindowEvent) {
+        match event {
+            WindowEvent::Resized(size) => {
+                println!("Window was resized: {:?}", size);
+                self.framebuffer_resized = true;
+            }
+
+            WindowEvent::CloseRequested => {
+                println!("\nThe close button was pressed");
+                event_loop.exit();
+            }
+
+            WindowEvent::KeyboardInput {
+                event: KeyEvent { physical_key: PhysicalKey::Code(KeyCode::KeyM), state: ElementState::Pressed, repeat: false, .. },
+                ..
+            } => {
+                let next = next_sample_count(self.msaa_samples, self.supported_samples);
+                if let Err(e) = self.set_msaa(next.as_raw()) {
+                    log::error!("Failed to change MSAA: {}", e);
+                    event_loop.exit();
+                }
+            }
 
-        let entry = unsafe { Entry::load().expect("Failed to load Vulkan") };
-        // println!("Supports Validation Layers {:?}", check_validation_layer_support(&entry));
-        // Application info
-        let app_name = CString::new(attributes.title.to_string()).unwrap();
-        let engine_name = CString::new("Custom Engine").unwrap();
-        let app_info = vk::ApplicationInfo {
-            s_type: vk::StructureType::APPLICATION_INFO,
-            p_application_name: app_name.as_ptr(),
-            application_version: vk::make_api_version(0, 1, 0, 0),
-            p_engine_name: engine_name.as_ptr(),
-            engine_version: vk::make_api_version(0, 1, 0, 0),
-            api_version: vk::make_api_version(0, 1, 3, 0),
-            ..Default::default()
+            WindowEvent::KeyboardInput {
+                event: KeyEvent { physical_key: PhysicalKey::Code(KeyCode::KeyV), state: ElementState::Pressed, repeat: false, .. },
+                ..
+            } => self.set_present_mode(self.swapchain_settings.present_mode.next()),
+
+            WindowEvent::KeyboardInput {
+                event: KeyEvent { physical_key: PhysicalKey::Code(KeyCode::Escape), state: ElementState::Pressed, .. },
+                ..
+            } => self.release_cursor(),
+
+            WindowEvent::KeyboardInput {
+                event: KeyEvent { physical_key: PhysicalKey::Code(key), state, .. },
+                ..
+            } => self.camera_controller.handle_key(key, state == ElementState::Pressed),
+
+            WindowEvent::MouseInput { state: ElementState::Pressed, button: MouseButton::Left, .. }
+                if !self.camera_controller.is_grabbed() => self.grab_cursor(),
+
+            WindowEvent::Focused(false) => self.release_cursor(),
+
+            WindowEvent::RedrawRequested => {
+                if let Err(e) = self.run_frame() {
+                    if e.is_device_lost() {
+                        log::error!("The GPU was lost (driver crash or reset), shutting down: {}", e);
+                    } else {
+                        log::error!("Failed to draw frame: {}", e);
+                    }
+                    event_loop.exit();
+                }
+            }
+            _ => ()
+        }
+    }
+
+    fn device_event(&mut self, _: &ActiveEventLoop, _: DeviceId, event: DeviceEvent) {
+        // Raw motion keeps working when the cursor is locked in place
+        if let DeviceEvent::MouseMotion { delta } = event {
+            self.camera_controller.handle_mouse_motion(delta.0, delta.1);
+        }
+    }
+
+    fn about_to_wait(&mut self, event_loop: &ActiveEventLoop) {
+        let Some(window) = self.window.as_ref() else {
+            return;
         };
 
+        let size = window.inner_size();
+        if size.width == 0 || size.height == 0 {
+            // Minimized: sleep until a resize brings the window back and don't simulate the gap
+            self.game_loop.pause();
+            event_loop.set_control_flow(ControlFlow::Wait);
+            return;
+        }
+
+        match self.game_loop.next_frame_at() {
+            Some(next_frame) if next_frame > Instant::now() => {
+                event_loop.set_control_flow(ControlFlow::WaitUntil(next_frame));
+            }
+            _ => {
+                event_loop.set_control_flow(ControlFlow::Poll);
+                window.request_redraw();
+            }
+        }
+    }
+
+    fn exiting(&mut self, _: &ActiveEventLoop) {
+        // Destroy Vulkan resources safely; initialization may have stopped part way
+        if let Some(device) = self.logical_device.as_ref() {
+            if let Err(e) = unsafe { device.device_wait_idle() } {
+                log::error!("Failed to wait for device idle on shutdown: {}", e);
+            }
+            self.cleanup_swapchain(true);
+            let device = self.logical_device.as_ref().unwrap();
+            self.pipeline_deletion_queue.flush(device);
+            if let Some(Err(e)) = self.pipeline_cache.take().map(|pipeline_cache| pipeline_cache.save(device)) {
+                log::error!("Failed to save the pipeline cache: {}", e);
+            }
+            self.frame_deletion_queue.flush(device);
+            self.deletion_queue.flush(device);
+            self.descriptor_allocator.destroy(device);
+            self.frame_uniforms = FrameUniforms::default();
+            self.subsystems.clear();
+            self.block_textures = None;
+            self.uploads = None;
+            self.allocator = None;
+            device.destroy();
+            self.logical_device = None;
+        }
+        if let Some(surface_loader) = self.surface_loader.as_ref() {
+            unsafe { surface_loader.destroy_surface(self.surface, None) };
+        }
+        if let Some(debug_messenger) = self.debug_messenger.take() {
+            debug_messenger.destroy();
+        }
+        if let Some(instance) = self.instance.as_ref() {
+            unsafe { instance.destroy_instance(None) };
+        }
+        println!("Exiting window");
+    }
+}
+
+impl AppEvents {
+    fn init_vulkan(&mut self) -> EngineResult<()> {
+        let window = self.window.as_ref().unwrap();
+        let title = window.title();
+
+        let entry = unsafe { Entry::load()? };
         // Get required extensions from winit
-        let extension_names = required_extensions(window);
+        let extension_names = required_extensions(window)?;
         // println!("Required Extensions for winit: "); // Debug
         // for ext in &extension_names {
         //     let ext_name = unsafe { CStr::from_ptr(ext.clone()) };
         //     println!("- {}", ext_name.to_string_lossy());
         // }
 
-        // let validation_layers = vec!["VK_LAYER_KHRONOS_validation"];
-        let instance_info = vk::InstanceCreateInfo {
-            s_type: vk::StructureType::INSTANCE_CREATE_INFO,
-            p_application_info: &app_info,
-            enabled_extension_count: extension_names.len() as u32,
-            pp_enabled_extension_names: extension_names.as_ptr(),
-            // enabled_layer_count: validation_layers.len() as u32,
-            // pp_enabled_layer_names: validation_layers.as_ptr() as *const *const i8,
-            ..Default::default()
-        };
-
-        let instance = unsafe {
-            entry
-                .create_instance(&instance_info, None)
-                .expect("Failed to create Vulkan instance")
-        };
+        let (instance, debug_messenger) = create_instance(&entry, &title, extension_names)?;
+        self.debug_messenger = debug_messenger;
 
         // Create Vulkan surface
         let surface = unsafe {
@@ -91,223 +287,454 @@ impl ApplicationHandler for AppEvents {
                 window.display_handle().unwrap().into(),
                 window.window_handle().unwrap().into(),
                 None
-            ).expect("Failed to create Vulkan surface")
+            )
         };
-
+        // Keep what exists so far so `exiting` can tear it down even if the surface failed
         self.entry = Some(entry);
         self.instance = Some(instance);
+        self.surface_loader = Some(ash::khr::surface::Instance::new(self.entry.as_ref().unwrap(), self.instance.as_ref().unwrap()));
+        let surface = surface.surface_context("create window surface")?;
         self.surface = surface;
-        self.surface_loader = Some(ash::khr::surface::Instance::new(&self.entry.as_ref().unwrap(), &self.instance.as_ref().unwrap()));
 
         println!("Vulkan surface & surface loader successfully created!");
 
         let instance = self.instance.as_ref().unwrap();
-        let physical_device = pick_physical_device(&instance);
+        let physical_device = pick_physical_device(
+            instance,
+            Some((self.surface_loader.as_ref().unwrap(), self.surface)),
+            self.device_preference.as_deref(),
+        )?;
         self.physical_device = physical_device;
+        self.depth_format = find_depth_format(instance, physical_device)?;
+        println!("Depth format: {:?}", self.depth_format);
+        if let Some(msaa) = msaa_from_env() {
+            self.msaa = msaa;
+        }
+        self.supported_samples = supported_sample_counts(instance, physical_device);
+        self.msaa_samples = clamp_sample_count(self.msaa, self.supported_samples);
+        println!("MSAA: {:?} (supported: {:?})", self.msaa_samples, self.supported_samples);
+        self.swapchain_settings = self.swapchain_settings.with_env_overrides();
+        if let Some(frames_in_flight) = frames_in_flight_from_env() {
+            self.frames_in_flight = frames_in_flight;
+        }
+
+        let queue_families = find_queue_families(instance, physical_device,
+            Some((self.surface_loader.as_ref().unwrap(), self.surface)))?;
+        let logical_device = create_logical_device(
+            instance,
+            physical_device,
+            queue_families,
+            &[ash::khr::swapchain::NAME.as_ptr()], // specific extensions I'll need to run this program
+        )?;
+        self.render_path = RenderPath::from_env(logical_device.dynamic_rendering);
+        println!("Render path: {:?}", self.render_path);
+        self.debug_names = DebugNames::new(instance, &logical_device, self.debug_messenger.is_some());
+        logical_device.name_queues(&self.debug_names);
+        self.logical_device = Some(logical_device);
+        self.allocator = Some(MemoryAllocator::new(instance, self.logical_device.as_ref().unwrap(), physical_device)?);
+        self.uploads = Some(UploadManager::new(
+            self.logical_device.as_ref().unwrap(),
+            self.allocator.as_ref().unwrap(),
+            STAGING_RING_SIZE,
+        )?);
+        let uploads = self.uploads.as_mut().unwrap();
+        let quad = Mesh::new(self.allocator.as_ref().unwrap(), uploads, "Quad", &QUAD_VERTICES, &QUAD_INDICES)?;
+        self.subsystems.push(Box::new(StaticScene { meshes: vec![quad] }));
+        uploads.flush_and_wait()?;
+
+        let block_texture_dir = Path::new(BLOCK_TEXTURE_DIR);
+        if block_texture_dir.is_dir() {
+            let block_textures = self.block_textures.insert(BlockTextures::load(
+                instance,
+                physical_device,
+                self.logical_device.as_ref().unwrap(),
+                self.allocator.as_ref().unwrap(),
+                uploads,
+                block_texture_dir,
+            )?);
+            self.debug_names.name(block_textures.image(), "Block Textures");
+            self.debug_names.name(block_textures.view(), "Block Texture Array View");
+            self.debug_names.name(block_textures.nearest_sampler(), "Block Nearest Sampler");
+            self.debug_names.name(block_textures.anisotropic_sampler(), "Block Anisotropic Sampler");
+        } else {
+            println!("No block textures at {}", BLOCK_TEXTURE_DIR);
+        }
 
-        let queue_family = find_queue_families(instance, physical_device,
-            self.surface, self.surface_loader.as_ref().unwrap().clone());
-        self.queue_family = queue_family;
-        let logical_device = create_logical_device(instance, physical_device, queue_family);
-        self.logical_device = Some(logical_device.0);
-        self.queue = logical_device.1;
+        let device = self.logical_device.as_ref().unwrap();
+        let cache_key = CacheKey::new(&unsafe { instance.get_physical_device_properties(physical_device) });
+        let cache_path = default_cache_dir().map(|dir| dir.join(PIPELINE_CACHE_FILE));
+        let pipeline_cache = self.pipeline_cache.insert(PipelineCache::load(device, cache_key, cache_path)?);
+        self.deletion_queue.push(pipeline_cache.handle());
+        self.debug_names.name(pipeline_cache.handle(), "Pipeline Cache");
 
-        println!("Logical Device properties: {:?}, {:?}", logical_device.1, logical_device.2);
+        self.descriptor_set_layout = create_scene_descriptor_set_layout(device)?;
+        self.deletion_queue.push(self.descriptor_set_layout);
+        self.debug_names.name(self.descriptor_set_layout, "Scene Descriptor Set Layout");
 
         let swapchain_stuff = create_swap_chain(
             instance,
-            self.logical_device.as_ref().unwrap().clone(),
+            self.logical_device.as_ref().unwrap().device.clone(),
             physical_device,
             surface,
             self.surface_loader.as_ref().unwrap().clone(),
-            queue_family,
-            window
-        );
+            &queue_families,
+            window,
+            self.swapchain_settings,
+            vk::SwapchainKHR::null(),
+        )?;
 
         self.swapchain = swapchain_stuff.swapchain;
         self.swapchain_loader = Some(swapchain_stuff.swapchain_loader);
-        println!("Swapchain: {:?}", swapchain_stuff.swapchain);
-
-        let swapchain_imageviews = create_image_views(
-            self.logical_device.as_ref().unwrap(),
-            swapchain_stuff.swapchain_format,
-            &swapchain_stuff.swapchain_images
-        );
-        self.swapchain_imageviews = swapchain_imageviews;
-
-        let render_pass = create_render_pass(
-            self.logical_device.as_ref().unwrap(),
-            swapchain_stuff.swapchain_format
-        );
-        println!("Render Pass: {:?}", render_pass);
-        self.render_pass = render_pass;
-        let (graphics_pipeline, pipeline_layout) = create_graphics_pipeline(
-            self.logical_device.as_ref().unwrap(),
-            self.render_pass,
-            swapchain_stuff.swapchain_extent
-        );
-        self.graphics_pipeline = graphics_pipeline;
-        self.pipeline_layout = pipeline_layout;
-
+        self.swapchain_images = swapchain_stuff.swapchain_images;
+        self.swapchain_format = swapchain_stuff.swapchain_format;
+        self.swapchain_extent = swapchain_stuff.swapchain_extent;
+        self.present_mode = swapchain_stuff.present_mode;
+        println!("Swapchain: {:?}, {:?} with {} images", self.swapchain, self.present_mode, self.swapchain_images.len());
+
+        self.create_swapchain_resources()?;
+        println!("Render Pass: {:?}", self.render_pass);
+        println!("Graphics Pipeline: {:?}", self.graphics_pipeline);
+        println!("Pipeline Layout: {:?}", self.pipeline_layout);
+        println!("Swapchain Framebuffers: {:?}", self.swapchain_framebuffers);
+
+        if let Some(dir) = shader_override_dir().filter(|dir| dir.is_dir()) {
+            println!("Loading shaders from {}", dir.display());
+            self.shader_manager = Some(ShaderManager::new(dir));
+        }
 
-        println!("Graphics Pipeline: {:?}", graphics_pipeline);
-        println!("Pipeline Layout: {:?}", pipeline_layout);
+        self.create_frame_resources()
+    }
 
-        let swapchain_framebuffers = create_framebuffers(
-            self.logical_device.as_ref().unwrap(),
-            render_pass,
-            &self.swapchain_imageviews,
-            &swapchain_stuff.swapchain_extent,
-        );
-        println!("Swapchain Framebuffers: {:?}", swapchain_framebuffers);
-
-        println!("Queue Family: {:?}", queue_family);
-        let command_pool = create_command_pool(&self.logical_device.as_ref().unwrap(), queue_family.1);
-        println!("Command Pool: {:?}", command_pool);
-        let command_buffers = create_command_buffers(
-            &self.logical_device.as_ref().unwrap(),
-            command_pool,
-            graphics_pipeline,
-            &swapchain_framebuffers,
-            render_pass,
-            swapchain_stuff.swapchain_extent,
+    /// Creates the camera uniforms, command buffer and sync objects of every frame in flight.
+    /// Whatever existed before must already be flushed from `frame_deletion_queue`.
+    fn create_frame_resources(&mut self) -> EngineResult<()> {
+        let frame_count = clamp_frames_in_flight(self.frames_in_flight);
+        let device = self.logical_device.as_ref().unwrap();
+
+        // The camera sets are the only ones in the pool, so it can start over
+        self.frame_uniforms = FrameUniforms::default();
+        self.descriptor_allocator.destroy(device);
+        self.descriptor_allocator = DescriptorAllocator::new(
+            &[PoolSizeRatio { descriptor_type: vk::DescriptorType::UNIFORM_BUFFER, ratio: 1.0 }],
+            frame_count as u32,
         );
-        println!("Command Buffers: {:?}", command_buffers);
-        self.command_buffers = command_buffers;
+        self.frame_uniforms = FrameUniforms::new(
+            device,
+            self.allocator.as_ref().unwrap(),
+            &mut self.descriptor_allocator,
+            self.descriptor_set_layout,
+            frame_count,
+        )?;
+        self.debug_names.name_all(self.frame_uniforms.sets(), "Camera Descriptor Set");
+
+        self.frame_commands = FrameCommands::create(device, device.queue_families.graphics, frame_count)?;
+        for (i, frame) in self.frame_commands.iter().enumerate() {
+            self.frame_deletion_queue.push(frame.command_pool);
+            self.debug_names.name(frame.command_pool, &format!("Frame Command Pool {}", i));
+            self.debug_names.name(frame.command_buffer, &format!("Frame Command Buffer {}", i));
+        }
 
-        let sync_objects = create_sync_objects(&self.logical_device.as_ref().unwrap());
+        let sync_objects = create_sync_objects(device, frame_count)?;
         self.image_available_semaphores = sync_objects.image_available_semaphores;
         self.render_finished_semaphores = sync_objects.render_finished_semaphores;
         self.in_flight_fences = sync_objects.in_flight_fences;
+        self.frame_deletion_queue.push_all(&self.image_available_semaphores);
+        self.frame_deletion_queue.push_all(&self.render_finished_semaphores);
+        self.frame_deletion_queue.push_all(&self.in_flight_fences);
+        self.debug_names.name_all(&self.image_available_semaphores, "Image Available Semaphore");
+        self.debug_names.name_all(&self.render_finished_semaphores, "Render Finished Semaphore");
+        self.debug_names.name_all(&self.in_flight_fences, "In Flight Fence");
         self.current_frame = 0;
+        println!("Frames in flight: {}", frame_count);
+
+        Ok(())
     }
 
-    fn window_event(&mut self, event_loop: &ActiveEventLoop, _id: WindowId, event: WindowEvent) {
-        match event {
-            WindowEvent::Resized(_) => {
-                let window = self.window.as_ref().unwrap();
-                println!("Window was resized: {:?}", window.inner_size());
+    /// Adds a subsystem that records its draws after the ones already registered.
+    pub fn add_render_subsystem(&mut self, subsystem: Box<dyn RenderSubsystem>) {
+        self.subsystems.push(subsystem);
+    }
+
+    /// Changes the MSAA sample count, clamped to what the device supports. Rebuilds the render
+    /// pass, pipeline and framebuffers if the effective sample count changes.
+    pub fn set_msaa(&mut self, samples: u32) -> EngineResult<()> {
+        self.msaa = samples;
+        if self.logical_device.is_none() {
+            // Picked up by `init_vulkan`
+            return Ok(());
+        }
+
+        let msaa_samples = clamp_sample_count(samples, self.supported_samples);
+        if msaa_samples == self.msaa_samples {
+            return Ok(());
+        }
+        self.msaa_samples = msaa_samples;
+        println!("MSAA: {:?}", msaa_samples);
+        if self.swapchain == vk::SwapchainKHR::null() {
+            // Picked up when the swapchain is recreated
+            return Ok(());
+        }
+
+        unsafe {
+            self.logical_device
+                .as_ref()
+                .unwrap()
+                .device_wait_idle()
+                .context("wait for device idle")?
+        };
+        self.cleanup_swapchain(false);
+        self.create_swapchain_resources()
+    }
+
+    /// Changes the present mode. The swapchain is recreated before the next frame; a mode the
+    /// surface doesn't support falls back to vsync.
+    pub fn set_present_mode(&mut self, present_mode: PresentMode) {
+        println!("Present mode: {:?}", present_mode);
+        self.swapchain_settings.present_mode = present_mode;
+        self.request_swapchain_recreation();
+    }
+
+    /// Changes how many images the swapchain asks for, `None` for the default. The swapchain is
+    /// recreated before the next frame.
+    pub fn set_swapchain_image_count(&mut self, image_count: Option<u32>) {
+        self.swapchain_settings.image_count = image_count;
+        self.request_swapchain_recreation();
+    }
+
+    fn request_swapchain_recreation(&mut self) {
+        if self.swapchain == vk::SwapchainKHR::null() {
+            // Picked up when the swapchain is created
+            return;
+        }
+        self.framebuffer_resized = true;
+        if let Some(window) = self.window.as_ref() {
+            window.request_redraw();
+        }
+    }
+
+    /// Changes how many frames the CPU may record ahead of the GPU, clamped to
+    /// `MAX_FRAMES_IN_FLIGHT`. Rebuilds the per-frame uniforms, command buffers and sync objects
+    /// if the effective count changes.
+    pub fn set_frames_in_flight(&mut self, frames_in_flight: usize) -> EngineResult<()> {
+        self.frames_in_flight = frames_in_flight;
+        if self.frame_commands.is_empty() || clamp_frames_in_flight(frames_in_flight) == self.frame_commands.len() {
+            // Picked up by `init_vulkan`, or nothing to do
+            return Ok(());
+        }
+
+        let device = self.logical_device.as_ref().unwrap();
+        unsafe { device.device_wait_idle().context("wait for device idle")? };
+        self.frame_deletion_queue.flush(device);
+        self.create_frame_resources()
+    }
+
+    /// Locks (or, where locking isn't supported, confines) and hides the cursor for mouse-look.
+    fn grab_cursor(&mut self) {
+        let Some(window) = self.window.as_ref() else {
+            return;
+        };
+
+        let grabbed = window
+            .set_cursor_grab(CursorGrabMode::Locked)
+            .or_else(|_| window.set_cursor_grab(CursorGrabMode::Confined));
+        match grabbed {
+            Ok(()) => {
+                window.set_cursor_visible(false);
+                self.camera_controller.set_grabbed(true);
+            }
+            Err(e) => log::warn!("Failed to grab the cursor: {}", e),
+        }
+    }
+
+    fn release_cursor(&mut self) {
+        if let Some(window) = self.window.as_ref() {
+            if let Err(e) = window.set_cursor_grab(CursorGrabMode::None) {
+                log::warn!("Failed to release the cursor: {}", e);
             }
+            window.set_cursor_visible(true);
+        }
+        self.camera_controller.set_grabbed(false);
+    }
 
-            WindowEvent::CloseRequested => {
-                println!("\nThe close button was pressed");
-                event_loop.exit();
+    /// Rebuilds the scene pipeline if one of its shaders changed on disk. Called between frames; a
+    /// pipeline that fails to build is reported and the old one stays in use.
+    fn reload_shaders(&mut self) -> EngineResult<()> {
+        let Some(shader_manager) = self.shader_manager.as_mut() else {
+            return Ok(());
+        };
+        let reloaded = shader_manager.poll(Instant::now());
+        let scene_shaders = [SCENE_VERTEX_SHADER, SCENE_FRAGMENT_SHADER].map(|name| shader_path(shader_manager.dir(), name));
+        if self.graphics_pipeline == vk::Pipeline::null() || !reloaded.iter().any(|path| scene_shaders.contains(path)) {
+            return Ok(());
+        }
+
+        let device = self.logical_device.as_ref().unwrap();
+        let (graphics_pipeline, pipeline_layout) = match create_graphics_pipeline(
+            device,
+            self.pipeline_cache_handle(),
+            self.scene_pipeline_target(),
+            &[self.descriptor_set_layout],
+            self.msaa_samples,
+        ) {
+            Ok(pipeline) => pipeline,
+            Err(e) => {
+                log::error!("Keeping the previous scene pipeline: {}", e);
+                return Ok(());
             }
+        };
 
-            WindowEvent::RedrawRequested => {
-                let wait_fences = [self.in_flight_fences[self.current_frame]];
-
-                unsafe {
-                    // Wait for the previous frame to finish
-                    self.logical_device.as_ref().unwrap()
-                        .wait_for_fences(&wait_fences, true, std::u64::MAX)
-                        .expect("Failed to wait for Fence!");
-
-                    // Reset the fence for this frame
-                    self.logical_device.as_ref().unwrap()
-                        .reset_fences(&wait_fences)
-                        .expect("Failed to reset Fence!");
-
-                    // Acquire the next image
-                    let (image_index, _is_sub_optimal) = match self.swapchain_loader.as_ref().unwrap()
-                        .acquire_next_image(
-                            self.swapchain,
-                            std::u64::MAX,
-                            self.image_available_semaphores[self.current_frame],
-                            vk::Fence::null(),
-                        ) {
-                            Ok((index, sub_optimal)) => (index, sub_optimal),
-                            Err(e) => {
-                                println!("Failed to acquire next image: {:?}", e);
-                                return;
-                            }
-                        };
-
-                    // Submit the command buffer
-                    let wait_semaphores = [self.image_available_semaphores[self.current_frame]];
-                    let wait_stages = [vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT];
-                    let command_buffers = [self.command_buffers[image_index as usize]];
-                    let signal_semaphores = [self.render_finished_semaphores[self.current_frame]];
-
-                    let submit_info = vk::SubmitInfo {
-                        s_type: vk::StructureType::SUBMIT_INFO,
-                        wait_semaphore_count: 1,
-                        p_wait_semaphores: wait_semaphores.as_ptr(),
-                        p_wait_dst_stage_mask: wait_stages.as_ptr(),
-                        command_buffer_count: 1,
-                        p_command_buffers: command_buffers.as_ptr(),
-                        signal_semaphore_count: 1,
-                        p_signal_semaphores: signal_semaphores.as_ptr(),
-                        ..Default::default()
-                    };
-
-                    self.logical_device.as_ref().unwrap()
-                        .queue_submit(
-                            self.queue,
-                            &[submit_info],
-                            self.in_flight_fences[self.current_frame],
-                        )
-                        .expect("Failed to submit draw command buffer");
-
-                    // Present the image
-                    let swapchains = [self.swapchain];
-                    let image_indices = [image_index];
-                    let present_info = vk::PresentInfoKHR {
-                        s_type: vk::StructureType::PRESENT_INFO_KHR,
-                        wait_semaphore_count: 1,
-                        p_wait_semaphores: signal_semaphores.as_ptr(),
-                        swapchain_count: 1,
-                        p_swapchains: swapchains.as_ptr(),
-                        p_image_indices: image_indices.as_ptr(),
-                        p_results: std::ptr::null_mut(),
-                        ..Default::default()
-                    };
-
-                    match self.swapchain_loader.as_ref().unwrap()
-                        .queue_present(self.queue, &present_info) {
-                        Ok(_) => {},
-                        Err(e) => println!("Failed to present queue: {:?}", e),
-                    }
+        // Frames in flight may still use the old pipeline
+        unsafe { device.device_wait_idle().context("wait for device idle")? };
+        self.pipeline_deletion_queue.replace(device, self.pipeline_layout, pipeline_layout);
+        self.pipeline_deletion_queue.replace(device, self.graphics_pipeline, graphics_pipeline);
+        self.graphics_pipeline = graphics_pipeline;
+        self.pipeline_layout = pipeline_layout;
+        self.debug_names.name(self.pipeline_layout, "Triangle Pipeline Layout");
+        self.debug_names.name(self.graphics_pipeline, "Triangle Pipeline");
+        println!("Scene pipeline reloaded");
+        Ok(())
+    }
 
-                    self.current_frame = (self.current_frame + 1) % MAX_FRAMES_IN_FLIGHT;
-                }
+    /// Runs the simulation ticks that are due, then renders one interpolated frame.
+    fn run_frame(&mut self) -> EngineResult<()> {
+        let timing = self.game_loop.begin_frame(Instant::now());
+        if timing.dropped > std::time::Duration::ZERO {
+            log::debug!("Frame took {:?}, skipped {:?} of simulation", timing.frame_time, timing.dropped);
+        }
+
+        let dt = self.game_loop.tick_duration();
+        for _ in 0..timing.ticks {
+            for subsystem in self.subsystems.iter_mut() {
+                subsystem.tick(dt);
             }
-            _ => ()
         }
+
+        self.camera_controller.update(&mut self.camera, timing.frame_time.min(MAX_FRAME_TIME));
+        self.reload_shaders()?;
+
+        self.draw_frame(timing.alpha)
     }
 
-    fn exiting(&mut self, _: &ActiveEventLoop) {
-        // Destroy Vulkan resources safely
-        unsafe { self.logical_device.as_ref().unwrap().destroy_device(None) };
-        unsafe { self.surface_loader.as_ref().unwrap().destroy_surface(self.surface, None) };
-        unsafe { self.instance.as_ref().unwrap().destroy_instance(None) };
-        println!("Exiting window");
+    fn draw_frame(&mut self, alpha: f32) -> EngineResult<()> {
+        // Nothing can be presented while the window is minimized; wait for a non-zero size
+        if self.framebuffer_resized && !self.recreate_swapchain()? {
+            return Ok(());
+        }
+
+        let wait_fences = [self.in_flight_fences[self.current_frame]];
+
+        unsafe {
+            // Wait for the previous frame to finish
+            self.logical_device.as_ref().unwrap()
+                .wait_for_fences(&wait_fences, true, u64::MAX)
+                .context("wait for in-flight fence")?;
+
+            // Acquire the next image
+            let (image_index, is_sub_optimal) = match self.swapchain_loader.as_ref().unwrap()
+                .acquire_next_image(
+                    self.swapchain,
+                    u64::MAX,
+                    self.image_available_semaphores[self.current_frame],
+                    vk::Fence::null(),
+                ) {
+                    Ok((index, sub_optimal)) => (index, sub_optimal),
+                    Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
+                        self.framebuffer_resized = true;
+                        self.window.as_ref().unwrap().request_redraw();
+                        return Ok(());
+                    }
+                    Err(e) => return Err(e).swapchain_context("acquire next image"),
+                };
+
+            // The GPU is done with this frame's uniform buffer once its fence has signaled
+            let aspect = self.swapchain_extent.width as f32 / self.swapchain_extent.height as f32;
+            self.frame_uniforms.update(self.current_frame, &self.camera.uniforms(aspect));
+
+            // Only reset the fence once we know work will be submitted for it
+            self.logical_device.as_ref().unwrap()
+                .reset_fences(&wait_fences)
+                .context("reset in-flight fence")?;
+
+            // Submit the command buffer
+            let wait_semaphores = [self.image_available_semaphores[self.current_frame]];
+            let wait_stages = [vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT];
+            let device = self.logical_device.as_ref().unwrap();
+            let frame_commands = self.frame_commands[self.current_frame];
+            let pass = ScenePass {
+                target: self.scene_target(image_index as usize),
+                pipeline: self.graphics_pipeline,
+                pipeline_layout: self.pipeline_layout,
+                descriptor_set: self.frame_uniforms.sets()[self.current_frame],
+                extent: self.swapchain_extent,
+            };
+            let command_buffer = frame_commands.begin(device)?;
+            record_scene(device, command_buffer, &pass, self.current_frame, alpha, &mut self.subsystems);
+            frame_commands.end(device)?;
+
+            let command_buffers = [command_buffer];
+            let signal_semaphores = [self.render_finished_semaphores[self.current_frame]];
+
+            let submit_info = vk::SubmitInfo {
+                s_type: vk::StructureType::SUBMIT_INFO,
+                wait_semaphore_count: 1,
+                p_wait_semaphores: wait_semaphores.as_ptr(),
+                p_wait_dst_stage_mask: wait_stages.as_ptr(),
+                command_buffer_count: 1,
+                p_command_buffers: command_buffers.as_ptr(),
+                signal_semaphore_count: 1,
+                p_signal_semaphores: signal_semaphores.as_ptr(),
+                ..Default::default()
+            };
+
+            device
+                .queue_submit(
+                    device.graphics_queue,
+                    &[submit_info],
+                    self.in_flight_fences[self.current_frame],
+                )
+                .context("submit draw command buffer")?;
+
+            // Present the image
+            let swapchains = [self.swapchain];
+            let image_indices = [image_index];
+            let present_info = vk::PresentInfoKHR {
+                s_type: vk::StructureType::PRESENT_INFO_KHR,
+                wait_semaphore_count: 1,
+                p_wait_semaphores: signal_semaphores.as_ptr(),
+                swapchain_count: 1,
+                p_swapchains: swapchains.as_ptr(),
+                p_image_indices: image_indices.as_ptr(),
+                p_results: std::ptr::null_mut(),
+                ..Default::default()
+            };
+
+            match self.swapchain_loader.as_ref().unwrap()
+                .queue_present(device.present_queue, &present_info) {
+                Ok(present_sub_optimal) => {
+                    if is_sub_optimal || present_sub_optimal {
+                        self.framebuffer_resized = true;
+                    }
+                }
+                Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => self.framebuffer_resized = true,
+                Err(e) => return Err(e).swapchain_context("present"),
+            }
+
+            self.current_frame = (self.current_frame + 1) % self.frame_commands.len();
+        }
+
+        if self.framebuffer_resized {
+            self.window.as_ref().unwrap().request_redraw();
+        }
+
+        Ok(())
     }
 }
 
-fn required_extensions(window: &Window) -> Vec<*const c_char> {
+fn required_extensions(window: &Window) -> EngineResult<Vec<*const c_char>> {
     let mut extensions = Vec::new();
     // Get required extensions from winit
-    let surface_extensions = ash_window::enumerate_required_extensions(window.display_handle().unwrap().into()).unwrap();
+    let surface_extensions = ash_window::enumerate_required_extensions(window.display_handle().unwrap().into())
+        .surface_context("enumerate required surface extensions")?;
     extensions.extend(surface_extensions.iter().copied());
-    extensions
+    Ok(extensions)
 }
-
-// fn check_validation_layer_support(entry: &ash::Entry) -> bool {
-//     let available_layers = unsafe {
-//         entry.enumerate_instance_layer_properties().unwrap()
-//     };
-
-//     for layer in &["VK_LAYER_KHRONOS_validation"] {
-//         let found = available_layers.iter().any(|l| {
-//             unsafe { CStr::from_ptr(l.layer_name.as_ptr()) }
-//                 .to_str()
-//                 .unwrap() == *layer
-//         });
-
-//         if !found {
-//             return false;
-//         }
-//     }
-//     true
-// }
\ No newline at end of file
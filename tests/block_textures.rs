@@ -0,0 +1,75 @@
+use std::fs;
+use std::path::PathBuf;
+
+use voxel_engine::vulkan::error::EngineError;
+use voxel_engine::vulkan::headless::write_png;
+use voxel_engine::vulkan::texture::*;
+
+mod common;
+
+fn texture_dir(name: &str) -> PathBuf {
+    common::scratch_dir("test-textures", name)
+}
+
+fn solid(width: u32, height: u32, rgba: [u8; 4]) -> Vec<u8> {
+    rgba.repeat((width * height) as usize)
+}
+
+#[test]
+fn mip_chain_goes_down_to_one_texel() {
+    assert_eq!(mip_level_count(1, 1), 1);
+    assert_eq!(mip_level_count(16, 16), 5);
+    assert_eq!(mip_level_count(16, 4), 5);
+    assert_eq!(mip_level_count(17, 3), 5);
+}
+
+#[test]
+fn faces_fall_back_to_side_then_block() {
+    let names: Vec<String> = ["dirt", "grass_bottom", "grass_side", "grass_top", "log_north"]
+        .iter()
+        .map(|name| name.to_string())
+        .collect();
+    let layers = BlockLayers::new(&names);
+
+    assert_eq!(layers.face_layer("grass", BlockFace::Top), Some(3));
+    assert_eq!(layers.face_layer("grass", BlockFace::Bottom), Some(1));
+    assert_eq!(layers.face_layer("grass", BlockFace::East), Some(2));
+    assert_eq!(layers.face_layer("dirt", BlockFace::North), Some(0));
+    assert_eq!(layers.face_layer("log", BlockFace::North), Some(4));
+    assert_eq!(layers.face_layer("log", BlockFace::South), None);
+    // `_side` only stands in for the four side faces
+    assert_eq!(layers.face_layer("stone", BlockFace::Top), None);
+}
+
+#[test]
+fn loads_pngs_in_name_order() {
+    let dir = texture_dir("name_order");
+    write_png(&dir.join("stone.png"), 2, 2, &solid(2, 2, [128, 128, 128, 255])).unwrap();
+    write_png(&dir.join("dirt.png"), 2, 2, &solid(2, 2, [100, 60, 20, 255])).unwrap();
+    fs::write(dir.join("notes.txt"), "not a texture").unwrap();
+
+    let images = load_block_images(&dir).unwrap();
+    assert_eq!((images.width, images.height), (2, 2));
+    assert_eq!(images.names, ["dirt", "stone"]);
+    assert_eq!(images.pixels.len(), 2 * 2 * 4 * 2);
+    assert_eq!(&images.pixels[..4], &[100, 60, 20, 255]);
+    assert_eq!(&images.pixels[16..20], &[128, 128, 128, 255]);
+}
+
+#[test]
+fn mismatched_sizes_are_rejected() {
+    let dir = texture_dir("mismatched_sizes");
+    write_png(&dir.join("a.png"), 2, 2, &solid(2, 2, [0, 0, 0, 255])).unwrap();
+    write_png(&dir.join("b.png"), 4, 4, &solid(4, 4, [0, 0, 0, 255])).unwrap();
+
+    match load_block_images(&dir) {
+        Err(EngineError::TextureLoading { path, .. }) => assert!(path.ends_with("b.png")),
+        other => panic!("expected a texture loading error, got {:?}", other.map(|images| images.names)),
+    }
+}
+
+#[test]
+fn empty_directory_is_an_error() {
+    let dir = texture_dir("empty");
+    assert!(matches!(load_block_images(&dir), Err(EngineError::TextureLoading { .. })));
+}
@@ -0,0 +1,118 @@
+use std::f32::consts::FRAC_PI_2;
+use std::time::Duration;
+
+use voxel_engine::camera::*;
+use winit::keyboard::KeyCode;
+
+const EPSILON: f32 = 1e-4;
+
+fn assert_close(actual: &[f32], expected: &[f32]) {
+    assert_eq!(actual.len(), expected.len());
+    for (a, e) in actual.iter().zip(expected) {
+        assert!((a - e).abs() < EPSILON, "{:?} != {:?}", actual, expected);
+    }
+}
+
+/// World position to normalized device coordinates through `camera`.
+fn project(camera: &Camera, aspect: f32, point: Vec3) -> [f32; 3] {
+    let view_projection = mul_mat4(&camera.projection_matrix(aspect), &camera.view_matrix());
+    let clip = transform(&view_projection, [point[0], point[1], point[2], 1.0]);
+    [clip[0] / clip[3], clip[1] / clip[3], clip[2] / clip[3]]
+}
+
+#[test]
+fn zero_yaw_looks_down_negative_z() {
+    let camera = Camera::new([0.0; 3], 0.0, 0.0);
+    assert_close(&camera.forward(), &[0.0, 0.0, -1.0]);
+    assert_close(&camera.right(), &[1.0, 0.0, 0.0]);
+
+    let turned = Camera::new([0.0; 3], FRAC_PI_2, 0.0);
+    assert_close(&turned.forward(), &[1.0, 0.0, 0.0]);
+}
+
+#[test]
+fn view_matrix_moves_the_camera_to_the_origin() {
+    let camera = Camera::new([1.0, 2.0, 3.0], 0.7, -0.3);
+    let view = camera.view_matrix();
+
+    let eye = transform(&view, [1.0, 2.0, 3.0, 1.0]);
+    assert_close(&eye, &[0.0, 0.0, 0.0, 1.0]);
+
+    let forward = camera.forward();
+    let ahead = [1.0 + forward[0] * 5.0, 2.0 + forward[1] * 5.0, 3.0 + forward[2] * 5.0, 1.0];
+    assert_close(&transform(&view, ahead), &[0.0, 0.0, -5.0, 1.0]);
+}
+
+#[test]
+fn perspective_is_reverse_z_with_y_down() {
+    let mut camera = Camera::new([0.0; 3], 0.0, 0.0);
+    camera.projection = Projection::Perspective { fov_y: FRAC_PI_2, near: 0.5, far: 100.0 };
+
+    assert_close(&project(&camera, 1.0, [0.0, 0.0, -0.5]), &[0.0, 0.0, 1.0]);
+    assert_close(&project(&camera, 1.0, [0.0, 0.0, -100.0]), &[0.0, 0.0, 0.0]);
+    // At 90 degrees the top edge of the view is as far up as the point is away; it lands at -1
+    let top = project(&camera, 1.0, [0.0, 10.0, -10.0]);
+    assert_close(&top[..2], &[0.0, -1.0]);
+    // Wider than tall: the same offset sideways covers less of the screen
+    let right = project(&camera, 2.0, [10.0, 0.0, -10.0]);
+    assert_close(&right[..2], &[0.5, 0.0]);
+}
+
+#[test]
+fn depth_decreases_with_distance() {
+    let camera = Camera::new([0.0; 3], 0.0, 0.0);
+    let near = project(&camera, 1.0, [0.0, 0.0, -1.0])[2];
+    let far = project(&camera, 1.0, [0.0, 0.0, -50.0])[2];
+    assert!(near > far && far > 0.0 && near < 1.0);
+}
+
+#[test]
+fn orthographic_is_reverse_z_with_y_down() {
+    let mut camera = Camera::new([0.0; 3], 0.0, 0.0);
+    camera.projection = Projection::Orthographic { height: 4.0, near: 1.0, far: 9.0 };
+
+    assert_close(&project(&camera, 2.0, [4.0, 2.0, -1.0]), &[1.0, -1.0, 1.0]);
+    assert_close(&project(&camera, 2.0, [-4.0, -2.0, -9.0]), &[-1.0, 1.0, 0.0]);
+    assert_close(&project(&camera, 2.0, [0.0, 0.0, -5.0]), &[0.0, 0.0, 0.5]);
+}
+
+#[test]
+fn pitch_is_clamped_short_of_vertical() {
+    let mut camera = Camera::new([0.0; 3], 0.0, 0.0);
+    camera.rotate(0.0, 10.0);
+    assert_eq!(camera.pitch, MAX_PITCH);
+    camera.rotate(0.0, -20.0);
+    assert_eq!(camera.pitch, -MAX_PITCH);
+}
+
+#[test]
+fn controller_only_reacts_while_grabbed() {
+    let mut camera = Camera::new([0.0; 3], 0.0, 0.0);
+    let mut controller = FlyController::default();
+
+    controller.handle_key(KeyCode::KeyW, true);
+    controller.handle_mouse_motion(100.0, 0.0);
+    controller.update(&mut camera, Duration::from_secs(1));
+    assert_eq!(camera, Camera::new([0.0; 3], 0.0, 0.0));
+
+    controller.set_grabbed(true);
+    controller.handle_key(KeyCode::KeyW, true);
+    controller.update(&mut camera, Duration::from_millis(500));
+    assert_close(&camera.position, &[0.0, 0.0, -controller.speed * 0.5]);
+
+    controller.handle_mouse_motion(100.0, 0.0);
+    controller.update(&mut camera, Duration::ZERO);
+    assert!((camera.yaw - 100.0 * controller.sensitivity).abs() < EPSILON);
+}
+
+#[test]
+fn diagonal_movement_is_not_faster() {
+    let mut camera = Camera::new([0.0; 3], 0.0, 0.0);
+    let mut controller = FlyController::default();
+    controller.set_grabbed(true);
+    controller.handle_key(KeyCode::KeyW, true);
+    controller.handle_key(KeyCode::KeyD, true);
+    controller.update(&mut camera, Duration::from_secs(1));
+
+    assert!((dot(camera.position, camera.position).sqrt() - controller.speed).abs() < EPSILON);
+}
@@ -0,0 +1,157 @@
+// Each test binary that includes this module uses only part of it
+#![allow(dead_code)]
+
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+use voxel_engine::vulkan::debug::validation_error_count;
+use voxel_engine::vulkan::headless::{write_png, HeadlessRenderer};
+
+/// Set to `1` to rewrite the reference images from the current output instead of comparing.
+pub const UPDATE_ENV: &str = "VOXEL_UPDATE_GOLDEN";
+/// Set to `1` on CI machines that have a (software) Vulkan driver so a missing driver fails the
+/// test instead of skipping it.
+pub const REQUIRE_VULKAN_ENV: &str = "VOXEL_REQUIRE_VULKAN";
+
+pub struct RgbaImage {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+pub struct Comparison {
+    pub mismatched_pixels: usize,
+    pub max_channel_difference: u8,
+    /// Mismatches in red over a dimmed copy of the expected image.
+    pub diff: RgbaImage,
+}
+
+/// A fresh, empty directory under `target/<group>/<name>` for one test.
+pub fn scratch_dir(group: &str, name: &str) -> PathBuf {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("target").join(group).join(name);
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+pub fn golden_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("golden")
+}
+
+pub fn diff_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("target").join("golden-diffs")
+}
+
+/// Creates a headless renderer, or returns `None` (after logging why) when the machine has no
+/// usable Vulkan driver and `VOXEL_REQUIRE_VULKAN` is not set.
+pub fn headless_renderer(width: u32, height: u32) -> Option<HeadlessRenderer> {
+    // Surface validation messages in the test output
+    let _ = env_logger::builder().is_test(true).try_init();
+
+    match HeadlessRenderer::new(width, height) {
+        Ok(renderer) => Some(renderer),
+        Err(e) if std::env::var(REQUIRE_VULKAN_ENV).as_deref() != Ok("1") => {
+            eprintln!("Skipping renderer test, Vulkan is not available: {}", e);
+            None
+        }
+        Err(e) => panic!("Vulkan is required but not available: {}", e),
+    }
+}
+
+pub fn read_png(path: &Path) -> RgbaImage {
+    let decoder = png::Decoder::new(File::open(path).unwrap_or_else(|_| panic!("Failed to open {:?}", path)));
+    let mut reader = decoder.read_info().expect("Failed to read PNG header");
+    let mut pixels = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut pixels).expect("Failed to decode PNG");
+    assert_eq!(info.color_type, png::ColorType::Rgba, "Reference {:?} must be RGBA", path);
+    assert_eq!(info.bit_depth, png::BitDepth::Eight, "Reference {:?} must be 8 bit", path);
+    pixels.truncate(info.buffer_size());
+
+    RgbaImage {
+        width: info.width,
+        height: info.height,
+        pixels,
+    }
+}
+
+/// A pixel mismatches when any of its channels differs by more than `tolerance`.
+pub fn compare(expected: &RgbaImage, actual: &RgbaImage, tolerance: u8) -> Comparison {
+    assert_eq!(
+        (expected.width, expected.height),
+        (actual.width, actual.height),
+        "Image sizes differ"
+    );
+
+    let mut mismatched_pixels = 0;
+    let mut max_channel_difference = 0;
+    let mut diff = Vec::with_capacity(expected.pixels.len());
+
+    for (e, a) in expected.pixels.chunks_exact(4).zip(actual.pixels.chunks_exact(4)) {
+        let difference = e.iter().zip(a).map(|(&e, &a)| e.abs_diff(a)).max().unwrap();
+        max_channel_difference = max_channel_difference.max(difference);
+
+        if difference > tolerance {
+            mismatched_pixels += 1;
+            diff.extend_from_slice(&[255, 0, 0, 255]);
+        } else {
+            let luma = ((e[0] as u32 * 3 + e[1] as u32 * 6 + e[2] as u32) / 10 / 4) as u8;
+            diff.extend_from_slice(&[luma, luma, luma, 255]);
+        }
+    }
+
+    Comparison {
+        mismatched_pixels,
+        max_channel_difference,
+        diff: RgbaImage {
+            width: expected.width,
+            height: expected.height,
+            pixels: diff,
+        },
+    }
+}
+
+/// Compares `actual` against `tests/golden/<name>.png`. On failure the actual frame and a diff
+/// image are written to `target/golden-diffs/` and the test panics.
+pub fn assert_matches_golden(name: &str, actual: &RgbaImage, tolerance: u8) {
+    let reference_path = golden_dir().join(format!("{}.png", name));
+
+    if std::env::var(UPDATE_ENV).as_deref() == Ok("1") {
+        write_png(&reference_path, actual.width, actual.height, &actual.pixels).expect("Failed to write reference image");
+        eprintln!("Updated reference image {:?}", reference_path);
+        return;
+    }
+
+    let expected = read_png(&reference_path);
+    let comparison = compare(&expected, actual, tolerance);
+    if comparison.mismatched_pixels == 0 {
+        return;
+    }
+
+    let output_dir = diff_dir();
+    fs::create_dir_all(&output_dir).expect("Failed to create diff directory");
+    let actual_path = output_dir.join(format!("{}.actual.png", name));
+    let diff_path = output_dir.join(format!("{}.diff.png", name));
+    write_png(&actual_path, actual.width, actual.height, &actual.pixels).expect("Failed to write actual image");
+    write_png(&diff_path, comparison.diff.width, comparison.diff.height, &comparison.diff.pixels).expect("Failed to write diff image");
+
+    panic!(
+        "{}: {} of {} pixels differ by more than {} (max difference {}). Actual: {:?}, diff: {:?}",
+        name,
+        comparison.mismatched_pixels,
+        actual.width * actual.height,
+        tolerance,
+        comparison.max_channel_difference,
+        actual_path,
+        diff_path,
+    );
+}
+
+/// Fails the test if the validation layer reported any error so far. Validation is on for test
+/// builds whenever the layer is installed (see `VOXEL_VALIDATION`).
+pub fn assert_no_validation_errors(renderer: &HeadlessRenderer) {
+    if !renderer.validation_enabled() {
+        eprintln!("Validation layer not active, skipping validation check");
+        return;
+    }
+    assert_eq!(validation_error_count(), 0, "Vulkan validation reported errors, see the log output");
+}
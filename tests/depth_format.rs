@@ -0,0 +1,48 @@
+use ash::vk;
+use voxel_engine::vulkan::depth::*;
+
+const DEPTH_ATTACHMENT: vk::FormatFeatureFlags = vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT;
+
+#[test]
+fn prefers_d32_when_supported() {
+    let format = select_format(&DEPTH_FORMAT_CANDIDATES, DEPTH_ATTACHMENT, |_| DEPTH_ATTACHMENT);
+    assert_eq!(format, Some(vk::Format::D32_SFLOAT));
+}
+
+#[test]
+fn falls_back_to_x8_d24() {
+    let format = select_format(&DEPTH_FORMAT_CANDIDATES, DEPTH_ATTACHMENT, |format| match format {
+        vk::Format::D32_SFLOAT => vk::FormatFeatureFlags::SAMPLED_IMAGE,
+        _ => DEPTH_ATTACHMENT | vk::FormatFeatureFlags::SAMPLED_IMAGE,
+    });
+    assert_eq!(format, Some(vk::Format::X8_D24_UNORM_PACK32));
+}
+
+#[test]
+fn falls_back_to_stencil_formats() {
+    let format = select_format(&DEPTH_FORMAT_CANDIDATES, DEPTH_ATTACHMENT, |format| match format {
+        vk::Format::D32_SFLOAT_S8_UINT => DEPTH_ATTACHMENT,
+        _ => vk::FormatFeatureFlags::SAMPLED_IMAGE,
+    });
+    assert_eq!(format, Some(vk::Format::D32_SFLOAT_S8_UINT));
+}
+
+#[test]
+fn no_candidate_supported() {
+    let format = select_format(&DEPTH_FORMAT_CANDIDATES, DEPTH_ATTACHMENT, |_| vk::FormatFeatureFlags::empty());
+    assert_eq!(format, None);
+}
+
+#[test]
+fn stencil_formats_view_both_aspects() {
+    assert_eq!(depth_aspect_mask(vk::Format::D32_SFLOAT), vk::ImageAspectFlags::DEPTH);
+    assert_eq!(
+        depth_aspect_mask(vk::Format::D24_UNORM_S8_UINT),
+        vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL
+    );
+    assert_eq!(depth_aspect_mask(vk::Format::X8_D24_UNORM_PACK32), vk::ImageAspectFlags::DEPTH);
+    assert_eq!(
+        depth_aspect_mask(vk::Format::D32_SFLOAT_S8_UINT),
+        vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL
+    );
+}
@@ -0,0 +1,85 @@
+use ash::vk;
+use voxel_engine::vulkan::device::{device_feature_score, device_matches_preference, device_type_score, QueueFamilies};
+
+#[test]
+fn gpus_outrank_software_rasterizers() {
+    let discrete = device_type_score(vk::PhysicalDeviceType::DISCRETE_GPU);
+    let integrated = device_type_score(vk::PhysicalDeviceType::INTEGRATED_GPU);
+    let cpu = device_type_score(vk::PhysicalDeviceType::CPU);
+
+    assert!(discrete > integrated);
+    assert!(integrated > cpu);
+    assert!(cpu > 0, "CPU devices must stay selectable as a fallback");
+}
+
+#[test]
+fn features_never_outweigh_device_type() {
+    let all_features = vk::PhysicalDeviceFeatures {
+        sampler_anisotropy: vk::TRUE,
+        fill_mode_non_solid: vk::TRUE,
+        multi_draw_indirect: vk::TRUE,
+        geometry_shader: vk::TRUE,
+        ..Default::default()
+    };
+
+    assert_eq!(device_feature_score(&vk::PhysicalDeviceFeatures::default()), 0);
+    assert!(
+        device_type_score(vk::PhysicalDeviceType::INTEGRATED_GPU) + device_feature_score(&all_features)
+            < device_type_score(vk::PhysicalDeviceType::DISCRETE_GPU)
+    );
+}
+
+#[test]
+fn preference_matches_index_or_name() {
+    assert!(device_matches_preference("1", 1, "llvmpipe (LLVM 17.0.6, 256 bits)"));
+    assert!(!device_matches_preference("0", 1, "llvmpipe (LLVM 17.0.6, 256 bits)"));
+    assert!(device_matches_preference("LLVMpipe", 1, "llvmpipe (LLVM 17.0.6, 256 bits)"));
+    assert!(device_matches_preference(" nvidia ", 0, "NVIDIA GeForce RTX 3070"));
+    assert!(!device_matches_preference("radeon", 0, "NVIDIA GeForce RTX 3070"));
+    assert!(!device_matches_preference("", 0, "NVIDIA GeForce RTX 3070"));
+}
+
+fn family(queue_flags: vk::QueueFlags) -> vk::QueueFamilyProperties {
+    vk::QueueFamilyProperties {
+        queue_flags,
+        queue_count: 1,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn single_family_serves_every_role() {
+    let families = [family(vk::QueueFlags::GRAPHICS | vk::QueueFlags::COMPUTE | vk::QueueFlags::TRANSFER)];
+    let selected = QueueFamilies::select(&families, |_| true).unwrap();
+
+    assert_eq!(selected, QueueFamilies { graphics: 0, present: 0, transfer: 0, compute: 0 });
+    assert_eq!(selected.unique(), vec![0]);
+    assert!(!selected.has_dedicated_transfer());
+    assert!(!selected.has_async_compute());
+}
+
+#[test]
+fn dedicated_transfer_and_compute_families_are_preferred() {
+    let families = [
+        family(vk::QueueFlags::GRAPHICS | vk::QueueFlags::COMPUTE | vk::QueueFlags::TRANSFER),
+        family(vk::QueueFlags::COMPUTE | vk::QueueFlags::TRANSFER),
+        family(vk::QueueFlags::TRANSFER),
+    ];
+    let selected = QueueFamilies::select(&families, |_| true).unwrap();
+
+    assert_eq!(selected, QueueFamilies { graphics: 0, present: 0, transfer: 2, compute: 1 });
+    assert_eq!(selected.unique(), vec![0, 2, 1]);
+}
+
+#[test]
+fn present_falls_back_to_another_family() {
+    let families = [
+        family(vk::QueueFlags::GRAPHICS | vk::QueueFlags::TRANSFER),
+        family(vk::QueueFlags::COMPUTE),
+    ];
+    let selected = QueueFamilies::select(&families, |index| index == 1).unwrap();
+
+    assert_eq!(selected.graphics, 0);
+    assert_eq!(selected.present, 1);
+    assert!(QueueFamilies::select(&families, |_| false).is_none());
+}
@@ -0,0 +1,24 @@
+use voxel_engine::vulkan::shaders::*;
+
+const SPIRV_MAGIC: u32 = 0x0723_0203;
+
+#[test]
+fn scene_shaders_are_embedded() {
+    for name in [SCENE_VERTEX_SHADER, SCENE_FRAGMENT_SHADER] {
+        let words = embedded_shader(name).unwrap_or_else(|| panic!("{} isn't embedded", name));
+        assert_eq!(words[0], SPIRV_MAGIC, "{}", name);
+        assert_eq!(words.as_ptr() as usize % std::mem::align_of::<u32>(), 0);
+    }
+}
+
+#[test]
+fn only_shader_sources_are_embedded() {
+    assert!(embedded_shader("missing.vert").is_none());
+    assert!(embedded_shader("glsl.vert.spv").is_none());
+}
+
+#[test]
+fn words_are_little_endian() {
+    let bytes = [0x03, 0x02, 0x23, 0x07, 0x00, 0x00, 0x01, 0x00];
+    assert_eq!(spirv_words(&bytes), [SPIRV_MAGIC, 0x0001_0000]);
+}
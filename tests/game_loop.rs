@@ -0,0 +1,66 @@
+use std::time::{Duration, Instant};
+
+use voxel_engine::game_loop::*;
+
+const TICK: Duration = Duration::from_millis(10);
+
+#[test]
+fn whole_ticks_run_and_the_remainder_becomes_alpha() {
+    let mut timestep = FixedTimestep::new(100);
+    assert_eq!(timestep.tick(), TICK);
+
+    let timing = timestep.advance(Duration::from_millis(25));
+    assert_eq!(timing.ticks, 2);
+    assert!((timing.alpha - 0.5).abs() < 1e-4);
+    assert_eq!(timing.dropped, Duration::ZERO);
+
+    // The leftover 5ms carries into the next frame
+    let timing = timestep.advance(Duration::from_millis(5));
+    assert_eq!(timing.ticks, 1);
+    assert!(timing.alpha.abs() < 1e-4);
+}
+
+#[test]
+fn long_frames_are_clamped() {
+    let mut timestep = FixedTimestep::new(20);
+
+    let timing = timestep.advance(Duration::from_secs(2));
+    assert_eq!(timing.frame_time, Duration::from_secs(2));
+    assert_eq!(timing.ticks, 5);
+    assert_eq!(timing.dropped, Duration::from_secs(2) - MAX_FRAME_TIME);
+}
+
+#[test]
+fn ticks_beyond_the_limit_are_dropped() {
+    let mut timestep = FixedTimestep::new(100);
+
+    let timing = timestep.advance(Duration::from_millis(125));
+    assert_eq!(timing.ticks, MAX_TICKS_PER_FRAME);
+    assert_eq!(timing.dropped, Duration::from_millis(40));
+    assert!((timing.alpha - 0.5).abs() < 1e-4);
+}
+
+#[test]
+fn first_frame_and_resumed_frames_run_no_ticks() {
+    let mut game_loop = GameLoop::new(100, None);
+    let start = Instant::now();
+
+    assert_eq!(game_loop.begin_frame(start).ticks, 0);
+    assert_eq!(game_loop.begin_frame(start + Duration::from_millis(30)).ticks, 3);
+
+    game_loop.pause();
+    assert_eq!(game_loop.begin_frame(start + Duration::from_secs(10)).ticks, 0);
+}
+
+#[test]
+fn frame_cap_schedules_the_next_frame() {
+    let mut game_loop = GameLoop::new(60, Some(50));
+    assert_eq!(game_loop.next_frame_at(), None);
+
+    let start = Instant::now();
+    game_loop.begin_frame(start);
+    assert_eq!(game_loop.next_frame_at(), Some(start + Duration::from_millis(20)));
+
+    game_loop.set_frame_cap(None);
+    assert_eq!(game_loop.next_frame_at(), None);
+}
@@ -0,0 +1,60 @@
+mod common;
+
+use common::*;
+
+/// Per-channel difference allowed between drivers (rasterization rules and rounding vary slightly).
+const TOLERANCE: u8 = 2;
+
+fn render_scene(name: &str, width: u32, height: u32) {
+    let Some(mut renderer) = headless_renderer(width, height) else {
+        return;
+    };
+    let image = RgbaImage {
+        width,
+        height,
+        pixels: renderer.render().expect("Failed to render offscreen frame"),
+    };
+    assert_matches_golden(name, &image, TOLERANCE);
+    assert_no_validation_errors(&renderer);
+}
+
+#[test]
+fn triangle_pipeline_64x64() {
+    render_scene("triangle_pipeline_64x64", 64, 64);
+}
+
+#[test]
+fn triangle_pipeline_320x180() {
+    render_scene("triangle_pipeline_320x180", 320, 180);
+}
+
+#[test]
+fn compare_accepts_differences_within_tolerance() {
+    let expected = RgbaImage { width: 2, height: 1, pixels: vec![10, 20, 30, 255, 0, 0, 0, 255] };
+    let actual = RgbaImage { width: 2, height: 1, pixels: vec![12, 18, 30, 255, 0, 0, 1, 255] };
+
+    let comparison = compare(&expected, &actual, 2);
+    assert_eq!(comparison.mismatched_pixels, 0);
+    assert_eq!(comparison.max_channel_difference, 2);
+}
+
+#[test]
+fn compare_marks_mismatches_in_diff_image() {
+    let expected = RgbaImage { width: 2, height: 1, pixels: vec![0, 0, 0, 255, 0, 0, 0, 255] };
+    let actual = RgbaImage { width: 2, height: 1, pixels: vec![0, 0, 0, 255, 200, 0, 0, 255] };
+
+    let comparison = compare(&expected, &actual, 2);
+    assert_eq!(comparison.mismatched_pixels, 1);
+    assert_eq!(comparison.max_channel_difference, 200);
+    assert_eq!(&comparison.diff.pixels[4..8], &[255, 0, 0, 255]);
+    assert_eq!(&comparison.diff.pixels[0..4], &[0, 0, 0, 255]);
+}
+
+#[test]
+fn reference_images_are_readable() {
+    for (name, width, height) in [("triangle_pipeline_64x64", 64, 64), ("triangle_pipeline_320x180", 320, 180)] {
+        let image = read_png(&golden_dir().join(format!("{}.png", name)));
+        assert_eq!((image.width, image.height), (width, height), "{}", name);
+        assert_eq!(image.pixels.len(), (width * height * 4) as usize, "{}", name);
+    }
+}
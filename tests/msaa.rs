@@ -0,0 +1,45 @@
+use ash::vk;
+use voxel_engine::vulkan::msaa::*;
+
+const UP_TO_4: vk::SampleCountFlags = vk::SampleCountFlags::from_raw(0b111);
+const ALL: vk::SampleCountFlags = vk::SampleCountFlags::from_raw(0b1111111);
+
+#[test]
+fn clamps_to_the_device_limit() {
+    assert_eq!(clamp_sample_count(8, UP_TO_4), vk::SampleCountFlags::TYPE_4);
+    assert_eq!(clamp_sample_count(8, ALL), vk::SampleCountFlags::TYPE_8);
+    assert_eq!(clamp_sample_count(2, ALL), vk::SampleCountFlags::TYPE_2);
+}
+
+#[test]
+fn odd_and_oversized_requests_round_down() {
+    assert_eq!(clamp_sample_count(3, ALL), vk::SampleCountFlags::TYPE_2);
+    // 16x and up are never picked, even when the device has them
+    assert_eq!(clamp_sample_count(64, ALL), vk::SampleCountFlags::TYPE_8);
+}
+
+#[test]
+fn zero_and_one_disable_msaa() {
+    assert_eq!(clamp_sample_count(0, ALL), vk::SampleCountFlags::TYPE_1);
+    assert_eq!(clamp_sample_count(1, ALL), vk::SampleCountFlags::TYPE_1);
+    assert_eq!(clamp_sample_count(4, vk::SampleCountFlags::TYPE_1), vk::SampleCountFlags::TYPE_1);
+}
+
+#[test]
+fn cycling_wraps_at_the_highest_supported_count() {
+    let mut samples = vk::SampleCountFlags::TYPE_1;
+    let mut seen = Vec::new();
+    for _ in 0..4 {
+        samples = next_sample_count(samples, UP_TO_4);
+        seen.push(samples);
+    }
+    assert_eq!(
+        seen,
+        [
+            vk::SampleCountFlags::TYPE_2,
+            vk::SampleCountFlags::TYPE_4,
+            vk::SampleCountFlags::TYPE_1,
+            vk::SampleCountFlags::TYPE_2,
+        ]
+    );
+}
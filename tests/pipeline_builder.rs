@@ -0,0 +1,101 @@
+use ash::vk;
+use voxel_engine::vulkan::pipeline::*;
+
+#[test]
+fn defaults_match_the_scene() {
+    let builder = PipelineBuilder::new();
+    assert_eq!(builder.dynamic_states(), [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR]);
+
+    let rasterization = builder.rasterization_state();
+    assert_eq!(rasterization.polygon_mode, vk::PolygonMode::FILL);
+    assert_eq!(rasterization.cull_mode, vk::CullModeFlags::BACK);
+    assert_eq!(rasterization.front_face, vk::FrontFace::COUNTER_CLOCKWISE);
+    assert_eq!(rasterization.line_width, 1.0);
+
+    let depth = builder.depth_stencil_state();
+    assert_eq!((depth.depth_test_enable, depth.depth_write_enable), (vk::TRUE, vk::TRUE));
+    assert_eq!(depth.depth_compare_op, vk::CompareOp::GREATER_OR_EQUAL);
+    assert_eq!(depth.stencil_test_enable, vk::FALSE);
+
+    let [attachment] = builder.color_blend_attachments()[..] else {
+        panic!("expected one color attachment");
+    };
+    assert_eq!(attachment.blend_enable, vk::FALSE);
+    assert_eq!(attachment.color_write_mask, vk::ColorComponentFlags::RGBA);
+}
+
+#[test]
+fn fixed_extent_drops_dynamic_viewport_and_scissor() {
+    let builder = PipelineBuilder::new()
+        .dynamic_state(vk::DynamicState::LINE_WIDTH)
+        .dynamic_state(vk::DynamicState::LINE_WIDTH)
+        .fixed_extent(vk::Extent2D { width: 640, height: 480 });
+    assert_eq!(builder.dynamic_states(), [vk::DynamicState::LINE_WIDTH]);
+}
+
+#[test]
+fn state_setters_apply() {
+    let stencil = vk::StencilOpState {
+        pass_op: vk::StencilOp::REPLACE,
+        compare_op: vk::CompareOp::ALWAYS,
+        write_mask: 0xff,
+        reference: 1,
+        ..Default::default()
+    };
+    let builder = PipelineBuilder::new()
+        .polygon_mode(vk::PolygonMode::LINE)
+        .cull_mode(vk::CullModeFlags::NONE, vk::FrontFace::CLOCKWISE)
+        .no_depth()
+        .stencil(stencil, stencil)
+        .color_attachments(&[BlendMode::Alpha, BlendMode::Additive]);
+
+    let rasterization = builder.rasterization_state();
+    assert_eq!((rasterization.polygon_mode, rasterization.cull_mode), (vk::PolygonMode::LINE, vk::CullModeFlags::NONE));
+    let depth = builder.depth_stencil_state();
+    assert_eq!((depth.depth_test_enable, depth.depth_write_enable), (vk::FALSE, vk::FALSE));
+    assert_eq!(depth.stencil_test_enable, vk::TRUE);
+    assert_eq!(depth.front.pass_op, vk::StencilOp::REPLACE);
+    assert_eq!(builder.color_blend_attachments().len(), 2);
+}
+
+#[test]
+fn blend_modes() {
+    let alpha = BlendMode::Alpha.attachment_state();
+    assert_eq!(alpha.blend_enable, vk::TRUE);
+    assert_eq!(
+        (alpha.src_color_blend_factor, alpha.dst_color_blend_factor),
+        (vk::BlendFactor::SRC_ALPHA, vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+    );
+
+    let premultiplied = BlendMode::PremultipliedAlpha.attachment_state();
+    assert_eq!(premultiplied.src_color_blend_factor, vk::BlendFactor::ONE);
+
+    let additive = BlendMode::Additive.attachment_state();
+    assert_eq!((additive.dst_color_blend_factor, additive.dst_alpha_blend_factor), (vk::BlendFactor::ONE, vk::BlendFactor::ONE));
+
+    assert_eq!(BlendMode::Opaque.attachment_state().blend_enable, vk::FALSE);
+}
+
+#[test]
+fn specialization_constants_are_packed_in_order() {
+    let constants = SpecializationConstants::new().add_u32(0, 16).add_bool(3, true).add_f32(1, 0.5);
+
+    let entries: Vec<_> = constants.entries().iter().map(|entry| (entry.constant_id, entry.offset, entry.size)).collect();
+    assert_eq!(entries, [(0, 0, 4), (3, 4, 4), (1, 8, 4)]);
+
+    let mut expected = Vec::new();
+    expected.extend_from_slice(&16u32.to_ne_bytes());
+    expected.extend_from_slice(&1u32.to_ne_bytes());
+    expected.extend_from_slice(&0.5f32.to_ne_bytes());
+    assert_eq!(constants.data(), expected);
+    assert!(SpecializationConstants::new().is_empty());
+}
+
+#[test]
+fn viewport_covers_the_extent() {
+    let extent = vk::Extent2D { width: 1280, height: 720 };
+    let viewport = full_viewport(extent);
+    assert_eq!((viewport.x, viewport.y, viewport.width, viewport.height), (0.0, 0.0, 1280.0, 720.0));
+    assert_eq!((viewport.min_depth, viewport.max_depth), (0.0, 1.0));
+    assert_eq!(full_scissor(extent).extent, extent);
+}
@@ -0,0 +1,60 @@
+use voxel_engine::vulkan::pipeline_cache::*;
+
+const KEY: CacheKey = CacheKey {
+    vendor_id: 0x10de,
+    device_id: 0x2684,
+    driver_version: 0x8a2d_c000,
+    pipeline_cache_uuid: [7; 16],
+};
+
+const DATA: &[u8] = b"driver-specific pipeline cache data";
+
+#[test]
+fn round_trips_for_the_same_device() {
+    let file = encode_cache_file(&KEY, DATA);
+    assert_eq!(decode_cache_file(&KEY, &file), Ok(DATA));
+    assert_eq!(decode_cache_file(&KEY, &encode_cache_file(&KEY, &[])), Ok(&[][..]));
+}
+
+#[test]
+fn other_devices_and_drivers_are_rejected() {
+    let file = encode_cache_file(&KEY, DATA);
+    let others = [
+        CacheKey { vendor_id: 0x1002, ..KEY },
+        CacheKey { device_id: 0x2704, ..KEY },
+        CacheKey { driver_version: KEY.driver_version + 1, ..KEY },
+        CacheKey { pipeline_cache_uuid: [8; 16], ..KEY },
+    ];
+    for other in others {
+        assert!(decode_cache_file(&other, &file).is_err(), "{:?}", other);
+    }
+}
+
+#[test]
+fn corrupt_files_are_rejected() {
+    let file = encode_cache_file(&KEY, DATA);
+
+    let mut flipped = file.clone();
+    *flipped.last_mut().unwrap() ^= 1;
+    assert!(decode_cache_file(&KEY, &flipped).is_err());
+
+    assert!(decode_cache_file(&KEY, &file[..file.len() - 1]).is_err());
+    assert!(decode_cache_file(&KEY, &file[..10]).is_err());
+    assert!(decode_cache_file(&KEY, b"").is_err());
+
+    let mut foreign = file.clone();
+    foreign[..4].copy_from_slice(b"\x7fELF");
+    assert!(decode_cache_file(&KEY, &foreign).is_err());
+}
+
+#[test]
+fn cache_dir_is_per_user() {
+    // Only meaningful without an override, which nothing in the test suite sets
+    if std::env::var_os(PIPELINE_CACHE_DIR_ENV).is_some() {
+        return;
+    }
+    if let Some(dir) = default_cache_dir() {
+        assert!(dir.ends_with("voxel_engine"), "{:?}", dir);
+        assert!(dir.is_absolute(), "{:?}", dir);
+    }
+}
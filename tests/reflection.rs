@@ -0,0 +1,149 @@
+use ash::vk;
+use voxel_engine::vulkan::descriptors::scene_descriptor_layout;
+use voxel_engine::vulkan::mesh::Vertex;
+use voxel_engine::vulkan::other::scene_pipeline_interface;
+use voxel_engine::vulkan::reflection::*;
+use voxel_engine::vulkan::shaders::*;
+
+fn reflect(name: &str) -> ShaderReflection {
+    ShaderReflection::new(embedded_shader(name).unwrap()).unwrap()
+}
+
+/// Assembles a module with a vertex entry point followed by `instructions`.
+fn module(instructions: &[&[u32]]) -> Vec<u32> {
+    let mut words = vec![0x0723_0203, 0x0001_0000, 0, 100, 0];
+    let name = u32::from_le_bytes(*b"main");
+    for instruction in [&[15, 0, 1, name, 0][..]].iter().chain(instructions) {
+        words.push(((instruction.len() as u32) << 16) | instruction[0]);
+        words.extend_from_slice(&instruction[1..]);
+    }
+    words
+}
+
+/// `layout(push_constant) uniform { ... }` with one member per (type, offset); `%2` is `float`,
+/// `%3` is `vec4` and `%4` is `mat4` with a 16 byte matrix stride.
+fn push_constant_module(members: &[(u32, u32)]) -> Vec<u32> {
+    let mut instructions: Vec<Vec<u32>> = vec![vec![22, 2, 32], vec![23, 3, 2, 4], vec![24, 4, 3, 4], vec![71, 10, 2]];
+    let mut block = vec![30, 10];
+    for (index, &(type_id, offset)) in members.iter().enumerate() {
+        block.push(type_id);
+        instructions.push(vec![72, 10, index as u32, 35, offset]);
+        if type_id == 4 {
+            instructions.push(vec![72, 10, index as u32, 7, 16]);
+        }
+    }
+    instructions.extend([block, vec![32, 11, 9, 10], vec![59, 11, 12, 9]]);
+    module(&instructions.iter().map(Vec::as_slice).collect::<Vec<_>>())
+}
+
+#[test]
+fn scene_vertex_shader_interface() {
+    let vertex = reflect(SCENE_VERTEX_SHADER);
+    assert_eq!(vertex.stage, vk::ShaderStageFlags::VERTEX);
+    assert_eq!(vertex.entry_point, "main");
+
+    let inputs: Vec<_> = vertex.inputs.iter().map(|input| (input.location, input.format, input.name.as_str())).collect();
+    assert_eq!(
+        inputs,
+        [(0, vk::Format::R32G32B32_SFLOAT, "inPosition"), (1, vk::Format::R32G32B32_SFLOAT, "inColor")]
+    );
+    assert_eq!(vertex.outputs.len(), 1, "gl_PerVertex is left out");
+    assert_eq!(
+        vertex.descriptor_bindings,
+        [DescriptorBinding {
+            set: 0,
+            binding: 0,
+            descriptor_type: vk::DescriptorType::UNIFORM_BUFFER,
+            count: 1,
+            stages: vk::ShaderStageFlags::VERTEX,
+            name: "ubo".to_string(),
+        }]
+    );
+    assert!(vertex.push_constants.is_none());
+}
+
+#[test]
+fn scene_shaders_match_vertex_and_layout() {
+    let vertex = embedded_shader(SCENE_VERTEX_SHADER).unwrap();
+    let fragment = embedded_shader(SCENE_FRAGMENT_SHADER).unwrap();
+    let interface = scene_pipeline_interface(vertex, fragment).unwrap();
+
+    assert_eq!(interface.stage(vk::ShaderStageFlags::FRAGMENT).unwrap().outputs[0].format, vk::Format::R32G32B32A32_SFLOAT);
+    assert!(interface.push_constant_ranges().is_empty());
+
+    let (attributes, stride) = interface.stage(vk::ShaderStageFlags::VERTEX).unwrap().vertex_attributes(0).unwrap();
+    let described: Vec<_> = Vertex::attribute_descriptions()
+        .iter()
+        .map(|attribute| (attribute.location, attribute.binding, attribute.format, attribute.offset))
+        .collect();
+    let reflected: Vec<_> = attributes
+        .iter()
+        .map(|attribute| (attribute.location, attribute.binding, attribute.format, attribute.offset))
+        .collect();
+    assert_eq!(reflected, described);
+    assert_eq!(stride, Vertex::binding_description().stride);
+}
+
+#[test]
+fn vertex_attribute_mismatches_are_errors() {
+    let interface = PipelineInterface::new(vec![reflect(SCENE_VERTEX_SHADER)]).unwrap();
+    let [position, color] = Vertex::attribute_descriptions();
+
+    assert!(interface.validate_vertex_input(&[position]).is_err());
+    let integer_color = vk::VertexInputAttributeDescription { format: vk::Format::R32G32B32_SINT, ..color };
+    assert!(interface.validate_vertex_input(&[position, integer_color]).is_err());
+    // Normalized bytes read as floats; the missing component is fine
+    let packed_color = vk::VertexInputAttributeDescription { format: vk::Format::R8G8_UNORM, ..color };
+    assert_eq!(interface.validate_vertex_input(&[position, packed_color]), Ok(()));
+}
+
+#[test]
+fn set_layout_mismatches_are_errors() {
+    let interface = PipelineInterface::new(vec![reflect(SCENE_VERTEX_SHADER)]).unwrap();
+    let scene = scene_descriptor_layout();
+    let ubo = scene.bindings()[0];
+    assert_eq!(interface.validate_set_layout(0, &[ubo]), Ok(()));
+
+    let dynamic = vk::DescriptorSetLayoutBinding { descriptor_type: vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC, ..ubo };
+    assert_eq!(interface.validate_set_layout(0, &[dynamic]), Ok(()));
+
+    let storage = vk::DescriptorSetLayoutBinding { descriptor_type: vk::DescriptorType::STORAGE_BUFFER, ..ubo };
+    let fragment_only = vk::DescriptorSetLayoutBinding { stage_flags: vk::ShaderStageFlags::FRAGMENT, ..ubo };
+    let elsewhere = vk::DescriptorSetLayoutBinding { binding: 1, ..ubo };
+    for layout in [storage, fragment_only, elsewhere] {
+        assert!(interface.validate_set_layout(0, &[layout]).is_err(), "{:?}", layout);
+    }
+}
+
+#[test]
+fn stages_must_link() {
+    let vertex = reflect(SCENE_VERTEX_SHADER);
+    let mut fragment = reflect(SCENE_FRAGMENT_SHADER);
+    fragment.inputs[0].format = vk::Format::R32G32B32A32_SFLOAT;
+    assert!(PipelineInterface::new(vec![vertex.clone(), fragment.clone()]).is_err());
+
+    fragment.inputs[0].format = vk::Format::R32G32B32_SFLOAT;
+    fragment.inputs[0].location = 3;
+    assert!(PipelineInterface::new(vec![vertex, fragment]).is_err());
+}
+
+#[test]
+fn push_constant_ranges_follow_member_offsets() {
+    let mat4_and_vec4 = ShaderReflection::new(&push_constant_module(&[(4, 0), (3, 64)])).unwrap();
+    let range = mat4_and_vec4.push_constants.unwrap();
+    assert_eq!((range.stage_flags, range.offset, range.size), (vk::ShaderStageFlags::VERTEX, 0, 80));
+
+    let offset_vec4 = ShaderReflection::new(&push_constant_module(&[(3, 16)])).unwrap();
+    let range = offset_vec4.push_constants.unwrap();
+    assert_eq!((range.offset, range.size), (16, 16));
+
+    let interface = PipelineInterface::new(vec![mat4_and_vec4]).unwrap();
+    assert_eq!(interface.push_constant_ranges().len(), 1);
+}
+
+#[test]
+fn malformed_modules_are_errors() {
+    assert!(ShaderReflection::new(&[0; 8]).is_err());
+    assert!(ShaderReflection::new(&module(&[])[..6]).is_err(), "truncated instruction");
+    assert!(ShaderReflection::new(&module(&[])[..5]).is_err(), "no entry point");
+}
@@ -0,0 +1,67 @@
+use ash::vk;
+use voxel_engine::vulkan::rendering::*;
+
+fn attachments(msaa: bool, final_layout: vk::ImageLayout) -> RenderingAttachments {
+    RenderingAttachments {
+        target: vk::Handle::from_raw(1),
+        target_view: vk::Handle::from_raw(2),
+        msaa_color: msaa.then(|| (vk::Handle::from_raw(3), vk::Handle::from_raw(4))),
+        depth: vk::Handle::from_raw(5),
+        depth_view: vk::Handle::from_raw(6),
+        depth_format: vk::Format::D24_UNORM_S8_UINT,
+        final_layout,
+    }
+}
+
+#[test]
+fn render_path_names() {
+    assert_eq!(RenderPath::parse("dynamic"), Some(RenderPath::Dynamic));
+    assert_eq!(RenderPath::parse(" RenderPass "), Some(RenderPath::RenderPass));
+    assert_eq!(RenderPath::parse("render_pass"), Some(RenderPath::RenderPass));
+    assert_eq!(RenderPath::parse("forward"), None);
+}
+
+#[test]
+fn dynamic_rendering_is_preferred_where_supported() {
+    assert_eq!(RenderPath::select(None, true), RenderPath::Dynamic);
+    assert_eq!(RenderPath::select(None, false), RenderPath::RenderPass);
+    assert_eq!(RenderPath::select(Some(RenderPath::RenderPass), true), RenderPath::RenderPass);
+    assert_eq!(RenderPath::select(Some(RenderPath::Dynamic), false), RenderPath::RenderPass);
+}
+
+#[test]
+fn begin_barriers_move_attachments_into_attachment_layouts() {
+    let barriers = attachments(false, vk::ImageLayout::PRESENT_SRC_KHR).begin_barriers();
+    let layouts: Vec<_> = barriers.iter().map(|barrier| (barrier.image, barrier.old_layout, barrier.new_layout)).collect();
+    assert_eq!(
+        layouts,
+        [
+            (vk::Handle::from_raw(1), vk::ImageLayout::UNDEFINED, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL),
+            (vk::Handle::from_raw(5), vk::ImageLayout::UNDEFINED, vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL),
+        ]
+    );
+    assert_eq!(barriers[0].dst_stage_mask, vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT);
+    assert_eq!(barriers[1].subresource_range.aspect_mask, vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL);
+    assert!(barriers[1].src_access_mask.contains(vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_WRITE));
+}
+
+#[test]
+fn msaa_color_is_transitioned_too() {
+    let barriers = attachments(true, vk::ImageLayout::PRESENT_SRC_KHR).begin_barriers();
+    assert_eq!(barriers.len(), 3);
+    assert_eq!(barriers[1].image, vk::Handle::from_raw(3));
+    assert_eq!(barriers[1].new_layout, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+}
+
+#[test]
+fn end_barrier_targets_the_final_layout() {
+    let present = attachments(false, vk::ImageLayout::PRESENT_SRC_KHR).end_barrier();
+    assert_eq!((present.old_layout, present.new_layout), (vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL, vk::ImageLayout::PRESENT_SRC_KHR));
+    assert_eq!(present.src_access_mask, vk::AccessFlags2::COLOR_ATTACHMENT_WRITE);
+    assert_eq!(present.dst_stage_mask, vk::PipelineStageFlags2::NONE);
+
+    let readback = attachments(true, vk::ImageLayout::TRANSFER_SRC_OPTIMAL).end_barrier();
+    assert_eq!(readback.image, vk::Handle::from_raw(1));
+    assert_eq!(readback.new_layout, vk::ImageLayout::TRANSFER_SRC_OPTIMAL);
+    assert_eq!((readback.dst_stage_mask, readback.dst_access_mask), (vk::PipelineStageFlags2::TRANSFER, vk::AccessFlags2::TRANSFER_READ));
+}
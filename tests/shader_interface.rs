@@ -0,0 +1,52 @@
+use std::mem::{offset_of, size_of};
+
+use ash::vk;
+use voxel_engine::vulkan::descriptors::{UniformBufferObject, IDENTITY};
+use voxel_engine::vulkan::memory::as_bytes;
+use voxel_engine::vulkan::mesh::{check_mesh_data, Vertex, QUAD_INDICES, QUAD_VERTICES};
+
+#[test]
+fn vertex_layout_matches_shader_inputs() {
+    let binding = Vertex::binding_description();
+    assert_eq!(binding.binding, 0);
+    assert_eq!(binding.stride, 24);
+    assert_eq!(binding.input_rate, vk::VertexInputRate::VERTEX);
+
+    let [position, color] = Vertex::attribute_descriptions();
+    assert_eq!((position.location, position.offset, position.format), (0, 0, vk::Format::R32G32B32_SFLOAT));
+    assert_eq!((color.location, color.offset, color.format), (1, 12, vk::Format::R32G32B32_SFLOAT));
+}
+
+#[test]
+fn quad_indices_stay_in_range() {
+    assert_eq!(QUAD_INDICES.len() % 3, 0);
+    assert!(QUAD_INDICES.iter().all(|&index| (index as usize) < QUAD_VERTICES.len()));
+}
+
+#[test]
+fn vertices_are_copied_byte_for_byte() {
+    let bytes = as_bytes(&QUAD_VERTICES);
+    assert_eq!(bytes.len(), QUAD_VERTICES.len() * 24);
+    assert_eq!(bytes[..4], (-0.5f32).to_ne_bytes());
+    assert_eq!(bytes[12..16], 1.0f32.to_ne_bytes());
+    assert_eq!(as_bytes(&[UniformBufferObject::default()]).len(), 3 * 64);
+}
+
+#[test]
+fn empty_meshes_are_rejected() {
+    assert!(check_mesh_data("Quad", &QUAD_VERTICES, &QUAD_INDICES).is_ok());
+    assert!(check_mesh_data("Empty Chunk", &[], &[]).is_err());
+    assert!(check_mesh_data("No Indices", &QUAD_VERTICES, &[]).is_err());
+}
+
+#[test]
+fn uniform_buffer_matches_std140_layout() {
+    // Three column-major mat4s back to back, as declared in glsl.vert
+    assert_eq!(size_of::<UniformBufferObject>(), 3 * 64);
+    assert_eq!(offset_of!(UniformBufferObject, model), 0);
+    assert_eq!(offset_of!(UniformBufferObject, view), 64);
+    assert_eq!(offset_of!(UniformBufferObject, proj), 128);
+
+    let ubo = UniformBufferObject::default();
+    assert_eq!((ubo.model, ubo.view, ubo.proj), (IDENTITY, IDENTITY, IDENTITY));
+}
@@ -0,0 +1,110 @@
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+use voxel_engine::vulkan::shaders::*;
+
+mod common;
+
+fn shader_dir(name: &str) -> PathBuf {
+    common::scratch_dir("test-shaders", name)
+}
+
+/// Writes `contents` and moves the modification time forward, so the change is seen even on file
+/// systems with coarse timestamps.
+fn touch(path: &Path, contents: &[u8], age: u64) {
+    fs::write(path, contents).unwrap();
+    let modified = SystemTime::now() + Duration::from_secs(age);
+    File::options().write(true).open(path).unwrap().set_modified(modified).unwrap();
+}
+
+fn prebuilt_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join(SHADER_DIR)
+}
+
+fn compiled_vertex_shader() -> Vec<u8> {
+    fs::read(shader_path(&prebuilt_dir(), SCENE_VERTEX_SHADER)).unwrap()
+}
+
+#[test]
+fn checked_in_spirv_is_valid() {
+    for name in [SCENE_VERTEX_SHADER, SCENE_FRAGMENT_SHADER] {
+        let bytes = fs::read(shader_path(&prebuilt_dir(), name)).unwrap();
+        assert_eq!(validate_spirv(&bytes), Ok(()), "{}", name);
+    }
+}
+
+#[test]
+fn truncated_or_foreign_bytes_are_rejected() {
+    let spirv = compiled_vertex_shader();
+    assert!(validate_spirv(&spirv[..spirv.len() - 1]).is_err());
+    assert!(validate_spirv(&spirv[..16]).is_err());
+    assert!(validate_spirv(&[0u8; 64]).is_err());
+}
+
+#[test]
+fn sources_map_to_spv_next_to_them() {
+    assert_eq!(spv_path(Path::new("shaders/glsl.frag")), PathBuf::from("shaders/glsl.frag.spv"));
+    assert!(is_glsl_source(Path::new("shaders/glsl.vert")));
+    assert!(!is_glsl_source(Path::new("shaders/glsl.vert.spv")));
+}
+
+#[test]
+fn watcher_reports_new_and_modified_files_once() {
+    let dir = shader_dir("watcher");
+    touch(&dir.join("a.frag"), b"old", 0);
+
+    let start = Instant::now();
+    let mut watcher = ShaderWatcher::new(&dir, Duration::from_millis(100));
+    assert!(watcher.poll(start).is_empty());
+
+    touch(&dir.join("a.frag"), b"new", 10);
+    touch(&dir.join("b.vert"), b"new", 10);
+    // Too soon after the last scan
+    assert!(watcher.poll(start + Duration::from_millis(50)).is_empty());
+    assert_eq!(watcher.poll(start + Duration::from_millis(100)), [dir.join("a.frag"), dir.join("b.vert")]);
+    assert!(watcher.poll(start + Duration::from_millis(200)).is_empty());
+}
+
+#[test]
+fn manager_reloads_valid_spirv_only() {
+    let dir = shader_dir("manager");
+    let mut manager = ShaderManager::new(&dir);
+    let start = Instant::now();
+    assert!(manager.poll(start).is_empty());
+
+    touch(&dir.join("good.vert.spv"), &compiled_vertex_shader(), 10);
+    touch(&dir.join("bad.frag.spv"), b"not spir-v", 10);
+    touch(&dir.join("notes.txt"), b"ignored", 10);
+    assert_eq!(manager.poll(start + SHADER_POLL_INTERVAL), [dir.join("good.vert.spv")]);
+}
+
+/// A compiler that rejects every source the way glslc reports a syntax error.
+#[cfg(unix)]
+fn failing_glslc(dir: &Path) -> PathBuf {
+    use std::os::unix::fs::PermissionsExt;
+
+    let glslc = dir.join("failing-glslc");
+    fs::write(&glslc, "#!/bin/sh\necho \"$1:1: error: '' : syntax error\" >&2\nexit 1\n").unwrap();
+    fs::set_permissions(&glslc, fs::Permissions::from_mode(0o755)).unwrap();
+    glslc
+}
+
+#[cfg(unix)]
+#[test]
+fn failed_compile_keeps_the_old_spirv() {
+    let dir = shader_dir("failed_compile");
+    let glslc = failing_glslc(&dir);
+    let spirv = compiled_vertex_shader();
+    fs::write(dir.join("scene.vert.spv"), &spirv).unwrap();
+    let mut manager = ShaderManager::new(&dir).with_glslc(&glslc);
+    let start = Instant::now();
+    manager.poll(start);
+
+    touch(&dir.join("scene.vert"), b"void main() {", 10);
+    assert!(manager.poll(start + SHADER_POLL_INTERVAL).is_empty());
+    assert_eq!(fs::read(dir.join("scene.vert.spv")).unwrap(), spirv);
+
+    let diagnostics = compile_glsl(&glslc, &dir.join("scene.vert"), &dir.join("scene.vert.spv")).unwrap_err();
+    assert!(diagnostics.contains("syntax error"), "{}", diagnostics);
+}
@@ -0,0 +1,60 @@
+use ash::vk;
+use voxel_engine::vulkan::other::*;
+use voxel_engine::vulkan::swapchain::*;
+
+fn capabilities(min_image_count: u32, max_image_count: u32) -> vk::SurfaceCapabilitiesKHR {
+    vk::SurfaceCapabilitiesKHR {
+        min_image_count,
+        max_image_count,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn present_mode_names() {
+    assert_eq!(PresentMode::parse("vsync"), Some(PresentMode::Vsync));
+    assert_eq!(PresentMode::parse(" Mailbox "), Some(PresentMode::LowLatency));
+    assert_eq!(PresentMode::parse("uncapped"), Some(PresentMode::Uncapped));
+    assert_eq!(PresentMode::parse("fifo_relaxed"), Some(PresentMode::Adaptive));
+    assert_eq!(PresentMode::parse("triple"), None);
+}
+
+#[test]
+fn present_modes_cycle_through_all() {
+    let mut mode = PresentMode::default();
+    let mut seen = Vec::new();
+    for _ in 0..PresentMode::ALL.len() {
+        seen.push(mode.vk_mode());
+        mode = mode.next();
+    }
+    assert_eq!(mode, PresentMode::default());
+    seen.sort_by_key(|mode| mode.as_raw());
+    seen.dedup();
+    assert_eq!(seen.len(), PresentMode::ALL.len());
+}
+
+#[test]
+fn unsupported_present_modes_fall_back_to_fifo() {
+    let available = [vk::PresentModeKHR::FIFO, vk::PresentModeKHR::IMMEDIATE];
+    assert_eq!(choose_swapchain_present_mode(PresentMode::Uncapped, &available), vk::PresentModeKHR::IMMEDIATE);
+    assert_eq!(choose_swapchain_present_mode(PresentMode::LowLatency, &available), vk::PresentModeKHR::FIFO);
+    assert_eq!(choose_swapchain_present_mode(PresentMode::Adaptive, &available), vk::PresentModeKHR::FIFO);
+}
+
+#[test]
+fn image_count_is_clamped_to_the_surface() {
+    assert_eq!(choose_swapchain_image_count(None, &capabilities(2, 8)), 3);
+    assert_eq!(choose_swapchain_image_count(None, &capabilities(3, 3)), 3);
+    assert_eq!(choose_swapchain_image_count(Some(1), &capabilities(2, 8)), 2);
+    assert_eq!(choose_swapchain_image_count(Some(16), &capabilities(2, 8)), 8);
+    // No upper limit
+    assert_eq!(choose_swapchain_image_count(Some(16), &capabilities(2, 0)), 16);
+}
+
+#[test]
+fn frames_in_flight_are_clamped() {
+    assert_eq!(clamp_frames_in_flight(0), DEFAULT_FRAMES_IN_FLIGHT);
+    assert_eq!(clamp_frames_in_flight(1), 1);
+    assert_eq!(clamp_frames_in_flight(3), 3);
+    assert_eq!(clamp_frames_in_flight(64), MAX_FRAMES_IN_FLIGHT);
+}
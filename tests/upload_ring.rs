@@ -0,0 +1,76 @@
+use ash::vk;
+use voxel_engine::vulkan::error::EngineError;
+use voxel_engine::vulkan::memory::ImageDesc;
+use voxel_engine::vulkan::upload::*;
+
+#[test]
+fn allocations_are_aligned_and_sequential() {
+    let mut ring = RingAllocator::new(256);
+
+    assert_eq!(ring.allocate(10, 16), Some(0));
+    assert_eq!(ring.allocate(10, 16), Some(16));
+    assert_eq!(ring.used(), 26);
+    assert_eq!(ring.submit(), 26);
+    assert_eq!(ring.submit(), 0);
+}
+
+#[test]
+fn full_ring_refuses_until_released() {
+    let mut ring = RingAllocator::new(64);
+
+    assert_eq!(ring.allocate(48, 16), Some(0));
+    let first_batch = ring.submit();
+    assert_eq!(ring.allocate(32, 16), None);
+
+    ring.release(first_batch);
+    assert_eq!(ring.used(), 0);
+    // Doesn't fit behind the head anymore, so it wraps to the start
+    assert_eq!(ring.allocate(32, 16), Some(0));
+}
+
+#[test]
+fn wrapping_counts_the_skipped_tail_as_used() {
+    let mut ring = RingAllocator::new(64);
+
+    assert_eq!(ring.allocate(40, 16), Some(0));
+    let first_batch = ring.submit();
+    assert_eq!(ring.allocate(8, 16), Some(48));
+    let second_batch = ring.submit();
+    ring.release(first_batch);
+
+    // The 8 bytes left at the end are skipped and the allocation lands at the start
+    assert_eq!(ring.allocate(16, 16), Some(0));
+    assert_eq!(ring.used(), 16 + 8 + 16);
+    // Only the 24 bytes between the head and the second batch are free
+    assert_eq!(ring.allocate(32, 8), None);
+    assert_eq!(ring.allocate(24, 8), Some(16));
+    assert_eq!(ring.used(), 64);
+
+    ring.release(second_batch);
+    let third_batch = ring.submit();
+    ring.release(third_batch);
+    assert_eq!(ring.used(), 0);
+}
+
+#[test]
+fn oversized_and_empty_requests_are_rejected() {
+    let mut ring = RingAllocator::new(64);
+
+    assert_eq!(ring.allocate(65, 4), None);
+    assert_eq!(ring.allocate(0, 4), None);
+    assert_eq!(ring.used(), 0);
+}
+
+#[test]
+fn image_uploads_must_cover_every_layer_exactly() {
+    let mut desc = ImageDesc::new(vk::Format::R8G8B8A8_SRGB, vk::Extent2D { width: 16, height: 8 }, vk::ImageUsageFlags::SAMPLED);
+    desc.array_layers = 3;
+
+    assert!(check_image_upload(&desc, 16 * 8 * 3 * 4).is_ok());
+    assert!(matches!(check_image_upload(&desc, 16 * 8 * 4), Err(EngineError::Upload(_))));
+    assert!(matches!(check_image_upload(&desc, 16 * 8 * 3 * 4 + 1), Err(EngineError::Upload(_))));
+    assert!(matches!(check_image_upload(&desc, 0), Err(EngineError::Upload(_))));
+
+    desc.format = vk::Format::BC7_SRGB_BLOCK;
+    assert!(matches!(check_image_upload(&desc, 16 * 8 * 3), Err(EngineError::Upload(_))));
+}